@@ -0,0 +1,97 @@
+// Compares decoding JSON encoded events with the event codec and with the
+// borrowed decoder used for projection rebuilds.
+use bytes::Bytes;
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, Criterion};
+use eshorizon::codec::json::borrowed::BorrowedEvent;
+use eshorizon::codec::json::event::EventCodec;
+use eshorizon::codec_main::{BasicEvent, EventCodec as _};
+use eshorizon::context::Context;
+use eshorizon::event::{register_event_data, EventData};
+use eshorizon::upcast::UpcasterRegistry;
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrderLine {
+    sku: String,
+    quantity: u32,
+    price: f64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct OrderPlaced {
+    customer: String,
+    lines: Vec<OrderLine>,
+}
+
+impl EventData for OrderPlaced {}
+
+fn encoded_event() -> Vec<u8> {
+    register_event_data(
+        "OrderPlaced".to_string(),
+        Box::new(|| Box::new(OrderPlaced { customer: String::new(), lines: Vec::new() })),
+    );
+
+    let data = OrderPlaced {
+        customer: "customer-1".to_string(),
+        lines: (0..20)
+            .map(|i| OrderLine { sku: format!("sku-{}", i), quantity: i, price: 9.95 })
+            .collect(),
+    };
+    let metadata = (0..10)
+        .map(|i| (format!("key-{}", i), json!({ "value": i, "tags": ["a", "b"] })))
+        .collect::<HashMap<_, _>>();
+    let event = BasicEvent::new(
+        "OrderPlaced".to_string(),
+        Some(Box::new(data)),
+        SystemTime::now(),
+        "Order".to_string(),
+        Uuid::new_v4(),
+        1,
+    )
+    .with_metadata(metadata);
+
+    futures::executor::block_on(EventCodec::default().marshal_event(&Context::new(), Arc::new(event))).unwrap()
+}
+
+fn decode(c: &mut Criterion) {
+    let encoded = encoded_event();
+    let buf = Bytes::from(encoded.clone());
+    let codec = EventCodec::default();
+    let upcasters = UpcasterRegistry::new();
+
+    let mut group = c.benchmark_group("decode");
+    // The codec takes ownership of its input, so copies are made outside the
+    // timed routine. Its future never waits, polling it measures the decoding.
+    group.bench_function("codec", |b| {
+        b.iter_batched(
+            || encoded.clone(),
+            |encoded| {
+                let (event, _) =
+                    futures::executor::block_on(codec.unmarshal_event(Context::new(), encoded)).unwrap();
+                black_box((event.aggregate_id(), event.version()));
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    group.bench_function("borrowed_envelope", |b| {
+        b.iter(|| {
+            let event = BorrowedEvent::decode(buf.clone(), &upcasters).unwrap();
+            black_box((event.aggregate_id(), event.version()));
+        })
+    });
+    group.bench_function("borrowed_data", |b| {
+        b.iter(|| {
+            let event = BorrowedEvent::decode(buf.clone(), &upcasters).unwrap();
+            black_box(event.data().unwrap());
+        })
+    });
+    group.finish();
+}
+
+criterion_group!(benches, decode);
+criterion_main!(benches);
@@ -53,6 +53,13 @@ pub fn register_aggregate(
     aggregates.insert(aggregate_type, factory);
 }
 
+// Returns the registered aggregate types, sorted.
+pub fn registered_aggregate_types() -> Vec<String> {
+    let mut aggregate_types: Vec<String> = AGGREGATES.read().unwrap().keys().cloned().collect();
+    aggregate_types.sort();
+    aggregate_types
+}
+
 // Create an aggregate of a specific type using the registered factory.
 pub fn create_aggregate(
     aggregate_type: &str,
@@ -0,0 +1,509 @@
+use std::any::Any;
+use std::error::Error as StdError;
+use std::sync::Arc;
+use std::time::SystemTime;
+use futures::StreamExt;
+use thiserror::Error;
+
+use crate::aggregate::{Aggregate, AggregateError};
+use crate::entity::Versionable;
+use crate::eventmaintenance::DeletedEvent;
+use crate::eventstore::{Event, EventStore, EventStoreError, SnapshotStore};
+use crate::snapshot::{AggregateType, Snapshotable};
+
+pub mod strategy;
+
+use strategy::{NoSnapshotStrategy, SnapshotStrategy};
+
+// VersionedAggregate is an aggregate that is rebuilt from its own events.
+pub trait VersionedAggregate: Aggregate + Versionable {
+    fn set_aggregate_version(&mut self, version: i32);
+
+    // ApplyEvent applies an event to the aggregate state.
+    fn apply_event(&mut self, event: &dyn Event) -> Result<(), AggregateError>;
+
+    // UncommittedEvents returns the events created since the aggregate was last saved.
+    fn uncommitted_events(&self) -> Vec<Arc<dyn Event>>;
+
+    fn clear_uncommitted_events(&mut self);
+}
+
+// SnapshotableAggregate is a versioned aggregate whose state can be snapshotted.
+pub trait SnapshotableAggregate: VersionedAggregate + Snapshotable {}
+
+impl<T: VersionedAggregate + Snapshotable> SnapshotableAggregate for T {}
+
+// Errors returned by the event sourced aggregate store.
+#[derive(Error, Debug)]
+pub enum AggregateStoreError {
+    #[error("mismatching event type")]
+    MismatchedEventType,
+
+    #[error("incorrect event version: expected {expected}, got {actual}")]
+    IncorrectEventVersion { expected: i32, actual: i32 },
+
+    #[error("aggregate has no version {0}")]
+    VersionNotFound(i32),
+
+    #[error("could not apply event: {0}")]
+    ApplyEvent(#[from] AggregateError),
+
+    #[error(transparent)]
+    EventStore(#[from] EventStoreError),
+
+    #[error("snapshot store: {0}")]
+    SnapshotStore(Box<dyn StdError + Send + Sync>),
+}
+
+// AggregateStore loads and saves event sourced aggregates using an event store,
+// and optionally a snapshot store to shorten the replay of long streams.
+pub struct AggregateStore {
+    store: Arc<dyn EventStore>,
+    snapshots: Option<Arc<dyn SnapshotStore>>,
+    strategy: Arc<dyn SnapshotStrategy>,
+}
+
+impl AggregateStore {
+    pub fn new(store: Arc<dyn EventStore>) -> Self {
+        Self {
+            store,
+            snapshots: None,
+            strategy: Arc::new(NoSnapshotStrategy),
+        }
+    }
+
+    // Create a store that snapshots aggregates as decided by the strategy.
+    pub fn with_snapshots(
+        store: Arc<dyn EventStore>,
+        snapshots: Arc<dyn SnapshotStore>,
+        strategy: Arc<dyn SnapshotStrategy>,
+    ) -> Self {
+        Self {
+            store,
+            snapshots: Some(snapshots),
+            strategy,
+        }
+    }
+
+    // Load rehydrates a freshly created aggregate by streaming its events.
+    pub async fn load(&self, aggregate: &mut dyn VersionedAggregate) -> Result<(), AggregateStoreError> {
+        let next = aggregate.aggregate_version() + 1;
+        let mut events = self.store.load_stream_from(aggregate.entity_id(), next).await?;
+        while let Some(event) = events.next().await {
+            apply_event(aggregate, event?.as_ref())?;
+        }
+        Ok(())
+    }
+
+    // LoadAtVersion rehydrates a freshly created aggregate as it was at the given version.
+    pub async fn load_at_version(
+        &self,
+        aggregate: &mut dyn VersionedAggregate,
+        version: i32,
+    ) -> Result<(), AggregateStoreError> {
+        let next = aggregate.aggregate_version() + 1;
+        let events = self.store.load_range(aggregate.entity_id(), next, version).await?;
+        for event in events {
+            apply_event(aggregate, event.as_ref())?;
+        }
+        if aggregate.aggregate_version() != version {
+            return Err(AggregateStoreError::VersionNotFound(version));
+        }
+        Ok(())
+    }
+
+    // LoadAsOf rehydrates a freshly created aggregate as it was at the given time.
+    pub async fn load_as_of(
+        &self,
+        aggregate: &mut dyn VersionedAggregate,
+        timestamp: SystemTime,
+    ) -> Result<(), AggregateStoreError> {
+        let events = self.store.load_as_of(aggregate.entity_id(), timestamp).await?;
+        let version = aggregate.aggregate_version();
+        for event in events.iter().filter(|e| e.version() > version) {
+            apply_event(aggregate, event.as_ref())?;
+        }
+        Ok(())
+    }
+
+    // LoadSnapshotable rehydrates a freshly created aggregate from its latest
+    // snapshot, then replays only the events stored after it.
+    pub async fn load_snapshotable(&self, aggregate: &mut dyn SnapshotableAggregate) -> Result<(), AggregateStoreError> {
+        if let Some(snapshots) = &self.snapshots {
+            let aggregate_type = AggregateType::new(aggregate.aggregate_type());
+            let snapshot = snapshots
+                .load_snapshot(aggregate.entity_id(), &aggregate_type)
+                .await
+                .map_err(AggregateStoreError::SnapshotStore)?;
+            if let Some(snapshot) = snapshot {
+                aggregate.apply_snapshot(&snapshot);
+                aggregate.set_aggregate_version(snapshot.version);
+            }
+        }
+        self.load(aggregate).await
+    }
+
+    // SaveSnapshotable saves an aggregate and takes a snapshot if the strategy asks for one.
+    pub async fn save_snapshotable(&self, aggregate: &mut dyn SnapshotableAggregate) -> Result<(), AggregateStoreError> {
+        self.save(aggregate).await?;
+
+        let snapshots = match &self.snapshots {
+            Some(snapshots) => snapshots,
+            None => return Ok(()),
+        };
+        let aggregate_type = AggregateType::new(aggregate.aggregate_type());
+        let last = snapshots
+            .load_snapshot(aggregate.entity_id(), &aggregate_type)
+            .await
+            .map_err(AggregateStoreError::SnapshotStore)?;
+        let (last_version, last_timestamp) = match &last {
+            Some(snapshot) => (snapshot.version, Some(snapshot.timestamp)),
+            None => (0, None),
+        };
+        if !self.strategy.should_take_snapshot(last_version, last_timestamp, aggregate) {
+            return Ok(());
+        }
+
+        let mut snapshot = aggregate.create_snapshot();
+        snapshot.version = aggregate.aggregate_version();
+        snapshot.aggregate_type = aggregate_type;
+        snapshots
+            .save_snapshot(aggregate.entity_id(), snapshot)
+            .await
+            .map_err(AggregateStoreError::SnapshotStore)
+    }
+
+    // Save stores the uncommitted events of an aggregate and applies them.
+    pub async fn save(&self, aggregate: &mut dyn VersionedAggregate) -> Result<(), AggregateStoreError> {
+        let events = aggregate.uncommitted_events();
+        if events.is_empty() {
+            return Ok(());
+        }
+
+        self.store.save(events.clone(), aggregate.aggregate_version()).await?;
+        aggregate.clear_uncommitted_events();
+        for event in events {
+            apply_event(aggregate, event.as_ref())?;
+        }
+        Ok(())
+    }
+}
+
+// Apply an event that must directly follow the aggregate's current version.
+// Events whose payload was deleted only advance the version.
+pub fn apply_event(aggregate: &mut dyn VersionedAggregate, event: &dyn Event) -> Result<(), AggregateStoreError> {
+    if event.aggregate_type() != aggregate.aggregate_type() {
+        return Err(AggregateStoreError::MismatchedEventType);
+    }
+    let expected = aggregate.aggregate_version() + 1;
+    if event.version() != expected {
+        return Err(AggregateStoreError::IncorrectEventVersion {
+            expected,
+            actual: event.version(),
+        });
+    }
+    if !(event as &dyn Any).is::<DeletedEvent>() {
+        aggregate.apply_event(event)?;
+    }
+    aggregate.set_aggregate_version(event.version());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::strategy::{EveryNumberEventSnapshotStrategy, PeriodSnapshotStrategy, PredicateSnapshotStrategy};
+    use crate::eventmaintenance::EventStoreMaintenance;
+    use crate::eventstore::memory::{MemoryEventStore, MemorySnapshotStore};
+    use crate::snapshot::{Snapshot, SnapshotData};
+    use std::fmt;
+    use std::time::Duration;
+    use std::time::SystemTime;
+    use uuid::Uuid;
+    use serde::{Deserialize, Serialize};
+
+    struct Added {
+        event_type: String,
+        aggregate_id: Uuid,
+        version: i32,
+        amount: i32,
+        timestamp: SystemTime,
+    }
+
+    impl Event for Added {
+        fn event_type(&self) -> String {
+            self.event_type.clone()
+        }
+
+        fn aggregate_type(&self) -> String {
+            "Counter".to_string()
+        }
+
+        fn aggregate_id(&self) -> Uuid {
+            self.aggregate_id
+        }
+
+        fn version(&self) -> i32 {
+            self.version
+        }
+
+        fn timestamp(&self) -> SystemTime {
+            self.timestamp
+        }
+
+        fn with_event_type(&self, event_type: &str) -> Option<Arc<dyn Event>> {
+            Some(Arc::new(Added {
+                event_type: event_type.to_string(),
+                aggregate_id: self.aggregate_id,
+                version: self.version,
+                amount: self.amount,
+                timestamp: self.timestamp,
+            }))
+        }
+    }
+
+    impl fmt::Display for Added {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "Added({})", self.amount)
+        }
+    }
+
+    struct Counter {
+        id: Uuid,
+        version: i32,
+        total: i32,
+        replayed: usize,
+        uncommitted: Vec<Arc<dyn Event>>,
+    }
+
+    impl Counter {
+        fn new(id: Uuid) -> Self {
+            Counter { id, version: 0, total: 0, replayed: 0, uncommitted: Vec::new() }
+        }
+
+        fn add(&mut self, amount: i32) {
+            let version = self.version + self.uncommitted.len() as i32 + 1;
+            self.uncommitted.push(Arc::new(Added {
+                event_type: "Added".to_string(),
+                aggregate_id: self.id,
+                version,
+                amount,
+                timestamp: SystemTime::now(),
+            }));
+        }
+    }
+
+    impl Aggregate for Counter {
+        fn aggregate_type(&self) -> String {
+            "Counter".to_string()
+        }
+
+        fn entity_id(&self) -> Uuid {
+            self.id
+        }
+
+        fn handle_command(&self) {}
+    }
+
+    impl Versionable for Counter {
+        fn aggregate_version(&self) -> i32 {
+            self.version
+        }
+    }
+
+    impl VersionedAggregate for Counter {
+        fn set_aggregate_version(&mut self, version: i32) {
+            self.version = version;
+        }
+
+        fn apply_event(&mut self, event: &dyn Event) -> Result<(), AggregateError> {
+            let event: &dyn std::any::Any = event;
+            let added = event
+                .downcast_ref::<Added>()
+                .ok_or_else(|| AggregateError::new("unexpected event"))?;
+            self.total += added.amount;
+            self.replayed += 1;
+            Ok(())
+        }
+
+        fn uncommitted_events(&self) -> Vec<Arc<dyn Event>> {
+            self.uncommitted.clone()
+        }
+
+        fn clear_uncommitted_events(&mut self) {
+            self.uncommitted.clear();
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct CounterSnapshot {
+        total: i32,
+    }
+
+    impl SnapshotData for CounterSnapshot {}
+
+    impl Snapshotable for Counter {
+        fn create_snapshot(&self) -> Snapshot {
+            Snapshot {
+                version: self.version,
+                aggregate_type: AggregateType::new("Counter".to_string()),
+                timestamp: SystemTime::now(),
+                state: Box::new(CounterSnapshot { total: self.total }),
+            }
+        }
+
+        fn apply_snapshot(&mut self, snapshot: &Snapshot) {
+            if let Some(state) = snapshot.state.as_any().downcast_ref::<CounterSnapshot>() {
+                self.total = state.total;
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let store = AggregateStore::new(Arc::new(MemoryEventStore::with_stream_buffer(1)));
+        let id = Uuid::new_v4();
+
+        let mut counter = Counter::new(id);
+        counter.add(2);
+        counter.add(3);
+        store.save(&mut counter).await.unwrap();
+        assert_eq!(counter.aggregate_version(), 2);
+        assert_eq!(counter.total, 5);
+        assert!(counter.uncommitted_events().is_empty());
+
+        counter.add(4);
+        store.save(&mut counter).await.unwrap();
+
+        let mut loaded = Counter::new(id);
+        store.load(&mut loaded).await.unwrap();
+        assert_eq!(loaded.aggregate_version(), 3);
+        assert_eq!(loaded.total, 9);
+    }
+
+    #[tokio::test]
+    async fn test_save_conflict() {
+        let store = AggregateStore::new(Arc::new(MemoryEventStore::new()));
+        let id = Uuid::new_v4();
+
+        let mut first = Counter::new(id);
+        first.add(1);
+        store.save(&mut first).await.unwrap();
+
+        let mut stale = Counter::new(id);
+        stale.add(1);
+        let err = store.save(&mut stale).await.unwrap_err();
+        assert!(matches!(err, AggregateStoreError::EventStore(_)));
+    }
+
+    #[tokio::test]
+    async fn test_load_after_maintenance() {
+        let events = Arc::new(MemoryEventStore::new());
+        let store = AggregateStore::new(events.clone());
+        let id = Uuid::new_v4();
+
+        let mut counter = Counter::new(id);
+        counter.add(1);
+        counter.add(2);
+        counter.add(3);
+        store.save(&mut counter).await.unwrap();
+
+        // Renamed events keep their concrete type.
+        events.rename_event("Added".to_string(), "Incremented".to_string()).await.unwrap();
+        let mut loaded = Counter::new(id);
+        store.load(&mut loaded).await.unwrap();
+        assert_eq!(loaded.aggregate_version(), 3);
+        assert_eq!(loaded.total, 6);
+
+        // Deleted events are skipped but still count as a version.
+        events.delete_event_data(id, 2).await.unwrap();
+        let mut loaded = Counter::new(id);
+        store.load(&mut loaded).await.unwrap();
+        assert_eq!(loaded.aggregate_version(), 3);
+        assert_eq!(loaded.total, 4);
+        assert_eq!(loaded.replayed, 2);
+
+        // The aggregate can still be saved after the deleted event.
+        loaded.add(4);
+        store.save(&mut loaded).await.unwrap();
+        assert_eq!(loaded.aggregate_version(), 4);
+    }
+
+    #[tokio::test]
+    async fn test_load_historic_state() {
+        let store = AggregateStore::new(Arc::new(MemoryEventStore::new()));
+        let id = Uuid::new_v4();
+
+        let mut counter = Counter::new(id);
+        counter.add(1);
+        counter.add(2);
+        store.save(&mut counter).await.unwrap();
+        let checkpoint = SystemTime::now();
+        tokio::time::sleep(std::time::Duration::from_millis(5)).await;
+        counter.add(3);
+        store.save(&mut counter).await.unwrap();
+
+        let mut at_version = Counter::new(id);
+        store.load_at_version(&mut at_version, 1).await.unwrap();
+        assert_eq!(at_version.total, 1);
+
+        let mut as_of = Counter::new(id);
+        store.load_as_of(&mut as_of, checkpoint).await.unwrap();
+        assert_eq!(as_of.aggregate_version(), 2);
+        assert_eq!(as_of.total, 3);
+
+        let mut missing = Counter::new(id);
+        let err = store.load_at_version(&mut missing, 10).await.unwrap_err();
+        assert!(matches!(err, AggregateStoreError::VersionNotFound(10)));
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_every_number_of_events() {
+        let snapshots = Arc::new(MemorySnapshotStore::new());
+        let store = AggregateStore::with_snapshots(
+            Arc::new(MemoryEventStore::new()),
+            snapshots.clone(),
+            Arc::new(EveryNumberEventSnapshotStrategy::new(2)),
+        );
+        let id = Uuid::new_v4();
+        let aggregate_type = AggregateType::new("Counter".to_string());
+
+        let mut counter = Counter::new(id);
+        counter.add(1);
+        store.save_snapshotable(&mut counter).await.unwrap();
+        assert!(snapshots.load_snapshot(id, &aggregate_type).await.unwrap().is_none());
+
+        counter.add(2);
+        counter.add(3);
+        store.save_snapshotable(&mut counter).await.unwrap();
+        let snapshot = snapshots.load_snapshot(id, &aggregate_type).await.unwrap().unwrap();
+        assert_eq!(snapshot.version, 3);
+
+        counter.add(4);
+        store.save_snapshotable(&mut counter).await.unwrap();
+
+        // Only the event after the snapshot is replayed.
+        let mut loaded = Counter::new(id);
+        store.load_snapshotable(&mut loaded).await.unwrap();
+        assert_eq!(loaded.aggregate_version(), 4);
+        assert_eq!(loaded.total, 10);
+        assert_eq!(loaded.replayed, 1);
+    }
+
+    #[test]
+    fn test_snapshot_strategies() {
+        let mut counter = Counter::new(Uuid::new_v4());
+        counter.version = 5;
+
+        let period = PeriodSnapshotStrategy::new(Duration::from_secs(60));
+        assert!(period.should_take_snapshot(0, None, &counter));
+        assert!(!period.should_take_snapshot(0, Some(SystemTime::now()), &counter));
+        let old = SystemTime::now() - Duration::from_secs(120);
+        assert!(period.should_take_snapshot(0, Some(old), &counter));
+
+        let predicate = PredicateSnapshotStrategy::new(|a: &dyn SnapshotableAggregate| a.aggregate_version() % 5 == 0);
+        assert!(predicate.should_take_snapshot(0, None, &counter));
+        counter.version = 6;
+        assert!(!predicate.should_take_snapshot(0, None, &counter));
+    }
+}
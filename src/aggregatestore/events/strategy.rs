@@ -0,0 +1,95 @@
+use std::time::{Duration, SystemTime};
+
+use super::SnapshotableAggregate;
+
+// SnapshotStrategy decides whether a snapshot should be taken after an aggregate was saved.
+pub trait SnapshotStrategy: Send + Sync {
+    fn should_take_snapshot(
+        &self,
+        last_snapshot_version: i32,
+        last_snapshot_timestamp: Option<SystemTime>,
+        aggregate: &dyn SnapshotableAggregate,
+    ) -> bool;
+}
+
+// NoSnapshotStrategy never takes snapshots.
+pub struct NoSnapshotStrategy;
+
+impl SnapshotStrategy for NoSnapshotStrategy {
+    fn should_take_snapshot(&self, _: i32, _: Option<SystemTime>, _: &dyn SnapshotableAggregate) -> bool {
+        false
+    }
+}
+
+// EveryNumberEventSnapshotStrategy takes a snapshot every N events.
+pub struct EveryNumberEventSnapshotStrategy {
+    snapshot_threshold: i32,
+}
+
+impl EveryNumberEventSnapshotStrategy {
+    pub fn new(snapshot_threshold: i32) -> Self {
+        EveryNumberEventSnapshotStrategy { snapshot_threshold }
+    }
+}
+
+impl SnapshotStrategy for EveryNumberEventSnapshotStrategy {
+    fn should_take_snapshot(
+        &self,
+        last_snapshot_version: i32,
+        _: Option<SystemTime>,
+        aggregate: &dyn SnapshotableAggregate,
+    ) -> bool {
+        aggregate.aggregate_version() - last_snapshot_version >= self.snapshot_threshold
+    }
+}
+
+// PeriodSnapshotStrategy takes a snapshot when the last one is older than a period.
+pub struct PeriodSnapshotStrategy {
+    snapshot_period: Duration,
+}
+
+impl PeriodSnapshotStrategy {
+    pub fn new(snapshot_period: Duration) -> Self {
+        PeriodSnapshotStrategy { snapshot_period }
+    }
+}
+
+impl SnapshotStrategy for PeriodSnapshotStrategy {
+    fn should_take_snapshot(
+        &self,
+        _: i32,
+        last_snapshot_timestamp: Option<SystemTime>,
+        _: &dyn SnapshotableAggregate,
+    ) -> bool {
+        match last_snapshot_timestamp {
+            Some(timestamp) => timestamp.elapsed().is_ok_and(|age| age >= self.snapshot_period),
+            None => true,
+        }
+    }
+}
+
+// PredicateSnapshotStrategy takes a snapshot when a custom predicate on the aggregate holds.
+pub struct PredicateSnapshotStrategy<F>
+where
+    F: Fn(&dyn SnapshotableAggregate) -> bool + Send + Sync,
+{
+    predicate: F,
+}
+
+impl<F> PredicateSnapshotStrategy<F>
+where
+    F: Fn(&dyn SnapshotableAggregate) -> bool + Send + Sync,
+{
+    pub fn new(predicate: F) -> Self {
+        PredicateSnapshotStrategy { predicate }
+    }
+}
+
+impl<F> SnapshotStrategy for PredicateSnapshotStrategy<F>
+where
+    F: Fn(&dyn SnapshotableAggregate) -> bool + Send + Sync,
+{
+    fn should_take_snapshot(&self, _: i32, _: Option<SystemTime>, aggregate: &dyn SnapshotableAggregate) -> bool {
+        (self.predicate)(aggregate)
+    }
+}
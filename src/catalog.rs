@@ -0,0 +1,349 @@
+use serde_json::{json, Map, Value};
+use std::collections::BTreeSet;
+use std::sync::Arc;
+
+use crate::aggregate;
+use crate::command_main;
+use crate::event;
+use crate::matcher::{self, EventMatcher};
+use crate::snapshot::SnapshotFactoryRegistry;
+
+// Version of the AsyncAPI specification of exported documents.
+pub const ASYNCAPI_VERSION: &str = "2.6.0";
+
+// AggregateInfo describes an aggregate type in the catalog.
+#[derive(Debug, Clone, PartialEq)]
+pub struct AggregateInfo {
+    pub aggregate_type: String,
+    pub commands: Vec<String>,
+    pub events: Vec<String>,
+    // Schema version of the snapshot data, if the aggregate has snapshots.
+    pub snapshot_schema_version: Option<u32>,
+}
+
+// HandlerInfo describes an event handler and the events its matcher selects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HandlerInfo {
+    pub handler_type: String,
+    pub events: Vec<String>,
+}
+
+// Catalog describes the domain model by walking the aggregate, command, event
+// data and snapshot registries. Which aggregates emit which events and which
+// handlers subscribe with which matchers isn't known to the registries, so
+// those are declared on the catalog.
+#[derive(Default)]
+pub struct Catalog {
+    snapshots: Option<Arc<SnapshotFactoryRegistry>>,
+    emitted: Vec<(String, String)>,
+    handlers: Vec<(String, Arc<dyn EventMatcher>)>,
+}
+
+impl Catalog {
+    pub fn new() -> Self {
+        Catalog::default()
+    }
+
+    pub fn with_snapshots(mut self, snapshots: Arc<SnapshotFactoryRegistry>) -> Self {
+        self.snapshots = Some(snapshots);
+        self
+    }
+
+    // Declares the event types an aggregate type emits.
+    pub fn with_events(mut self, aggregate_type: &str, event_types: &[&str]) -> Self {
+        for event_type in event_types {
+            self.emitted.push((aggregate_type.to_string(), event_type.to_string()));
+        }
+        self
+    }
+
+    // Declares an event handler and the matcher it's subscribed with.
+    pub fn with_handler(mut self, handler_type: &str, matcher: Arc<dyn EventMatcher>) -> Self {
+        self.handlers.push((handler_type.to_string(), matcher));
+        self
+    }
+
+    // Returns every aggregate type known to the registries or the catalog, sorted.
+    pub fn aggregates(&self) -> Vec<AggregateInfo> {
+        let commands: Vec<(String, String)> = command_main::registered_command_types()
+            .into_iter()
+            .filter_map(|command_type| {
+                let command = command_main::create_command(&command_type).ok()?;
+                Some((command.aggregate_type(), command_type))
+            })
+            .collect();
+        let snapshots: Vec<(String, u32)> = self
+            .snapshots
+            .iter()
+            .flat_map(|snapshots| {
+                snapshots
+                    .aggregate_types()
+                    .into_iter()
+                    .map(|aggregate_type| {
+                        let schema_version = snapshots.schema_version(&aggregate_type);
+                        (aggregate_type.as_str().to_string(), schema_version)
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+
+        let aggregate_types: BTreeSet<String> = aggregate::registered_aggregate_types()
+            .into_iter()
+            .chain(commands.iter().map(|(aggregate_type, _)| aggregate_type.clone()))
+            .chain(self.emitted.iter().map(|(aggregate_type, _)| aggregate_type.clone()))
+            .chain(snapshots.iter().map(|(aggregate_type, _)| aggregate_type.clone()))
+            .collect();
+
+        let of = |pairs: &[(String, String)], aggregate_type: &str| -> Vec<String> {
+            let names: BTreeSet<String> = pairs
+                .iter()
+                .filter(|(owner, _)| owner == aggregate_type)
+                .map(|(_, name)| name.clone())
+                .collect();
+            names.into_iter().collect()
+        };
+
+        aggregate_types
+            .into_iter()
+            .map(|aggregate_type| AggregateInfo {
+                commands: of(&commands, &aggregate_type),
+                events: of(&self.emitted, &aggregate_type),
+                snapshot_schema_version: snapshots
+                    .iter()
+                    .find(|(owner, _)| *owner == aggregate_type)
+                    .map(|(_, schema_version)| *schema_version),
+                aggregate_type,
+            })
+            .collect()
+    }
+
+    // Returns the registered event types that no aggregate is declared to emit.
+    pub fn unassigned_events(&self) -> Vec<String> {
+        event::registered_event_types()
+            .into_iter()
+            .filter(|event_type| !self.emitted.iter().any(|(_, emitted)| emitted == event_type))
+            .collect()
+    }
+
+    // Returns the declared handlers with the events their matchers select,
+    // found by matching every known event type with its emitting aggregate.
+    pub fn handlers(&self) -> Vec<HandlerInfo> {
+        let candidates: Vec<(String, matcher::TestEvent)> = self
+            .emitted
+            .iter()
+            .map(|(aggregate_type, event_type)| (event_type.clone(), aggregate_type.as_str()))
+            .chain(self.unassigned_events().into_iter().map(|event_type| (event_type, "")))
+            .map(|(event_type, aggregate_type)| {
+                let probe = matcher::TestEvent::new(
+                    matcher::EventType::from_name(&event_type),
+                    matcher::AggregateType::from_name(aggregate_type),
+                );
+                (event_type, probe)
+            })
+            .collect();
+
+        self.handlers
+            .iter()
+            .map(|(handler_type, matcher)| {
+                let events: BTreeSet<String> = candidates
+                    .iter()
+                    .filter(|(_, probe)| matcher.matches(probe))
+                    .map(|(event_type, _)| event_type.clone())
+                    .collect();
+                HandlerInfo {
+                    handler_type: handler_type.clone(),
+                    events: events.into_iter().collect(),
+                }
+            })
+            .collect()
+    }
+
+    // Exports the catalog as an AsyncAPI document with a commands and an
+    // events channel per aggregate. Handlers are listed in the x-handlers
+    // extension and payloads use the JSON Schemas of registered types.
+    pub fn to_asyncapi(&self, title: &str, version: &str) -> Value {
+        let mut channels = Map::new();
+        let mut messages = Map::new();
+        let reference = |name: &String| json!({ "$ref": format!("#/components/messages/{}", name) });
+
+        for aggregate in self.aggregates() {
+            if !aggregate.commands.is_empty() {
+                channels.insert(
+                    format!("{}/commands", aggregate.aggregate_type),
+                    json!({
+                        "publish": {
+                            "operationId": format!("handle{}Command", aggregate.aggregate_type),
+                            "message": { "oneOf": aggregate.commands.iter().map(reference).collect::<Vec<_>>() },
+                        },
+                    }),
+                );
+            }
+
+            let mut channel = Map::new();
+            if !aggregate.events.is_empty() {
+                channel.insert(
+                    "subscribe".to_string(),
+                    json!({
+                        "operationId": format!("on{}Event", aggregate.aggregate_type),
+                        "message": { "oneOf": aggregate.events.iter().map(reference).collect::<Vec<_>>() },
+                    }),
+                );
+            }
+            if let Some(schema_version) = aggregate.snapshot_schema_version {
+                channel.insert("x-snapshot".to_string(), json!({ "schemaVersion": schema_version }));
+            }
+            if !channel.is_empty() {
+                channels.insert(format!("{}/events", aggregate.aggregate_type), Value::Object(channel));
+            }
+
+            for command_type in aggregate.commands {
+                let payload = command_payload(&command_type);
+                messages.insert(command_type.clone(), json!({ "name": command_type, "payload": payload }));
+            }
+        }
+
+        for event_type in self
+            .emitted
+            .iter()
+            .map(|(_, event_type)| event_type.clone())
+            .chain(self.unassigned_events())
+        {
+            let payload = event_payload(&event_type);
+            messages.insert(event_type.clone(), json!({ "name": event_type, "payload": payload }));
+        }
+
+        let handlers: Map<String, Value> = self
+            .handlers()
+            .into_iter()
+            .map(|handler| (handler.handler_type, json!({ "events": handler.events })))
+            .collect();
+
+        json!({
+            "asyncapi": ASYNCAPI_VERSION,
+            "info": { "title": title, "version": version },
+            "channels": channels,
+            "components": { "messages": messages },
+            "x-handlers": handlers,
+        })
+    }
+}
+
+fn event_payload(event_type: &str) -> Value {
+    event::event_data_schema(event_type).unwrap_or_else(|| json!({}))
+}
+
+fn command_payload(command_type: &str) -> Value {
+    command_main::command_schema(command_type).unwrap_or_else(|| json!({}))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::matcher::{AggregateType, EventType, MatchAggregates, MatchEvents};
+    use crate::snapshot;
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct OpenTicket {
+        id: Uuid,
+    }
+
+    impl command_main::Command for OpenTicket {
+        fn aggregate_id(&self) -> Uuid {
+            self.id
+        }
+
+        fn aggregate_type(&self) -> String {
+            "CatalogTicket".to_string()
+        }
+
+        fn command_type(&self) -> String {
+            "CatalogOpenTicket".to_string()
+        }
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct TicketState {
+        open: bool,
+    }
+
+    impl snapshot::SnapshotData for TicketState {}
+
+    #[test]
+    fn test_catalog() {
+        aggregate::register_aggregate(
+            "CatalogTicket".to_string(),
+            Box::new(|id| Box::new(aggregate::MyAggregate::new(id))),
+        );
+        command_main::register_command(
+            "CatalogOpenTicket".to_string(),
+            Box::new(|| Box::new(OpenTicket { id: Uuid::nil() })),
+        );
+        for event_type in ["CatalogTicketOpened", "CatalogTicketClosed", "CatalogUserInvited"] {
+            event::register_event_data(
+                event_type.to_string(),
+                Box::new(|| Box::new(event::MyEventData { field: String::new() })),
+            );
+        }
+        let snapshots = Arc::new(SnapshotFactoryRegistry::new());
+        snapshots.register_snapshot_data(snapshot::AggregateType::new("CatalogTicket".to_string()), |_| {
+            Box::new(TicketState { open: false })
+        });
+        snapshots.set_schema_version(snapshot::AggregateType::new("CatalogTicket".to_string()), 2);
+
+        let catalog = Catalog::new()
+            .with_snapshots(snapshots)
+            .with_events("CatalogTicket", &["CatalogTicketOpened", "CatalogTicketClosed"])
+            .with_handler(
+                "TicketProjector",
+                Arc::new(MatchAggregates::new(vec![AggregateType::from_name("CatalogTicket")])),
+            )
+            .with_handler(
+                "Mailer",
+                Arc::new(MatchEvents::new(vec![
+                    EventType::from_name("CatalogTicketClosed"),
+                    EventType::from_name("CatalogUserInvited"),
+                ])),
+            );
+
+        let ticket = catalog
+            .aggregates()
+            .into_iter()
+            .find(|aggregate| aggregate.aggregate_type == "CatalogTicket")
+            .unwrap();
+        assert_eq!(
+            ticket,
+            AggregateInfo {
+                aggregate_type: "CatalogTicket".to_string(),
+                commands: vec!["CatalogOpenTicket".to_string()],
+                events: vec!["CatalogTicketClosed".to_string(), "CatalogTicketOpened".to_string()],
+                snapshot_schema_version: Some(2),
+            }
+        );
+        assert!(catalog.unassigned_events().contains(&"CatalogUserInvited".to_string()));
+        assert_eq!(
+            catalog.handlers(),
+            vec![
+                HandlerInfo {
+                    handler_type: "TicketProjector".to_string(),
+                    events: vec!["CatalogTicketClosed".to_string(), "CatalogTicketOpened".to_string()],
+                },
+                HandlerInfo {
+                    handler_type: "Mailer".to_string(),
+                    events: vec!["CatalogTicketClosed".to_string(), "CatalogUserInvited".to_string()],
+                },
+            ]
+        );
+
+        let document = catalog.to_asyncapi("Tickets", "1.0.0");
+        assert_eq!(document["asyncapi"], ASYNCAPI_VERSION);
+        assert_eq!(
+            document["channels"]["CatalogTicket/commands"]["publish"]["message"]["oneOf"][0]["$ref"],
+            "#/components/messages/CatalogOpenTicket"
+        );
+        assert_eq!(document["channels"]["CatalogTicket/events"]["x-snapshot"]["schemaVersion"], 2);
+        assert_eq!(document["components"]["messages"]["CatalogUserInvited"]["name"], "CatalogUserInvited");
+        assert_eq!(document["x-handlers"]["Mailer"]["events"][1], "CatalogUserInvited");
+    }
+}
@@ -0,0 +1,415 @@
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::sync::Arc;
+use async_trait::async_trait;
+use futures::StreamExt;
+use thiserror::Error;
+use tokio::sync::Mutex;
+use tokio::task;
+
+use crate::eventhandler::{Event, EventHandler, EventHandlerError, EventHandlerType};
+use crate::eventstore::{matches_event, EventStoreError, GlobalEventStore, PositionedEvent};
+use crate::matcher::EventMatcher;
+
+// Errors related to handler checkpoints.
+#[derive(Error, Debug)]
+pub enum CheckpointError {
+    #[error("could not load checkpoint: {0}")]
+    Load(String),
+
+    #[error("could not save checkpoint: {0}")]
+    Save(String),
+
+    #[error(transparent)]
+    EventStore(#[from] EventStoreError),
+
+    #[error(transparent)]
+    Handler(#[from] EventHandlerError),
+}
+
+// CheckpointStore persists the last processed global position per event handler.
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    // LoadCheckpoint returns the last processed position, or 0 if the handler never ran
+    async fn load_checkpoint(&self, handler_type: &EventHandlerType) -> Result<u64, CheckpointError>;
+
+    // SaveCheckpoint stores the last processed position for a handler
+    async fn save_checkpoint(&self, handler_type: &EventHandlerType, position: u64) -> Result<(), CheckpointError>;
+}
+
+// MemoryCheckpointStore keeps checkpoints in memory, mostly useful for tests.
+#[derive(Default)]
+pub struct MemoryCheckpointStore {
+    checkpoints: Mutex<HashMap<EventHandlerType, u64>>,
+}
+
+impl MemoryCheckpointStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for MemoryCheckpointStore {
+    async fn load_checkpoint(&self, handler_type: &EventHandlerType) -> Result<u64, CheckpointError> {
+        Ok(self.checkpoints.lock().await.get(handler_type).copied().unwrap_or(0))
+    }
+
+    async fn save_checkpoint(&self, handler_type: &EventHandlerType, position: u64) -> Result<(), CheckpointError> {
+        self.checkpoints.lock().await.insert(handler_type.clone(), position);
+        Ok(())
+    }
+}
+
+// FileCheckpointStore keeps all checkpoints in a single JSON file. The file is
+// rewritten through a temporary file so a crash never leaves it half written,
+// which makes every save read, write and rename the whole file. Handlers fed by
+// a CheckpointedHandler should save in batches with with_checkpoint_interval.
+pub struct FileCheckpointStore {
+    path: PathBuf,
+    lock: Mutex<()>,
+}
+
+impl FileCheckpointStore {
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            lock: Mutex::new(()),
+        }
+    }
+
+    async fn read(&self) -> Result<HashMap<String, u64>, CheckpointError> {
+        match tokio::fs::read(&self.path).await {
+            Ok(bytes) => serde_json::from_slice(&bytes).map_err(|e| CheckpointError::Load(e.to_string())),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(HashMap::new()),
+            Err(e) => Err(CheckpointError::Load(e.to_string())),
+        }
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileCheckpointStore {
+    async fn load_checkpoint(&self, handler_type: &EventHandlerType) -> Result<u64, CheckpointError> {
+        let _guard = self.lock.lock().await;
+        Ok(self.read().await?.get(handler_type.as_str()).copied().unwrap_or(0))
+    }
+
+    async fn save_checkpoint(&self, handler_type: &EventHandlerType, position: u64) -> Result<(), CheckpointError> {
+        let _guard = self.lock.lock().await;
+        let mut checkpoints = self.read().await?;
+        checkpoints.insert(handler_type.to_string(), position);
+
+        let bytes = serde_json::to_vec(&checkpoints).map_err(|e| CheckpointError::Save(e.to_string()))?;
+        let tmp = self.path.with_extension("tmp");
+        tokio::fs::write(&tmp, bytes).await.map_err(|e| CheckpointError::Save(e.to_string()))?;
+        tokio::fs::rename(&tmp, &self.path).await.map_err(|e| CheckpointError::Save(e.to_string()))
+    }
+}
+
+// Stored events are handed to event handlers through their Display form.
+impl Event for PositionedEvent {}
+
+// Default number of processed events after which the checkpoint is saved.
+pub const DEFAULT_CHECKPOINT_INTERVAL: usize = 1;
+
+// CheckpointedHandler feeds events from the global log to a named handler and
+// records its progress, so that it resumes where it left off after a restart.
+// The checkpoint is only advanced after the handler succeeded, which gives
+// at-least-once delivery.
+pub struct CheckpointedHandler {
+    handler: Arc<dyn EventHandler>,
+    matcher: Option<Arc<dyn EventMatcher>>,
+    store: Arc<dyn GlobalEventStore>,
+    checkpoints: Arc<dyn CheckpointStore>,
+    checkpoint_interval: usize,
+}
+
+// Unsaved is the progress made since the checkpoint was last saved.
+#[derive(Default)]
+struct Unsaved {
+    position: Option<u64>,
+    events: usize,
+}
+
+impl CheckpointedHandler {
+    pub fn new(
+        handler: Arc<dyn EventHandler>,
+        matcher: Option<Arc<dyn EventMatcher>>,
+        store: Arc<dyn GlobalEventStore>,
+        checkpoints: Arc<dyn CheckpointStore>,
+    ) -> Self {
+        Self {
+            handler,
+            matcher,
+            store,
+            checkpoints,
+            checkpoint_interval: DEFAULT_CHECKPOINT_INTERVAL,
+        }
+    }
+
+    // Save the checkpoint once every `interval` events instead of after every
+    // event. After a crash up to `interval - 1` events are handled again. The
+    // checkpoint is also saved when catching up ends, when the history has been
+    // replayed and before returning an error.
+    pub fn with_checkpoint_interval(mut self, interval: usize) -> Self {
+        self.checkpoint_interval = interval.max(1);
+        self
+    }
+
+    pub fn handler_type(&self) -> EventHandlerType {
+        self.handler.handler_type()
+    }
+
+    // Checkpoint returns the last position processed by the handler.
+    pub async fn checkpoint(&self) -> Result<u64, CheckpointError> {
+        self.checkpoints.load_checkpoint(&self.handler_type()).await
+    }
+
+    // Lag returns how many events the handler is behind the head of the log.
+    pub async fn lag(&self) -> Result<u64, CheckpointError> {
+        let head = self.store.head_position().await?;
+        Ok(head.saturating_sub(self.checkpoint().await?))
+    }
+
+    // CatchUp processes all events stored after the checkpoint and returns the
+    // number of events passed to the handler.
+    pub async fn catch_up(&self) -> Result<usize, CheckpointError> {
+        let mut unsaved = Unsaved::default();
+        let handled = self.catch_up_from_checkpoint(&mut unsaved).await;
+        self.save(&mut unsaved).await?;
+        handled
+    }
+
+    async fn catch_up_from_checkpoint(&self, unsaved: &mut Unsaved) -> Result<usize, CheckpointError> {
+        let from = self.checkpoint().await?;
+        let ctx = task::spawn(async {});
+        let mut handled = 0;
+        let mut events = self.store.load_all_stream(from, None).await?;
+        while let Some(event) = events.next().await {
+            if self.process(&ctx, event?, unsaved).await? {
+                handled += 1;
+            }
+        }
+        Ok(handled)
+    }
+
+    // Run processes the history after the checkpoint and then keeps handling live
+    // events until the store is closed or the handler fails.
+    pub async fn run(&self) -> Result<(), CheckpointError> {
+        let mut unsaved = Unsaved::default();
+        let result = self.run_from_checkpoint(&mut unsaved).await;
+        self.save(&mut unsaved).await?;
+        result
+    }
+
+    async fn run_from_checkpoint(&self, unsaved: &mut Unsaved) -> Result<(), CheckpointError> {
+        let from = self.checkpoint().await?;
+        let ctx = task::spawn(async {});
+        let mut subscription = self.store.subscribe(from, None).await?;
+        let mut replaying = !subscription.is_live();
+        while let Some(event) = subscription.next().await {
+            self.process(&ctx, event, unsaved).await?;
+            if replaying && subscription.is_live() {
+                replaying = false;
+                self.save(unsaved).await?;
+            }
+        }
+        Ok(())
+    }
+
+    // Events not selected by the matcher still advance the checkpoint, so they
+    // are not counted as lag.
+    async fn process(
+        &self,
+        ctx: &task::JoinHandle<()>,
+        event: PositionedEvent,
+        unsaved: &mut Unsaved,
+    ) -> Result<bool, CheckpointError> {
+        let position = event.position;
+        let matched = matches_event(self.matcher.as_deref(), event.event.as_ref());
+        if matched {
+            self.handler.handle_event(ctx, Arc::new(event))?;
+        }
+        unsaved.position = Some(position);
+        unsaved.events += 1;
+        if unsaved.events >= self.checkpoint_interval {
+            self.save(unsaved).await?;
+        }
+        Ok(matched)
+    }
+
+    async fn save(&self, unsaved: &mut Unsaved) -> Result<(), CheckpointError> {
+        if let Some(position) = unsaved.position.take() {
+            self.checkpoints.save_checkpoint(&self.handler_type(), position).await?;
+        }
+        unsaved.events = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eventhandler::EventHandlerFunc;
+    use crate::eventstore::memory::MemoryEventStore;
+    use crate::eventstore::testutil::event;
+    use crate::eventstore::EventStore;
+    use crate::matcher::{EventType, MatchEvents};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use uuid::Uuid;
+
+    fn counting_handler(count: Arc<AtomicUsize>) -> Arc<dyn EventHandler> {
+        Arc::new(EventHandlerFunc::new("counter".to_string(), move |_, _| {
+            count.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }))
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_resumes_from_checkpoint() {
+        let store = Arc::new(MemoryEventStore::new());
+        let checkpoints = Arc::new(MemoryCheckpointStore::new());
+        let count = Arc::new(AtomicUsize::new(0));
+        let id = Uuid::new_v4();
+
+        store.save(vec![event("Created", id, 1), event("Updated", id, 2)], 0).await.unwrap();
+
+        let handler = CheckpointedHandler::new(counting_handler(count.clone()), None, store.clone(), checkpoints.clone());
+        assert_eq!(handler.lag().await.unwrap(), 2);
+        assert_eq!(handler.catch_up().await.unwrap(), 2);
+        assert_eq!(handler.checkpoint().await.unwrap(), 2);
+        assert_eq!(handler.lag().await.unwrap(), 0);
+
+        // A restarted handler only sees the new events.
+        store.save(vec![event("Updated", id, 3)], 2).await.unwrap();
+        let restarted = CheckpointedHandler::new(counting_handler(count.clone()), None, store.clone(), checkpoints);
+        assert_eq!(restarted.lag().await.unwrap(), 1);
+        assert_eq!(restarted.catch_up().await.unwrap(), 1);
+        assert_eq!(count.load(Ordering::SeqCst), 3);
+    }
+
+    #[tokio::test]
+    async fn test_unmatched_events_advance_checkpoint() {
+        let store = Arc::new(MemoryEventStore::new());
+        let count = Arc::new(AtomicUsize::new(0));
+        let id = Uuid::new_v4();
+        store.save(vec![event("Created", id, 1), event("Updated", id, 2)], 0).await.unwrap();
+
+        let matcher: Arc<dyn EventMatcher> = Arc::new(MatchEvents::new(vec![EventType::from_name("Created")]));
+        let handler = CheckpointedHandler::new(
+            counting_handler(count.clone()),
+            Some(matcher),
+            store,
+            Arc::new(MemoryCheckpointStore::new()),
+        );
+        assert_eq!(handler.catch_up().await.unwrap(), 1);
+        assert_eq!(handler.lag().await.unwrap(), 0);
+        assert_eq!(count.load(Ordering::SeqCst), 1);
+    }
+
+    #[tokio::test]
+    async fn test_failed_event_is_not_checkpointed() {
+        let store = Arc::new(MemoryEventStore::new());
+        let id = Uuid::new_v4();
+        store.save(vec![event("Created", id, 1)], 0).await.unwrap();
+
+        let failing: Arc<dyn EventHandler> = Arc::new(EventHandlerFunc::new("failing".to_string(), |_, _| {
+            Err(EventHandlerError::HandlingError("boom".to_string()))
+        }));
+        let handler = CheckpointedHandler::new(failing, None, store, Arc::new(MemoryCheckpointStore::new()));
+        assert!(handler.catch_up().await.is_err());
+        assert_eq!(handler.checkpoint().await.unwrap(), 0);
+        assert_eq!(handler.lag().await.unwrap(), 1);
+    }
+
+    // CountingCheckpointStore counts how often checkpoints are saved.
+    #[derive(Default)]
+    struct CountingCheckpointStore {
+        checkpoints: MemoryCheckpointStore,
+        saves: AtomicUsize,
+    }
+
+    #[async_trait]
+    impl CheckpointStore for CountingCheckpointStore {
+        async fn load_checkpoint(&self, handler_type: &EventHandlerType) -> Result<u64, CheckpointError> {
+            self.checkpoints.load_checkpoint(handler_type).await
+        }
+
+        async fn save_checkpoint(&self, handler_type: &EventHandlerType, position: u64) -> Result<(), CheckpointError> {
+            self.saves.fetch_add(1, Ordering::SeqCst);
+            self.checkpoints.save_checkpoint(handler_type, position).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_checkpoint_interval() {
+        let store = Arc::new(MemoryEventStore::new());
+        let id = Uuid::new_v4();
+        let events = (1..=5).map(|version| event("Updated", id, version)).collect();
+        store.save(events, 0).await.unwrap();
+
+        let checkpoints = Arc::new(CountingCheckpointStore::default());
+        let handler = CheckpointedHandler::new(
+            counting_handler(Arc::new(AtomicUsize::new(0))),
+            None,
+            store.clone(),
+            checkpoints.clone(),
+        )
+        .with_checkpoint_interval(2);
+        assert_eq!(handler.catch_up().await.unwrap(), 5);
+        assert_eq!(handler.checkpoint().await.unwrap(), 5);
+        assert_eq!(checkpoints.saves.load(Ordering::SeqCst), 3);
+
+        // Progress made before a failure is saved.
+        store.save(vec![event("Updated", id, 6), event("Failing", id, 7)], 5).await.unwrap();
+        let failing: Arc<dyn EventHandler> = Arc::new(EventHandlerFunc::new("counter".to_string(), |_, event| {
+            if event.to_string().contains("Failing") {
+                return Err(EventHandlerError::HandlingError("boom".to_string()));
+            }
+            Ok(())
+        }));
+        let handler = CheckpointedHandler::new(failing, None, store, checkpoints).with_checkpoint_interval(10);
+        assert!(handler.catch_up().await.is_err());
+        assert_eq!(handler.checkpoint().await.unwrap(), 6);
+    }
+
+    #[tokio::test]
+    async fn test_run_handles_live_events() {
+        let store = Arc::new(MemoryEventStore::new());
+        let count = Arc::new(AtomicUsize::new(0));
+        let id = Uuid::new_v4();
+        store.save(vec![event("Created", id, 1)], 0).await.unwrap();
+
+        let checkpoints = Arc::new(MemoryCheckpointStore::new());
+        let handler = CheckpointedHandler::new(counting_handler(count.clone()), None, store.clone(), checkpoints.clone());
+        let running = tokio::spawn(async move {
+            handler.run().await.unwrap();
+            handler.checkpoint().await.unwrap()
+        });
+
+        // Wait for the history to be handled, the subscription is live from then on.
+        let handler_type = EventHandlerType::new("counter".to_string());
+        while checkpoints.load_checkpoint(&handler_type).await.unwrap() < 1 {
+            tokio::task::yield_now().await;
+        }
+        store.save(vec![event("Updated", id, 2)], 1).await.unwrap();
+        store.close().await.unwrap();
+        assert_eq!(running.await.unwrap(), 2);
+        assert_eq!(count.load(Ordering::SeqCst), 2);
+    }
+
+    #[tokio::test]
+    async fn test_file_checkpoint_store() {
+        let path = std::env::temp_dir().join(format!("checkpoints-{}.json", Uuid::new_v4()));
+        let handler_type = EventHandlerType::new("projector".to_string());
+
+        let store = FileCheckpointStore::new(&path);
+        assert_eq!(store.load_checkpoint(&handler_type).await.unwrap(), 0);
+        store.save_checkpoint(&handler_type, 42).await.unwrap();
+
+        // A new instance reads the persisted value.
+        let reopened = FileCheckpointStore::new(&path);
+        assert_eq!(reopened.load_checkpoint(&handler_type).await.unwrap(), 42);
+        std::fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,335 @@
+use apache_avro::reader::datum::GenericDatumReader;
+use apache_avro::types::Value as AvroValue;
+use apache_avro::writer::datum::GenericDatumWriter;
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::{Arc, OnceLock};
+use thiserror::Error;
+
+use super::context::{marshal_context, unmarshal_context};
+use crate::codec_main::{self, BasicEvent, CodecError};
+use crate::context::Context;
+use crate::event::{decode_event_data, EventData};
+
+pub mod store;
+
+pub use apache_avro::Schema;
+pub use store::{FileSchemaStore, SchemaStore};
+
+// Schema of the envelope written by the Avro event codec, for consumers in
+// other languages. Values of the metadata and context maps are JSON encoded.
+pub const ENVELOPE_SCHEMA: &str = r#"{
+    "type": "record",
+    "name": "Event",
+    "namespace": "eshorizon.codec",
+    "fields": [
+        {"name": "event_type", "type": "string"},
+        {"name": "data", "type": ["null", "bytes"]},
+        {"name": "timestamp", "type": {"type": "long", "logicalType": "timestamp-micros"}},
+        {"name": "aggregate_type", "type": "string"},
+        {"name": "aggregate_id", "type": {"type": "string", "logicalType": "uuid"}},
+        {"name": "version", "type": "int"},
+        {"name": "metadata", "type": {"type": "map", "values": "string"}},
+        {"name": "context", "type": {"type": "map", "values": "string"}}
+    ]
+}"#;
+
+// First byte of encoded event data, followed by the big endian schema id.
+const MAGIC_BYTE: u8 = 0;
+
+// Errors related to Avro encoding and schemas.
+#[derive(Error, Debug)]
+pub enum AvroError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid schema: {0}")]
+    Schema(String),
+
+    #[error("could not encode: {0}")]
+    Encode(String),
+
+    #[error("could not decode: {0}")]
+    Decode(String),
+
+    #[error("unknown schema id {0}")]
+    UnknownSchema(u32),
+
+    #[error("no schema registered for {0}")]
+    NoSchema(String),
+
+    #[error("schema for {subject} is incompatible with the previous version: {reason}")]
+    Incompatible { subject: String, reason: String },
+}
+
+impl From<AvroError> for CodecError {
+    fn from(err: AvroError) -> Self {
+        CodecError::new(&err.to_string())
+    }
+}
+
+fn write_datum(schema: &Schema, value: AvroValue) -> Result<Vec<u8>, AvroError> {
+    GenericDatumWriter::builder(schema)
+        .build()
+        .and_then(|writer| writer.write_value_to_vec(value))
+        .map_err(|e| AvroError::Encode(e.to_string()))
+}
+
+// Reads a datum written with the writer schema, resolved against the reader schema if any.
+fn read_datum(writer: &Schema, reader: Option<&Schema>, input: &mut &[u8]) -> Result<AvroValue, AvroError> {
+    GenericDatumReader::builder(writer)
+        .maybe_reader_schema(reader)
+        .build()
+        .and_then(|datum_reader| datum_reader.read_value(input))
+        .map_err(|e| AvroError::Decode(e.to_string()))
+}
+
+fn envelope_schema() -> &'static Schema {
+    static ENVELOPE: OnceLock<Schema> = OnceLock::new();
+    ENVELOPE.get_or_init(|| Schema::parse_str(ENVELOPE_SCHEMA).expect("invalid envelope schema"))
+}
+
+// EventCodec encodes events in the Avro envelope, with the data encoded with
+// the latest schema registered for its event type. The data starts with the
+// id of the schema it was written with, so older data is resolved against the
+// latest schema when decoded and compatible changes need no upcasters.
+pub struct EventCodec {
+    schemas: Arc<dyn SchemaStore>,
+}
+
+impl EventCodec {
+    pub fn new(schemas: Arc<dyn SchemaStore>) -> Self {
+        EventCodec { schemas }
+    }
+
+    // Encodes event data with the latest schema of the event type.
+    pub fn encode_data(&self, event_type: &str, data: &Value) -> Result<Vec<u8>, AvroError> {
+        let (id, schema) = self.schemas.latest(event_type)?;
+        let value = AvroValue::try_from(data.clone())
+            .and_then(|value| value.resolve(&schema))
+            .map_err(|e| AvroError::Encode(e.to_string()))?;
+        let mut buf = vec![MAGIC_BYTE];
+        buf.extend_from_slice(&id.to_be_bytes());
+        buf.extend(write_datum(&schema, value)?);
+        Ok(buf)
+    }
+
+    // Decodes event data written with any registered schema of the event type
+    // into the shape of its latest schema.
+    pub fn decode_data(&self, event_type: &str, data: &[u8]) -> Result<Value, AvroError> {
+        let [MAGIC_BYTE, a, b, c, d, ref input @ ..] = *data else {
+            return Err(AvroError::Decode("missing schema id".to_string()));
+        };
+        let writer = self.schemas.schema(u32::from_be_bytes([a, b, c, d]))?;
+        let reader = match self.schemas.latest(event_type) {
+            Ok((_, reader)) => reader,
+            Err(AvroError::NoSchema(_)) => writer.clone(),
+            Err(err) => return Err(err),
+        };
+        let value = read_datum(&writer, Some(&reader), &mut &input[..])?;
+        Value::try_from(value).map_err(|e| AvroError::Decode(e.to_string()))
+    }
+}
+
+fn string_map(values: HashMap<String, Value>) -> AvroValue {
+    AvroValue::Map(
+        values
+            .into_iter()
+            .map(|(key, value)| (key, AvroValue::String(value.to_string())))
+            .collect(),
+    )
+}
+
+fn json_map(value: AvroValue) -> Result<HashMap<String, Value>, AvroError> {
+    let AvroValue::Map(values) = value else {
+        return Err(AvroError::Decode("expected a map".to_string()));
+    };
+    values
+        .into_iter()
+        .map(|(key, value)| match value {
+            AvroValue::String(value) => serde_json::from_str(&value)
+                .map(|value| (key, value))
+                .map_err(|e| AvroError::Decode(e.to_string())),
+            _ => Err(AvroError::Decode(format!("expected a string for {}", key))),
+        })
+        .collect()
+}
+
+#[async_trait]
+impl codec_main::EventCodec for EventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let data = match event.data() {
+            Some(data) => {
+                let data = serde_json::to_value(data).map_err(|e| CodecError::new(&e.to_string()))?;
+                let data = self.encode_data(&event.event_type(), &data)?;
+                AvroValue::Union(1, Box::new(AvroValue::Bytes(data)))
+            }
+            None => AvroValue::Union(0, Box::new(AvroValue::Null)),
+        };
+        let timestamp: DateTime<Utc> = event.timestamp().into();
+        let envelope = AvroValue::Record(vec![
+            ("event_type".to_string(), AvroValue::String(event.event_type())),
+            ("data".to_string(), data),
+            ("timestamp".to_string(), AvroValue::TimestampMicros(timestamp.timestamp_micros())),
+            ("aggregate_type".to_string(), AvroValue::String(event.aggregate_type())),
+            ("aggregate_id".to_string(), AvroValue::Uuid(event.aggregate_id())),
+            ("version".to_string(), AvroValue::Int(event.version())),
+            ("metadata".to_string(), string_map(event.metadata())),
+            ("context".to_string(), string_map(marshal_context(ctx).map_err(|e| CodecError::new(&e))?)),
+        ]);
+        Ok(write_datum(envelope_schema(), envelope)?)
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let envelope = read_datum(envelope_schema(), None, &mut data.as_slice())?;
+        let AvroValue::Record(fields) = envelope else {
+            return Err(AvroError::Decode("expected an event record".to_string()).into());
+        };
+        // The fields are in the order of the envelope schema.
+        let Ok::<[_; 8], _>(
+            [
+                (_, AvroValue::String(event_type)),
+                (_, data),
+                (_, AvroValue::TimestampMicros(timestamp)),
+                (_, AvroValue::String(aggregate_type)),
+                (_, AvroValue::Uuid(aggregate_id)),
+                (_, AvroValue::Int(version)),
+                (_, metadata),
+                (_, context),
+            ],
+        ) = fields.try_into()
+        else {
+            return Err(AvroError::Decode("invalid event record".to_string()).into());
+        };
+
+        let data = match data {
+            AvroValue::Union(_, data) => match *data {
+                AvroValue::Bytes(data) => {
+                    let data = self.decode_data(&event_type, &data)?;
+                    Some(
+                        decode_event_data(&event_type, &mut <dyn erased_serde::Deserializer>::erase(data))
+                            .map_err(|e| CodecError::new(&e))? as Box<dyn EventData>,
+                    )
+                }
+                _ => None,
+            },
+            _ => return Err(AvroError::Decode("invalid event data".to_string()).into()),
+        };
+        let timestamp =
+            DateTime::from_timestamp_micros(timestamp).ok_or_else(|| CodecError::new("invalid event timestamp"))?;
+
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, json_map(context)?).map_err(|e| CodecError::new(&e))?;
+
+        let event = BasicEvent::new(event_type, data, timestamp.into(), aggregate_type, aggregate_id, version)
+            .with_metadata(json_map(metadata)?);
+        Ok((Arc::new(event), ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec_main::EventCodec as _;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
+    use std::any::Any;
+    use std::time::SystemTime;
+    use uuid::Uuid;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct AccountOpened {
+        owner: String,
+        #[serde(default)]
+        currency: Option<String>,
+    }
+
+    impl EventData for AccountOpened {}
+
+    #[tokio::test]
+    async fn test_event_codec() {
+        crate::event::register_event_data(
+            "AvroAccountOpened".to_string(),
+            Box::new(|| Box::new(AccountOpened { owner: String::new(), currency: None })),
+        );
+        let dir = std::env::temp_dir().join(format!("eshorizon-schemas-{}", Uuid::new_v4()));
+        let schemas = Arc::new(FileSchemaStore::new(&dir).unwrap());
+        schemas
+            .register(
+                "AvroAccountOpened",
+                &json!({"type": "record", "name": "AccountOpened", "fields": [{"name": "owner", "type": "string"}]}),
+            )
+            .unwrap();
+        let codec = EventCodec::new(schemas.clone());
+
+        let id = Uuid::new_v4();
+        let event = BasicEvent::new(
+            "AvroAccountOpened".to_string(),
+            Some(Box::new(AccountOpened { owner: "Ada".to_string(), currency: None })),
+            SystemTime::now(),
+            "Account".to_string(),
+            id,
+            1,
+        )
+        .with_metadata(HashMap::from([("source".to_string(), json!("test"))]));
+        let data = codec.marshal_event(&Context::new(), Arc::new(event)).await.unwrap();
+
+        // The envelope can be read with its published schema.
+        let envelope = Schema::parse_str(ENVELOPE_SCHEMA).unwrap();
+        let decoded = Value::try_from(read_datum(&envelope, None, &mut data.as_slice()).unwrap()).unwrap();
+        assert_eq!(decoded["aggregate_id"], id.to_string());
+        assert_eq!(decoded["metadata"]["source"], "\"test\"");
+
+        // Data written with the first schema is read with the evolved one.
+        schemas
+            .register(
+                "AvroAccountOpened",
+                &json!({"type": "record", "name": "AccountOpened", "fields": [
+                    {"name": "owner", "type": "string"},
+                    {"name": "currency", "type": ["string", "null"], "default": "EUR"},
+                ]}),
+            )
+            .unwrap();
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!((decoded.aggregate_id(), decoded.version()), (id, 1));
+        assert_eq!(decoded.metadata()["source"], "test");
+        let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<AccountOpened>().unwrap();
+        assert_eq!((data.owner.as_str(), data.currency.as_deref()), ("Ada", Some("EUR")));
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_recursive_and_logical_types() {
+        let dir = std::env::temp_dir().join(format!("eshorizon-schemas-{}", Uuid::new_v4()));
+        let schemas = Arc::new(FileSchemaStore::new(&dir).unwrap());
+        schemas
+            .register(
+                "AvroTasksPlanned",
+                &json!({"type": "record", "name": "Task", "fields": [
+                    {"name": "id", "type": {"type": "string", "logicalType": "uuid"}},
+                    {"name": "due", "type": {"type": "int", "logicalType": "date"}},
+                    {"name": "next", "type": ["null", "Task"], "default": null},
+                ]}),
+            )
+            .unwrap();
+        let codec = EventCodec::new(schemas);
+
+        let data = json!({
+            "id": Uuid::new_v4().to_string(),
+            "due": 20000,
+            "next": {"id": Uuid::new_v4().to_string(), "due": 20001, "next": null},
+        });
+        let encoded = codec.encode_data("AvroTasksPlanned", &data).unwrap();
+        assert_eq!(codec.decode_data("AvroTasksPlanned", &encoded).unwrap(), data);
+        assert!(codec.encode_data("AvroTasksPlanned", &json!({"id": "not a uuid", "due": 1})).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
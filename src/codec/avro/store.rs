@@ -0,0 +1,170 @@
+use apache_avro::schema_compatibility::SchemaCompatibility;
+use apache_avro::Schema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock};
+
+use super::AvroError;
+
+// SchemaStore assigns ids to the Avro schemas of event types, which are
+// embedded in encoded events to find the schema they were written with.
+pub trait SchemaStore: Send + Sync {
+    // Registers a schema for a subject and returns its id. Registering a schema
+    // that is already the latest one for the subject returns its existing id,
+    // and new versions must be able to read data written with the previous one.
+    fn register(&self, subject: &str, schema: &Value) -> Result<u32, AvroError>;
+
+    fn schema(&self, id: u32) -> Result<Arc<Schema>, AvroError>;
+
+    // Returns the id and schema of the latest version of a subject.
+    fn latest(&self, subject: &str) -> Result<(u32, Arc<Schema>), AvroError>;
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct StoredSchema {
+    id: u32,
+    subject: String,
+    schema: Value,
+}
+
+// A stored schema together with its parsed form.
+struct RegisteredSchema {
+    stored: StoredSchema,
+    parsed: Arc<Schema>,
+}
+
+impl RegisteredSchema {
+    fn new(stored: StoredSchema) -> Result<Self, AvroError> {
+        let parsed = Arc::new(parse(&stored.schema)?);
+        Ok(RegisteredSchema { stored, parsed })
+    }
+}
+
+fn parse(schema: &Value) -> Result<Schema, AvroError> {
+    Schema::parse(schema).map_err(|e| AvroError::Schema(e.to_string()))
+}
+
+// FileSchemaStore keeps one JSON file per registered schema in a directory,
+// so schema ids stay stable across restarts. Schemas are parsed once, when
+// they are loaded or registered.
+pub struct FileSchemaStore {
+    dir: PathBuf,
+    schemas: RwLock<Vec<RegisteredSchema>>,
+}
+
+impl FileSchemaStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, AvroError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+
+        let mut schemas = Vec::new();
+        for entry in fs::read_dir(&dir)? {
+            let path = entry?.path();
+            if path.extension().and_then(|extension| extension.to_str()) != Some("json") {
+                continue;
+            }
+            let stored: StoredSchema = serde_json::from_slice(&fs::read(&path)?)
+                .map_err(|e| AvroError::Schema(format!("{}: {}", path.display(), e)))?;
+            schemas.push(RegisteredSchema::new(stored)?);
+        }
+        schemas.sort_by_key(|registered| registered.stored.id);
+
+        Ok(FileSchemaStore {
+            dir,
+            schemas: RwLock::new(schemas),
+        })
+    }
+}
+
+impl SchemaStore for FileSchemaStore {
+    fn register(&self, subject: &str, schema: &Value) -> Result<u32, AvroError> {
+        let parsed = parse(schema)?;
+        let mut schemas = self.schemas.write().unwrap();
+
+        if let Some(latest) = schemas.iter().rev().find(|registered| registered.stored.subject == subject) {
+            if &latest.stored.schema == schema {
+                return Ok(latest.stored.id);
+            }
+            SchemaCompatibility::can_read(&latest.parsed, &parsed).map_err(|e| AvroError::Incompatible {
+                subject: subject.to_string(),
+                reason: e.to_string(),
+            })?;
+        }
+
+        let stored = StoredSchema {
+            id: schemas.last().map_or(1, |registered| registered.stored.id + 1),
+            subject: subject.to_string(),
+            schema: schema.clone(),
+        };
+        let path = self.dir.join(format!("{}.json", stored.id));
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&stored).map_err(|e| AvroError::Schema(e.to_string()))?)?;
+        fs::rename(&tmp, &path)?;
+
+        let id = stored.id;
+        schemas.push(RegisteredSchema {
+            stored,
+            parsed: Arc::new(parsed),
+        });
+        Ok(id)
+    }
+
+    fn schema(&self, id: u32) -> Result<Arc<Schema>, AvroError> {
+        let schemas = self.schemas.read().unwrap();
+        let registered = schemas
+            .iter()
+            .find(|registered| registered.stored.id == id)
+            .ok_or(AvroError::UnknownSchema(id))?;
+        Ok(registered.parsed.clone())
+    }
+
+    fn latest(&self, subject: &str) -> Result<(u32, Arc<Schema>), AvroError> {
+        let schemas = self.schemas.read().unwrap();
+        let registered = schemas
+            .iter()
+            .rev()
+            .find(|registered| registered.stored.subject == subject)
+            .ok_or_else(|| AvroError::NoSchema(subject.to_string()))?;
+        Ok((registered.stored.id, registered.parsed.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use uuid::Uuid;
+
+    #[test]
+    fn test_file_schema_store() {
+        let dir = std::env::temp_dir().join(format!("eshorizon-schemas-{}", Uuid::new_v4()));
+        let store = FileSchemaStore::new(&dir).unwrap();
+
+        let v1 = json!({"type": "record", "name": "User", "fields": [{"name": "name", "type": "string"}]});
+        let v2 = json!({"type": "record", "name": "User", "fields": [
+            {"name": "name", "type": "string"},
+            {"name": "email", "type": ["null", "string"], "default": null},
+        ]});
+        let incompatible = json!({"type": "record", "name": "User", "fields": [{"name": "age", "type": "int"}]});
+
+        assert_eq!(store.register("UserCreated", &v1).unwrap(), 1);
+        assert_eq!(store.register("UserCreated", &v1).unwrap(), 1);
+        assert_eq!(store.register("UserCreated", &v2).unwrap(), 2);
+        assert!(matches!(
+            store.register("UserCreated", &incompatible),
+            Err(AvroError::Incompatible { .. })
+        ));
+        assert!(matches!(store.latest("UserDeleted"), Err(AvroError::NoSchema(_))));
+
+        // Schemas are loaded again from the directory.
+        let store = FileSchemaStore::new(&dir).unwrap();
+        let (id, latest) = store.latest("UserCreated").unwrap();
+        assert_eq!((id, latest.as_ref()), (2, &Schema::parse(&v2).unwrap()));
+        assert_eq!(*store.schema(1).unwrap(), Schema::parse(&v1).unwrap());
+        assert!(matches!(store.schema(3), Err(AvroError::UnknownSchema(3))));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -2,7 +2,13 @@ use bson::{self, Bson};
 use mongodb::bson::doc;
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
-use std::error::Error;
+use std::sync::Arc;
+use async_trait::async_trait;
+
+use super::context::{marshal_context, unmarshal_context};
+use crate::codec_main::{self, CodecError};
+use crate::command_main::decode_command;
+use crate::context::Context;
 
 // Command struct, similar to the internal command structure in Go
 #[derive(Debug, Serialize, Deserialize,PartialEq)]
@@ -27,6 +33,37 @@ impl CommandCodec {
     }
 }
 
+// CommandCodec decodes commands into the concrete type registered with
+// command_main::register_command for their command type.
+#[async_trait]
+impl codec_main::CommandCodec for CommandCodec {
+    async fn marshal_command(&self, ctx: &Context, command: Arc<dyn codec_main::Command>) -> Result<Vec<u8>, CodecError> {
+        let command = Command {
+            command_type: command.command_type(),
+            command: bson::to_bson(&*command).map_err(|e| CodecError::new(&e.to_string()))?,
+            context: marshal_context(ctx).map_err(|e| CodecError::new(&e))?,
+        };
+        CommandCodec::marshal_command(&command).map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_command(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Command>, Context), CodecError> {
+        let command = CommandCodec::unmarshal_command(&data).map_err(|e| CodecError::new(&e.to_string()))?;
+        let decoded = decode_command(
+            &command.command_type,
+            &mut <dyn erased_serde::Deserializer>::erase(bson::Deserializer::new(command.command)),
+        )
+        .map_err(|e| CodecError::new(&e.to_string()))?;
+
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, command.context).map_err(|e| CodecError::new(&e))?;
+        Ok((Arc::from(decoded as Box<dyn codec_main::Command>), ctx))
+    }
+}
+
 // Unit tests for CommandCodec
 #[cfg(test)]
 mod tests {
@@ -83,4 +120,44 @@ mod tests {
         // Ensure the deserialized command is the same as the original
         assert_eq!(deserialized_command, command);
     }
-}
\ No newline at end of file
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RenameUser {
+        id: uuid::Uuid,
+        name: String,
+    }
+
+    impl crate::command_main::Command for RenameUser {
+        fn aggregate_id(&self) -> uuid::Uuid {
+            self.id
+        }
+
+        fn aggregate_type(&self) -> String {
+            "User".to_string()
+        }
+
+        fn command_type(&self) -> String {
+            "BsonCodecRenameUser".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_codec() {
+        use crate::codec_main::CommandCodec as _;
+
+        crate::command_main::register_command(
+            "BsonCodecRenameUser".to_string(),
+            Box::new(|| Box::new(RenameUser { id: uuid::Uuid::nil(), name: String::new() })),
+        );
+
+        let id = uuid::Uuid::new_v4();
+        let codec = CommandCodec;
+        let data = codec
+            .marshal_command(&Context::new(), Arc::new(RenameUser { id, name: "Grace".to_string() }))
+            .await
+            .unwrap();
+        let (command, _) = codec.unmarshal_command(Context::new(), data).await.unwrap();
+        let command = (command.as_ref() as &dyn std::any::Any).downcast_ref::<RenameUser>().unwrap();
+        assert_eq!((command.id, command.name.as_str()), (id, "Grace"));
+    }
+}
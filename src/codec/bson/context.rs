@@ -0,0 +1,41 @@
+use mongodb::bson::Bson;
+use std::collections::HashMap;
+
+use crate::context::{self, CloneableAny, Context};
+
+// Marshal a context through the registered context marshalers into BSON values.
+pub fn marshal_context(ctx: &Context) -> Result<HashMap<String, Bson>, String> {
+    context::marshal_context(ctx)?
+        .into_iter()
+        .map(|(key, value)| {
+            let value = if let Some(value) = value.downcast_ref::<i32>() {
+                Bson::Int32(*value)
+            } else if let Some(value) = value.downcast_ref::<String>() {
+                Bson::String(value.clone())
+            } else {
+                return Err(format!("unsupported context value for {}", key));
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+// Unmarshal BSON context values through the registered context unmarshalers.
+pub fn unmarshal_context(ctx: &mut Context, values: HashMap<String, Bson>) -> Result<(), String> {
+    let values = values
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match value {
+                Bson::String(value) => CloneableAny::new(value),
+                Bson::Int32(value) => CloneableAny::new(value),
+                Bson::Int64(value) => match i32::try_from(value) {
+                    Ok(value) => CloneableAny::new(value),
+                    Err(_) => return Err(format!("unsupported context value for {}", key)),
+                },
+                _ => return Err(format!("unsupported context value for {}", key)),
+            };
+            Ok((key, value))
+        })
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    context::unmarshal_context(ctx, values)
+}
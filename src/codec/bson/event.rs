@@ -2,8 +2,16 @@ use chrono::{DateTime, Utc};
 use mongodb::bson::{self, Bson};
 use serde::{Deserialize, Serialize};
 use std::collections::HashMap;
+use std::sync::Arc;
+use async_trait::async_trait;
 use uuid::Uuid;
 
+use super::context::{marshal_context, unmarshal_context};
+use crate::codec_main::{self, BasicEvent, CodecError};
+use crate::context::Context;
+use crate::event::{decode_event_data, EventData};
+use crate::upcast::{UpcastError, UpcasterRegistry, DEFAULT_SCHEMA_VERSION};
+
 // Event struct to match the bson event format
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub struct Event {
@@ -14,16 +22,31 @@ pub struct Event {
     pub aggregate_type: String,
     pub aggregate_id: Uuid,
     pub version: i32,
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub metadata: HashMap<String, Bson>,
     #[serde(default, skip_serializing_if = "HashMap::is_empty")]
     pub context: HashMap<String, Bson>,
 }
 
-// EventCodec responsible for encoding and decoding events to and from BSON
-pub struct EventCodec;
+fn default_schema_version() -> u32 {
+    DEFAULT_SCHEMA_VERSION
+}
+
+// EventCodec responsible for encoding and decoding events to and from BSON.
+// Events are written with the current schema version of their event type and
+// upcast by the registered upcasters when decoded.
+#[derive(Default, Clone)]
+pub struct EventCodec {
+    upcasters: Arc<UpcasterRegistry>,
+}
 
 impl EventCodec {
+    pub fn new(upcasters: Arc<UpcasterRegistry>) -> Self {
+        EventCodec { upcasters }
+    }
+
     // Marshal the event into BSON bytes
     pub fn marshal_event(event: &Event) -> Result<Vec<u8>, bson::ser::Error> {
         bson::to_vec(event)
@@ -33,10 +56,91 @@ impl EventCodec {
     pub fn unmarshal_event(data: &[u8]) -> Result<Event, bson::de::Error> {
         bson::from_slice(data)
     }
+
+    // Upcast the event type and data of an unmarshaled event to the current schema
+    pub fn upcast_event(event: &mut Event, upcasters: &UpcasterRegistry) -> Result<(), UpcastError> {
+        let (event_type, schema_version, data) =
+            upcasters.upcast_bson(event.event_type.clone(), event.schema_version, event.data.take())?;
+        event.event_type = event_type;
+        event.schema_version = schema_version;
+        event.data = data;
+        Ok(())
+    }
+}
+
+// EventCodec encodes any event whose data is registered with
+// event::register_event_data, decoding the data into its concrete type.
+#[async_trait]
+impl codec_main::EventCodec for EventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let data = event
+            .data()
+            .map(bson::to_bson)
+            .transpose()
+            .map_err(|e| CodecError::new(&e.to_string()))?;
+        let metadata = event
+            .metadata()
+            .into_iter()
+            .map(|(key, value)| Bson::try_from(value).map(|value| (key, value)))
+            .collect::<Result<HashMap<_, _>, _>>()
+            .map_err(|e| CodecError::new(&e.to_string()))?;
+
+        let mut encoded = Event::new(
+            event.event_type(),
+            data,
+            event.timestamp().into(),
+            event.aggregate_type(),
+            event.aggregate_id(),
+            event.version(),
+            metadata,
+            marshal_context(ctx).map_err(|e| CodecError::new(&e))?,
+        );
+        encoded.schema_version = self.upcasters.schema_version(&encoded.event_type);
+        EventCodec::marshal_event(&encoded).map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let mut event = EventCodec::unmarshal_event(&data).map_err(|e| CodecError::new(&e.to_string()))?;
+        EventCodec::upcast_event(&mut event, &self.upcasters).map_err(|e| CodecError::new(&e.to_string()))?;
+
+        let data = match event.data {
+            Some(data) => Some(
+                decode_event_data(
+                    &event.event_type,
+                    &mut <dyn erased_serde::Deserializer>::erase(bson::Deserializer::new(data)),
+                )
+                .map_err(|e| CodecError::new(&e))? as Box<dyn EventData>,
+            ),
+            None => None,
+        };
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, event.context).map_err(|e| CodecError::new(&e))?;
+
+        let metadata = event
+            .metadata
+            .into_iter()
+            .map(|(key, value)| (key, value.into_relaxed_extjson()))
+            .collect();
+        let decoded = BasicEvent::new(
+            event.event_type,
+            data,
+            event.timestamp.into(),
+            event.aggregate_type,
+            event.aggregate_id,
+            event.version,
+        )
+        .with_metadata(metadata);
+        Ok((Arc::new(decoded), ctx))
+    }
 }
 
 // Example of creating a new event and serializing/deserializing
 impl Event {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         event_type: String,
         data: Option<Bson>,
@@ -54,6 +158,7 @@ impl Event {
             aggregate_type,
             aggregate_id,
             version,
+            schema_version: DEFAULT_SCHEMA_VERSION,
             metadata,
             context,
         }
@@ -175,4 +280,107 @@ mod tests {
         assert_eq!(deserialized_event.version, event.version);
         assert!(deserialized_event.data.is_none());
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_upcast_event() {
+        let upcasters = UpcasterRegistry::new();
+        upcasters.register_fn("TestEvent".to_string(), 1, |data| Ok(serde_json::json!({"value": data})));
+        upcasters.set_schema_version("TestEvent".to_string(), 2);
+
+        let event = Event::new(
+            "TestEvent".to_string(),
+            Some(Bson::String("TestData".to_string())),
+            Utc::now(),
+            "TestAggregate".to_string(),
+            Uuid::new_v4(),
+            1,
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let serialized_event = EventCodec::marshal_event(&event).unwrap();
+
+        let mut deserialized_event = EventCodec::unmarshal_event(&serialized_event).unwrap();
+        EventCodec::upcast_event(&mut deserialized_event, &upcasters).unwrap();
+        assert_eq!(deserialized_event.schema_version, 2);
+        assert_eq!(
+            deserialized_event.data,
+            Some(Bson::Document(bson::doc! {"value": "TestData"}))
+        );
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct UserRenamed {
+        name: String,
+    }
+
+    impl EventData for UserRenamed {}
+
+    #[tokio::test]
+    async fn test_event_codec() {
+        use crate::codec_main::EventCodec as _;
+
+        crate::event::register_event_data(
+            "BsonCodecUserRenamed".to_string(),
+            Box::new(|| Box::new(UserRenamed { name: String::new() })),
+        );
+
+        let aggregate_id = Uuid::new_v4();
+        let event = BasicEvent::new(
+            "BsonCodecUserRenamed".to_string(),
+            Some(Box::new(UserRenamed { name: "Grace".to_string() })),
+            std::time::SystemTime::now(),
+            "User".to_string(),
+            aggregate_id,
+            2,
+        )
+        .with_metadata(HashMap::from([("source".to_string(), serde_json::json!("test"))]));
+
+        let codec = EventCodec::default();
+        let data = codec.marshal_event(&Context::new(), Arc::new(event)).await.unwrap();
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+
+        assert_eq!(decoded.aggregate_id(), aggregate_id);
+        assert_eq!(decoded.version(), 2);
+        assert_eq!(decoded.metadata()["source"], "test");
+        let data = decoded.data().unwrap() as &dyn std::any::Any;
+        assert_eq!(data.downcast_ref::<UserRenamed>().unwrap().name, "Grace");
+    }
+
+    #[tokio::test]
+    async fn test_event_codec_upcasts() {
+        use crate::codec_main::EventCodec as _;
+
+        crate::event::register_event_data(
+            "BsonCodecUserRegistered".to_string(),
+            Box::new(|| Box::new(UserRenamed { name: String::new() })),
+        );
+        let upcasters = Arc::new(UpcasterRegistry::new());
+        upcasters.register_fn("BsonCodecUserCreated".to_string(), 1, |data| {
+            Ok(serde_json::json!({"name": data["first_name"]}))
+        });
+        upcasters.register_rename("BsonCodecUserCreated".to_string(), 2, "BsonCodecUserRegistered".to_string());
+        upcasters.set_schema_version("BsonCodecUserRegistered".to_string(), 2);
+        let codec = EventCodec::new(upcasters);
+
+        // Old events are renamed and decoded into the current shape.
+        let stored = Event::new(
+            "BsonCodecUserCreated".to_string(),
+            Some(Bson::Document(bson::doc! {"first_name": "Grace"})),
+            Utc::now(),
+            "User".to_string(),
+            Uuid::new_v4(),
+            1,
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let data = EventCodec::marshal_event(&stored).unwrap();
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!(decoded.event_type(), "BsonCodecUserRegistered");
+        let data = decoded.data().unwrap() as &dyn std::any::Any;
+        assert_eq!(data.downcast_ref::<UserRenamed>().unwrap().name, "Grace");
+
+        // New events are written at the current version.
+        let data = codec.marshal_event(&Context::new(), decoded).await.unwrap();
+        assert_eq!(EventCodec::unmarshal_event(&data).unwrap().schema_version, 2);
+    }
+}
@@ -1,3 +1,5 @@
 pub mod uuid;
+pub mod context;
 pub mod event;
-pub mod command;
\ No newline at end of file
+pub mod command;
+pub mod snapshot;
\ No newline at end of file
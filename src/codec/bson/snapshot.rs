@@ -0,0 +1,126 @@
+use chrono::{DateTime, Utc};
+use mongodb::bson::{self, Bson};
+use serde::{Deserialize, Serialize};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::codec_main::{self, CodecError};
+use crate::snapshot::{AggregateType, Snapshot as AggregateSnapshot, SnapshotFactoryRegistry};
+
+// Snapshot struct matching the BSON snapshot format.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Snapshot {
+    pub version: i32,
+    pub aggregate_type: String,
+    pub timestamp: DateTime<Utc>,
+    pub schema_version: u32,
+    pub state: Bson,
+}
+
+// SnapshotCodec encodes and decodes snapshots in BSON format. Upgraders work on
+// JSON, so outdated state is converted through relaxed extended JSON.
+pub struct SnapshotCodec {
+    registry: Arc<SnapshotFactoryRegistry>,
+}
+
+impl SnapshotCodec {
+    pub fn new(registry: Arc<SnapshotFactoryRegistry>) -> Self {
+        SnapshotCodec { registry }
+    }
+}
+
+impl codec_main::SnapshotCodec for SnapshotCodec {
+    fn marshal_snapshot(&self, snapshot: &AggregateSnapshot) -> Result<Vec<u8>, CodecError> {
+        let state = bson::to_bson(&*snapshot.state).map_err(|e| CodecError::new(&e.to_string()))?;
+        let snapshot = Snapshot {
+            version: snapshot.version,
+            aggregate_type: snapshot.aggregate_type.as_str().to_string(),
+            timestamp: snapshot.timestamp.into(),
+            schema_version: self.registry.schema_version(&snapshot.aggregate_type),
+            state,
+        };
+        bson::to_vec(&snapshot).map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    fn unmarshal_snapshot(&self, aggregate_id: Uuid, data: &[u8]) -> Result<Option<AggregateSnapshot>, CodecError> {
+        let snapshot: Snapshot = bson::from_slice(data).map_err(|e| CodecError::new(&e.to_string()))?;
+        let aggregate_type = AggregateType::new(snapshot.aggregate_type);
+
+        let mut state = snapshot.state;
+        if snapshot.schema_version != self.registry.schema_version(&aggregate_type) {
+            let upgraded = self
+                .registry
+                .upgrade_state(&aggregate_type, snapshot.schema_version, state.into_relaxed_extjson())
+                .map_err(|e| CodecError::new(&e))?;
+            state = match upgraded {
+                Some(value) => Bson::try_from(value).map_err(|e| CodecError::new(&e.to_string()))?,
+                None => return Ok(None),
+            };
+        }
+        let state = self
+            .registry
+            .decode_snapshot_data(
+                aggregate_id,
+                aggregate_type.clone(),
+                &mut <dyn erased_serde::Deserializer>::erase(bson::Deserializer::new(state)),
+            )
+            .map_err(|e| CodecError::new(&e))?;
+
+        Ok(Some(AggregateSnapshot {
+            version: snapshot.version,
+            aggregate_type,
+            timestamp: snapshot.timestamp.into(),
+            state,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec_main::SnapshotCodec as _;
+    use crate::snapshot::SnapshotData;
+    use serde_json::json;
+    use std::time::SystemTime;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct CounterState {
+        id: Uuid,
+        total: i32,
+    }
+
+    impl SnapshotData for CounterState {}
+
+    #[test]
+    fn test_marshal_unmarshal_snapshot() {
+        let registry = Arc::new(SnapshotFactoryRegistry::new());
+        let aggregate_type = AggregateType::new("Counter".to_string());
+        registry.register_snapshot_data(aggregate_type.clone(), |id| Box::new(CounterState { id, total: 0 }));
+
+        let id = Uuid::new_v4();
+        let codec = SnapshotCodec::new(registry.clone());
+        let data = codec
+            .marshal_snapshot(&AggregateSnapshot {
+                version: 7,
+                aggregate_type: aggregate_type.clone(),
+                timestamp: SystemTime::now(),
+                state: Box::new(CounterState { id, total: 12 }),
+            })
+            .unwrap();
+
+        let decoded = codec.unmarshal_snapshot(id, &data).unwrap().unwrap();
+        assert_eq!(decoded.version, 7);
+        let state = decoded.state.as_any().downcast_ref::<CounterState>().unwrap();
+        assert_eq!((state.id, state.total), (id, 12));
+
+        // Migrate the stored state to schema version 2.
+        registry.set_schema_version(aggregate_type.clone(), 2);
+        registry.register_upgrader(aggregate_type, 1, |mut state| {
+            state["total"] = json!(state["total"].as_i64().unwrap_or_default() + 1);
+            Ok(state)
+        });
+        let decoded = codec.unmarshal_snapshot(id, &data).unwrap().unwrap();
+        let state = decoded.state.as_any().downcast_ref::<CounterState>().unwrap();
+        assert_eq!(state.total, 13);
+    }
+}
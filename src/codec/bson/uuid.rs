@@ -1,4 +1,4 @@
-use mongodb::bson::{self, Bson, Binary, doc, from_bson, to_bson, spec::BinarySubtype};
+use mongodb::bson::{Bson, Binary, spec::BinarySubtype};
 use serde::{Deserialize, Serialize, Serializer, Deserializer};
 use uuid::Uuid;
 use std::fmt;
@@ -58,7 +58,7 @@ pub fn decode_uuid_from_bson(bson: Bson) -> Result<Uuid, fmt::Error> {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use mongodb::bson::{Bson, Binary, spec::BinarySubtype};
+    use mongodb::bson::{from_bson, to_bson, Bson, Binary, spec::BinarySubtype};
 
     #[test]
     fn test_encode_uuid_to_bson() {
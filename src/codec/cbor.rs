@@ -0,0 +1,147 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use super::envelope;
+use crate::codec_main::{self, CodecError};
+use crate::context::Context;
+use crate::upcast::UpcasterRegistry;
+
+fn to_vec<T: serde::Serialize>(value: &T) -> Result<Vec<u8>, CodecError> {
+    let mut data = Vec::new();
+    ciborium::into_writer(value, &mut data).map_err(|e| CodecError::new(&e.to_string()))?;
+    Ok(data)
+}
+
+// EventCodec encodes events as CBOR maps with the envelope fields, upcasting
+// them with the registered upcasters when decoded.
+#[derive(Default, Clone)]
+pub struct EventCodec {
+    upcasters: Arc<UpcasterRegistry>,
+}
+
+impl EventCodec {
+    pub fn new(upcasters: Arc<UpcasterRegistry>) -> Self {
+        EventCodec { upcasters }
+    }
+}
+
+#[async_trait]
+impl codec_main::EventCodec for EventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let event = envelope::Event::from_event(ctx, event.as_ref(), &self.upcasters)?;
+        to_vec(&event)
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let event: envelope::Event = ciborium::from_reader(data.as_slice()).map_err(|e| CodecError::new(&e.to_string()))?;
+        event.into_event(ctx, &self.upcasters)
+    }
+}
+
+// CommandCodec encodes commands as CBOR maps with the envelope fields.
+pub struct CommandCodec;
+
+#[async_trait]
+impl codec_main::CommandCodec for CommandCodec {
+    async fn marshal_command(&self, ctx: &Context, command: Arc<dyn codec_main::Command>) -> Result<Vec<u8>, CodecError> {
+        let command = envelope::Command::from_command(ctx, command.as_ref())?;
+        to_vec(&command)
+    }
+
+    async fn unmarshal_command(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Command>, Context), CodecError> {
+        let command: envelope::Command =
+            ciborium::from_reader(data.as_slice()).map_err(|e| CodecError::new(&e.to_string()))?;
+        command.into_command(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec_main::{BasicEvent, CommandCodec as _, EventCodec as _};
+    use serde::{Deserialize, Serialize};
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+    use uuid::Uuid;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ItemAdded {
+        sku: String,
+        quantity: u32,
+    }
+
+    impl crate::event::EventData for ItemAdded {}
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct AddItem {
+        id: Uuid,
+        sku: String,
+    }
+
+    impl crate::command_main::Command for AddItem {
+        fn aggregate_id(&self) -> Uuid {
+            self.id
+        }
+
+        fn aggregate_type(&self) -> String {
+            "Cart".to_string()
+        }
+
+        fn command_type(&self) -> String {
+            "CborAddItem".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_codec() {
+        crate::event::register_event_data(
+            "CborItemAdded".to_string(),
+            Box::new(|| Box::new(ItemAdded { sku: String::new(), quantity: 0 })),
+        );
+
+        let id = Uuid::new_v4();
+        let event = BasicEvent::new(
+            "CborItemAdded".to_string(),
+            Some(Box::new(ItemAdded { sku: "A-1".to_string(), quantity: 2 })),
+            SystemTime::now(),
+            "Cart".to_string(),
+            id,
+            4,
+        )
+        .with_metadata(HashMap::from([("source".to_string(), serde_json::json!("test"))]));
+
+        let codec = EventCodec::default();
+        let data = codec.marshal_event(&Context::new(), Arc::new(event)).await.unwrap();
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!((decoded.aggregate_id(), decoded.version()), (id, 4));
+        assert_eq!(decoded.metadata()["source"], "test");
+        let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<ItemAdded>().unwrap();
+        assert_eq!((data.sku.as_str(), data.quantity), ("A-1", 2));
+    }
+
+    #[tokio::test]
+    async fn test_command_codec() {
+        crate::command_main::register_command(
+            "CborAddItem".to_string(),
+            Box::new(|| Box::new(AddItem { id: Uuid::nil(), sku: String::new() })),
+        );
+
+        let id = Uuid::new_v4();
+        let data = CommandCodec
+            .marshal_command(&Context::new(), Arc::new(AddItem { id, sku: "B-2".to_string() }))
+            .await
+            .unwrap();
+        let (command, _) = CommandCodec.unmarshal_command(Context::new(), data).await.unwrap();
+        let command = (command.as_ref() as &dyn Any).downcast_ref::<AddItem>().unwrap();
+        assert_eq!((command.id, command.sku.as_str()), (id, "B-2"));
+    }
+}
@@ -0,0 +1,431 @@
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{Arc, OnceLock};
+use mongodb::bson::{self, Bson, Document};
+use serde::Deserialize;
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::eventmaintenance::{MaintenanceError, PayloadCleanup};
+
+// Field of the reference object left in place of a checked payload.
+const CLAIM_CHECK_FIELD: &str = "$claim_check";
+
+const JSON_EVENT_PAYLOAD: &str = "raw_data";
+const BSON_EVENT_PAYLOAD: &str = "data";
+
+// Errors related to claim-check storage.
+#[derive(Error, Debug)]
+pub enum ClaimCheckError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("blob {0} not found")]
+    BlobNotFound(String),
+
+    #[error("invalid envelope: {0}")]
+    Format(String),
+}
+
+// BlobStore keeps payloads that are too large to be stored inline.
+pub trait BlobStore: Send + Sync {
+    // Stores a blob under a key, replacing any blob with the same key.
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), ClaimCheckError>;
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, ClaimCheckError>;
+
+    // Deletes a blob, deleting a missing blob is not an error.
+    fn delete(&self, key: &str) -> Result<(), ClaimCheckError>;
+}
+
+// FileBlobStore keeps one file per blob in a directory.
+pub struct FileBlobStore {
+    dir: PathBuf,
+}
+
+impl FileBlobStore {
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self, ClaimCheckError> {
+        let dir = dir.into();
+        fs::create_dir_all(&dir)?;
+        Ok(FileBlobStore { dir })
+    }
+
+    fn path(&self, key: &str) -> Result<PathBuf, ClaimCheckError> {
+        // Keys are UUIDs, anything else could escape the directory.
+        let key = Uuid::parse_str(key).map_err(|_| ClaimCheckError::BlobNotFound(key.to_string()))?;
+        Ok(self.dir.join(key.to_string()))
+    }
+}
+
+impl BlobStore for FileBlobStore {
+    fn put(&self, key: &str, data: &[u8]) -> Result<(), ClaimCheckError> {
+        let path = self.path(key)?;
+        let tmp = path.with_extension("tmp");
+        fs::write(&tmp, data)?;
+        fs::rename(&tmp, &path)?;
+        Ok(())
+    }
+
+    fn get(&self, key: &str) -> Result<Vec<u8>, ClaimCheckError> {
+        match fs::read(self.path(key)?) {
+            Ok(data) => Ok(data),
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => Err(ClaimCheckError::BlobNotFound(key.to_string())),
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<(), ClaimCheckError> {
+        match fs::remove_file(self.path(key)?) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => Err(err.into()),
+            _ => Ok(()),
+        }
+    }
+}
+
+// Returns the blob key of an event's payload. Keys are derived from the
+// aggregate ID and version, so maintenance can delete the blob of an event
+// without loading it.
+pub fn blob_key(aggregate_id: Uuid, version: i32) -> String {
+    Uuid::new_v5(&aggregate_id, &version.to_be_bytes()).to_string()
+}
+
+// The envelope fields identifying an encoded event.
+#[derive(Deserialize)]
+struct EventTarget {
+    aggregate_id: Uuid,
+    version: i32,
+}
+
+// BlobPayload is a payload value that can be read back from a blob.
+pub trait BlobPayload: Sized {
+    fn from_blob(data: &[u8]) -> Result<Self, ClaimCheckError>;
+}
+
+impl BlobPayload for Value {
+    fn from_blob(data: &[u8]) -> Result<Self, ClaimCheckError> {
+        serde_json::from_slice(data).map_err(|e| ClaimCheckError::Format(e.to_string()))
+    }
+}
+
+impl BlobPayload for Bson {
+    fn from_blob(data: &[u8]) -> Result<Self, ClaimCheckError> {
+        // BSON payloads are wrapped in a document so values of any type can be stored.
+        let mut wrapper: Document = bson::from_slice(data).map_err(|e| ClaimCheckError::Format(e.to_string()))?;
+        Ok(wrapper.remove("v").unwrap_or(Bson::Null))
+    }
+}
+
+// LazyPayload is the data of a loaded event, fetching checked payloads from
+// the blob store on first access.
+pub enum LazyPayload<T = Value> {
+    Inline(Option<T>),
+    Claimed {
+        key: String,
+        blobs: Arc<dyn BlobStore>,
+        value: OnceLock<T>,
+    },
+}
+
+impl<T: BlobPayload> LazyPayload<T> {
+    // Returns the blob key if the payload is stored in the blob store.
+    pub fn claim_check(&self) -> Option<&str> {
+        match self {
+            LazyPayload::Inline(_) => None,
+            LazyPayload::Claimed { key, .. } => Some(key),
+        }
+    }
+
+    pub fn value(&self) -> Result<Option<&T>, ClaimCheckError> {
+        match self {
+            LazyPayload::Inline(value) => Ok(value.as_ref()),
+            LazyPayload::Claimed { key, blobs, value } => {
+                if let Some(value) = value.get() {
+                    return Ok(Some(value));
+                }
+                let loaded = T::from_blob(&blobs.get(key)?)?;
+                Ok(Some(value.get_or_init(|| loaded)))
+            }
+        }
+    }
+}
+
+// ClaimCheck moves event payloads whose encoded size exceeds a limit into a
+// blob store, keeping only a reference in the encoded event. Used as the
+// PayloadCleanup of an event store, it deletes the blob of an event when the
+// event's data is deleted or redacted.
+pub struct ClaimCheck {
+    blobs: Arc<dyn BlobStore>,
+    limit: usize,
+}
+
+impl ClaimCheck {
+    pub fn new(blobs: Arc<dyn BlobStore>, limit: usize) -> Self {
+        ClaimCheck { blobs, limit }
+    }
+
+    // Checks in the payload of a JSON encoded event if it exceeds the limit.
+    pub fn check_in_json_event(&self, data: &[u8]) -> Result<Vec<u8>, ClaimCheckError> {
+        let mut envelope = json_envelope(data)?;
+        let Some(payload) = envelope.get(JSON_EVENT_PAYLOAD) else {
+            return Ok(data.to_vec());
+        };
+        let payload = serde_json::to_vec(payload).map_err(|e| ClaimCheckError::Format(e.to_string()))?;
+        if payload.len() <= self.limit {
+            return Ok(data.to_vec());
+        }
+
+        let target: EventTarget = serde_json::from_slice(data).map_err(|e| ClaimCheckError::Format(e.to_string()))?;
+        let key = blob_key(target.aggregate_id, target.version);
+        self.blobs.put(&key, &payload)?;
+        envelope.insert(JSON_EVENT_PAYLOAD.to_string(), json!({ CLAIM_CHECK_FIELD: key }));
+        serde_json::to_vec(&envelope).map_err(|e| ClaimCheckError::Format(e.to_string()))
+    }
+
+    // Loads the payload of a JSON encoded event without fetching checked blobs yet.
+    pub fn load_json_payload(&self, data: &[u8]) -> Result<LazyPayload, ClaimCheckError> {
+        let mut envelope = json_envelope(data)?;
+        let payload = envelope.remove(JSON_EVENT_PAYLOAD);
+        Ok(match payload.as_ref().and_then(json_claim_check) {
+            Some(key) => LazyPayload::Claimed {
+                key,
+                blobs: self.blobs.clone(),
+                value: OnceLock::new(),
+            },
+            None => LazyPayload::Inline(payload),
+        })
+    }
+
+    // Resolves a checked payload of a JSON encoded event, so the event codec can decode it.
+    pub fn resolve_json_event(&self, data: &[u8]) -> Result<Vec<u8>, ClaimCheckError> {
+        let mut envelope = json_envelope(data)?;
+        let Some(key) = envelope.get(JSON_EVENT_PAYLOAD).and_then(json_claim_check) else {
+            return Ok(data.to_vec());
+        };
+        let payload = Value::from_blob(&self.blobs.get(&key)?)?;
+        envelope.insert(JSON_EVENT_PAYLOAD.to_string(), payload);
+        serde_json::to_vec(&envelope).map_err(|e| ClaimCheckError::Format(e.to_string()))
+    }
+
+    // Redacts a JSON encoded event, removing its payload and deleting any checked blob.
+    pub fn redact_json_event(&self, data: &[u8]) -> Result<Vec<u8>, ClaimCheckError> {
+        let mut envelope = json_envelope(data)?;
+        if let Some(payload) = envelope.remove(JSON_EVENT_PAYLOAD) {
+            if let Some(key) = json_claim_check(&payload) {
+                self.blobs.delete(&key)?;
+            }
+        }
+        serde_json::to_vec(&envelope).map_err(|e| ClaimCheckError::Format(e.to_string()))
+    }
+
+    // Checks in the payload of a BSON encoded event if it exceeds the limit.
+    pub fn check_in_bson_event(&self, data: &[u8]) -> Result<Vec<u8>, ClaimCheckError> {
+        let mut envelope = bson_envelope(data)?;
+        let Some(payload) = envelope.get(BSON_EVENT_PAYLOAD) else {
+            return Ok(data.to_vec());
+        };
+        let payload = bson::to_vec(&bson::doc! { "v": payload.clone() })
+            .map_err(|e| ClaimCheckError::Format(e.to_string()))?;
+        if payload.len() <= self.limit {
+            return Ok(data.to_vec());
+        }
+
+        let target: EventTarget = bson::from_slice(data).map_err(|e| ClaimCheckError::Format(e.to_string()))?;
+        let key = blob_key(target.aggregate_id, target.version);
+        self.blobs.put(&key, &payload)?;
+        envelope.insert(BSON_EVENT_PAYLOAD, bson::doc! { CLAIM_CHECK_FIELD: key });
+        bson::to_vec(&envelope).map_err(|e| ClaimCheckError::Format(e.to_string()))
+    }
+
+    // Loads the payload of a BSON encoded event without fetching checked blobs yet.
+    pub fn load_bson_payload(&self, data: &[u8]) -> Result<LazyPayload<Bson>, ClaimCheckError> {
+        let mut envelope = bson_envelope(data)?;
+        let payload = envelope.remove(BSON_EVENT_PAYLOAD);
+        Ok(match payload.as_ref().and_then(bson_claim_check) {
+            Some(key) => LazyPayload::Claimed {
+                key,
+                blobs: self.blobs.clone(),
+                value: OnceLock::new(),
+            },
+            None => LazyPayload::Inline(payload),
+        })
+    }
+
+    // Resolves a checked payload of a BSON encoded event, so the event codec can decode it.
+    pub fn resolve_bson_event(&self, data: &[u8]) -> Result<Vec<u8>, ClaimCheckError> {
+        let mut envelope = bson_envelope(data)?;
+        let Some(key) = envelope.get(BSON_EVENT_PAYLOAD).and_then(bson_claim_check) else {
+            return Ok(data.to_vec());
+        };
+        envelope.insert(BSON_EVENT_PAYLOAD, Bson::from_blob(&self.blobs.get(&key)?)?);
+        bson::to_vec(&envelope).map_err(|e| ClaimCheckError::Format(e.to_string()))
+    }
+
+    // Redacts a BSON encoded event, removing its payload and deleting any checked blob.
+    pub fn redact_bson_event(&self, data: &[u8]) -> Result<Vec<u8>, ClaimCheckError> {
+        let mut envelope = bson_envelope(data)?;
+        if let Some(payload) = envelope.remove(BSON_EVENT_PAYLOAD) {
+            if let Some(key) = bson_claim_check(&payload) {
+                self.blobs.delete(&key)?;
+            }
+        }
+        bson::to_vec(&envelope).map_err(|e| ClaimCheckError::Format(e.to_string()))
+    }
+}
+
+impl PayloadCleanup for ClaimCheck {
+    fn payload_removed(&self, aggregate_id: Uuid, version: i32) -> Result<(), MaintenanceError> {
+        self.blobs
+            .delete(&blob_key(aggregate_id, version))
+            .map_err(|e| MaintenanceError::Store(e.to_string()))
+    }
+}
+
+fn json_envelope(data: &[u8]) -> Result<Map<String, Value>, ClaimCheckError> {
+    serde_json::from_slice(data).map_err(|e| ClaimCheckError::Format(e.to_string()))
+}
+
+fn bson_envelope(data: &[u8]) -> Result<Document, ClaimCheckError> {
+    bson::from_slice(data).map_err(|e| ClaimCheckError::Format(e.to_string()))
+}
+
+fn json_claim_check(payload: &Value) -> Option<String> {
+    let object = payload.as_object()?;
+    if object.len() != 1 {
+        return None;
+    }
+    object.get(CLAIM_CHECK_FIELD)?.as_str().map(str::to_string)
+}
+
+fn bson_claim_check(payload: &Bson) -> Option<String> {
+    let document = payload.as_document()?;
+    if document.len() != 1 {
+        return None;
+    }
+    document.get_str(CLAIM_CHECK_FIELD).ok().map(str::to_string)
+}
+
+#[cfg(all(test, feature = "json", feature = "bson"))]
+mod tests {
+    use super::*;
+    use crate::codec::bson::event::{Event as BsonEvent, EventCodec as BsonEventCodec};
+    use crate::codec::json::event::EventCodec as JsonEventCodec;
+    use chrono::Utc;
+    use std::collections::HashMap;
+
+    fn blob_store() -> (Arc<FileBlobStore>, PathBuf) {
+        let dir = std::env::temp_dir().join(format!("eshorizon-blobs-{}", Uuid::new_v4()));
+        (Arc::new(FileBlobStore::new(&dir).unwrap()), dir)
+    }
+
+    fn json_event(data: Value) -> Vec<u8> {
+        json_event_of(Uuid::new_v4(), 1, data)
+    }
+
+    fn json_event_of(aggregate_id: Uuid, version: i32, data: Value) -> Vec<u8> {
+        JsonEventCodec::marshal_event(
+            "Uploaded".to_string(),
+            Some(data),
+            Utc::now(),
+            "Document".to_string(),
+            aggregate_id,
+            version,
+            crate::upcast::DEFAULT_SCHEMA_VERSION,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_claim_check() {
+        let (blobs, dir) = blob_store();
+        let claim_check = ClaimCheck::new(blobs.clone(), 128);
+        let content = "x".repeat(1024);
+        let encoded = json_event(json!({ "content": content }));
+
+        let checked = claim_check.check_in_json_event(&encoded).unwrap();
+        assert!(checked.len() < encoded.len());
+
+        // The payload is only fetched when accessed.
+        let payload = claim_check.load_json_payload(&checked).unwrap();
+        let key = payload.claim_check().unwrap().to_string();
+        assert_eq!(payload.value().unwrap().unwrap()["content"], content);
+
+        let event = JsonEventCodec::unmarshal_event(&claim_check.resolve_json_event(&checked).unwrap()).unwrap();
+        assert_eq!(event.data.unwrap()["content"], content);
+
+        // Redacting the event garbage-collects its blob.
+        let redacted = claim_check.redact_json_event(&checked).unwrap();
+        assert!(JsonEventCodec::unmarshal_event(&redacted).unwrap().data.is_none());
+        assert!(matches!(blobs.get(&key), Err(ClaimCheckError::BlobNotFound(_))));
+
+        // Small payloads stay inline.
+        let small = json_event(json!({ "content": "x" }));
+        assert_eq!(claim_check.check_in_json_event(&small).unwrap(), small);
+        assert!(claim_check.load_json_payload(&small).unwrap().claim_check().is_none());
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_bson_claim_check() {
+        let (blobs, dir) = blob_store();
+        let claim_check = ClaimCheck::new(blobs, 128);
+        let event = BsonEvent::new(
+            "Uploaded".to_string(),
+            Some(Bson::String("x".repeat(1024))),
+            Utc::now(),
+            "Document".to_string(),
+            Uuid::new_v4(),
+            1,
+            HashMap::new(),
+            HashMap::new(),
+        );
+
+        let checked = claim_check
+            .check_in_bson_event(&BsonEventCodec::marshal_event(&event).unwrap())
+            .unwrap();
+        assert_ne!(BsonEventCodec::unmarshal_event(&checked).unwrap().data, event.data);
+
+        let payload = claim_check.load_bson_payload(&checked).unwrap();
+        assert_eq!(payload.claim_check(), Some(blob_key(event.aggregate_id, 1).as_str()));
+        assert_eq!(payload.value().unwrap(), event.data.as_ref());
+
+        let resolved = claim_check.resolve_bson_event(&checked).unwrap();
+        assert_eq!(BsonEventCodec::unmarshal_event(&resolved).unwrap().data, event.data);
+
+        claim_check.redact_bson_event(&checked).unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_maintenance_deletes_blobs() {
+        use crate::eventmaintenance::EventStoreMaintenance;
+        use crate::eventstore::memory::MemoryEventStore;
+        use crate::eventstore::testutil::event;
+        use crate::eventstore::EventStore;
+
+        let (blobs, dir) = blob_store();
+        let claim_check = Arc::new(ClaimCheck::new(blobs.clone(), 128));
+        let store = MemoryEventStore::new().with_payload_cleanup(claim_check.clone());
+        let id = Uuid::new_v4();
+        store.save(vec![event("Uploaded", id, 1), event("Uploaded", id, 2)], 0).await.unwrap();
+        for version in [1, 2] {
+            let encoded = json_event_of(id, version, json!({ "content": "x".repeat(1024) }));
+            claim_check.check_in_json_event(&encoded).unwrap();
+        }
+
+        store.delete_event_data(id, 1).await.unwrap();
+        assert!(matches!(blobs.get(&blob_key(id, 1)), Err(ClaimCheckError::BlobNotFound(_))));
+        assert!(blobs.get(&blob_key(id, 2)).is_ok());
+
+        store
+            .redact_event(id, 2, &|e: &dyn crate::eventstore::Event| event("Redacted", e.aggregate_id(), e.version()))
+            .await
+            .unwrap();
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 0);
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
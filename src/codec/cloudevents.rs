@@ -0,0 +1,282 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::envelope;
+use crate::codec_main::{self, CodecError};
+use crate::context::Context;
+use crate::upcast::UpcasterRegistry;
+
+// Version of the CloudEvents specification written by the codec.
+pub const SPEC_VERSION: &str = "1.0";
+
+// Default prefix of the source attribute, followed by the aggregate type.
+pub const DEFAULT_SOURCE_PREFIX: &str = "/eshorizon";
+
+// CloudEvent is the structured JSON form of a CloudEvents 1.0 event. Our
+// version, schema version, metadata and context are carried as extension
+// attributes, with the maps encoded as JSON strings since extensions must be
+// primitive values.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct CloudEvent {
+    pub specversion: String,
+    pub id: String,
+    pub source: String,
+    #[serde(rename = "type")]
+    pub event_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub subject: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub time: Option<DateTime<Utc>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub datacontenttype: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub aggregateversion: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub schemaversion: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub metadata: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub context: Option<String>,
+    // Any other extension attributes, kept so they survive a round trip.
+    #[serde(flatten)]
+    pub extensions: HashMap<String, Value>,
+}
+
+// EventCodec maps events to and from CloudEvents structured JSON. The source
+// is the configured prefix followed by the aggregate type, the subject is the
+// aggregate id and the id is derived from the aggregate id and version.
+// Events are upcast by the registered upcasters when decoded.
+pub struct EventCodec {
+    source_prefix: String,
+    upcasters: Arc<UpcasterRegistry>,
+}
+
+impl EventCodec {
+    pub fn new(source_prefix: &str) -> Self {
+        EventCodec {
+            source_prefix: source_prefix.trim_end_matches('/').to_string(),
+            upcasters: Arc::default(),
+        }
+    }
+
+    // Upcast decoded events with the given upcasters.
+    pub fn with_upcasters(mut self, upcasters: Arc<UpcasterRegistry>) -> Self {
+        self.upcasters = upcasters;
+        self
+    }
+
+    // Converts an event and its context into a CloudEvent.
+    pub fn to_cloud_event(&self, ctx: &Context, event: &dyn codec_main::Event) -> Result<CloudEvent, CodecError> {
+        let event = envelope::Event::from_event(ctx, event, &self.upcasters)?;
+        let data = event.data;
+        Ok(CloudEvent {
+            specversion: SPEC_VERSION.to_string(),
+            id: format!("{}/{}", event.aggregate_id, event.version),
+            source: format!("{}/{}", self.source_prefix, event.aggregate_type),
+            event_type: event.event_type,
+            subject: Some(event.aggregate_id.to_string()),
+            time: Some(event.timestamp),
+            datacontenttype: data.as_ref().map(|_| super::JSON_CONTENT_TYPE.to_string()),
+            data,
+            aggregateversion: Some(event.version),
+            schemaversion: Some(event.schema_version),
+            metadata: encode_map(event.metadata)?,
+            context: encode_map(event.context)?,
+            extensions: HashMap::new(),
+        })
+    }
+
+    // Converts a CloudEvent back into an event and restores its context.
+    pub fn from_cloud_event(
+        &self,
+        ctx: Context,
+        event: CloudEvent,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        if event.specversion != SPEC_VERSION {
+            return Err(CodecError::new(&format!("unsupported specversion {}", event.specversion)));
+        }
+        let aggregate_type = event
+            .source
+            .rsplit('/')
+            .next()
+            .filter(|aggregate_type| !aggregate_type.is_empty())
+            .ok_or_else(|| CodecError::new(&format!("no aggregate type in source {}", event.source)))?;
+        let aggregate_id = event
+            .subject
+            .as_deref()
+            .ok_or_else(|| CodecError::new("missing subject"))
+            .and_then(|subject| Uuid::parse_str(subject).map_err(|e| CodecError::new(&e.to_string())))?;
+
+        let envelope = envelope::Event {
+            event_type: event.event_type,
+            data: event.data,
+            timestamp: event.time.unwrap_or_else(Utc::now),
+            aggregate_type: aggregate_type.to_string(),
+            aggregate_id,
+            version: event.aggregateversion.unwrap_or_default(),
+            schema_version: event.schemaversion.unwrap_or(crate::upcast::DEFAULT_SCHEMA_VERSION),
+            metadata: decode_map(event.metadata)?,
+            context: decode_map(event.context)?,
+        };
+        envelope.into_event(ctx, &self.upcasters)
+    }
+}
+
+impl Default for EventCodec {
+    fn default() -> Self {
+        EventCodec::new(DEFAULT_SOURCE_PREFIX)
+    }
+}
+
+fn encode_map(values: HashMap<String, Value>) -> Result<Option<String>, CodecError> {
+    if values.is_empty() {
+        return Ok(None);
+    }
+    serde_json::to_string(&values)
+        .map(Some)
+        .map_err(|e| CodecError::new(&e.to_string()))
+}
+
+fn decode_map(values: Option<String>) -> Result<HashMap<String, Value>, CodecError> {
+    match values {
+        Some(values) => serde_json::from_str(&values).map_err(|e| CodecError::new(&e.to_string())),
+        None => Ok(HashMap::new()),
+    }
+}
+
+#[async_trait]
+impl codec_main::EventCodec for EventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let event = self.to_cloud_event(ctx, event.as_ref())?;
+        serde_json::to_vec(&event).map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let event: CloudEvent = serde_json::from_slice(&data).map_err(|e| CodecError::new(&e.to_string()))?;
+        self.from_cloud_event(ctx, event)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec_main::{BasicEvent, EventCodec as _};
+    use std::any::Any;
+    use std::time::SystemTime;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct OrderPlaced {
+        total: u32,
+    }
+
+    impl crate::event::EventData for OrderPlaced {}
+
+    #[tokio::test]
+    async fn test_event_codec() {
+        crate::event::register_event_data(
+            "CloudEventsOrderPlaced".to_string(),
+            Box::new(|| Box::new(OrderPlaced { total: 0 })),
+        );
+
+        let id = Uuid::new_v4();
+        let event = BasicEvent::new(
+            "CloudEventsOrderPlaced".to_string(),
+            Some(Box::new(OrderPlaced { total: 12 })),
+            SystemTime::now(),
+            "Order".to_string(),
+            id,
+            3,
+        )
+        .with_metadata(HashMap::from([("source".to_string(), serde_json::json!("test"))]));
+
+        let codec = EventCodec::new("/shop/");
+        let data = codec.marshal_event(&Context::new(), Arc::new(event)).await.unwrap();
+
+        let encoded: Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(encoded["specversion"], "1.0");
+        assert_eq!(encoded["type"], "CloudEventsOrderPlaced");
+        assert_eq!(encoded["source"], "/shop/Order");
+        assert_eq!(encoded["subject"], id.to_string());
+        assert_eq!(encoded["id"], format!("{}/3", id));
+        assert_eq!(encoded["data"]["total"], 12);
+        assert_eq!(encoded["aggregateversion"], 3);
+
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!(decoded.aggregate_type(), "Order");
+        assert_eq!((decoded.aggregate_id(), decoded.version()), (id, 3));
+        assert_eq!(decoded.metadata()["source"], "test");
+        let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<OrderPlaced>().unwrap();
+        assert_eq!(data.total, 12);
+    }
+
+    #[tokio::test]
+    async fn test_event_codec_upcasts() {
+        crate::event::register_event_data(
+            "CloudEventsOrderTotaled".to_string(),
+            Box::new(|| Box::new(OrderPlaced { total: 0 })),
+        );
+        let upcasters = Arc::new(UpcasterRegistry::new());
+        upcasters.register_fn("CloudEventsOrderTotaled".to_string(), 1, |data| {
+            Ok(serde_json::json!({ "total": data["amount"] }))
+        });
+        upcasters.set_schema_version("CloudEventsOrderTotaled".to_string(), 2);
+        let codec = EventCodec::default().with_upcasters(upcasters);
+
+        // Events without a schema version are upcast from the first one.
+        let id = Uuid::new_v4();
+        let data = serde_json::to_vec(&serde_json::json!({
+            "specversion": "1.0",
+            "id": format!("{}/1", id),
+            "source": "/eshorizon/Order",
+            "type": "CloudEventsOrderTotaled",
+            "subject": id.to_string(),
+            "data": {"amount": 7},
+        }))
+        .unwrap();
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        let total = (decoded.data().unwrap() as &dyn Any).downcast_ref::<OrderPlaced>().unwrap().total;
+        assert_eq!(total, 7);
+
+        // New events are written at the current schema version.
+        let data = codec.marshal_event(&Context::new(), decoded).await.unwrap();
+        let encoded: Value = serde_json::from_slice(&data).unwrap();
+        assert_eq!(encoded["schemaversion"], 2);
+        assert!(codec.unmarshal_event(Context::new(), data).await.is_ok());
+    }
+
+    #[tokio::test]
+    async fn test_unmarshal_foreign_event() {
+        let id = Uuid::new_v4();
+        let data = serde_json::to_vec(&serde_json::json!({
+            "specversion": "1.0",
+            "id": "A234-1234-1234",
+            "source": "https://example.com/Invoice",
+            "type": "CloudEventsInvoiceSent",
+            "subject": id.to_string(),
+            "comexampleextension": "value",
+        }))
+        .unwrap();
+
+        let (decoded, _) = EventCodec::default().unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!(decoded.aggregate_type(), "Invoice");
+        assert_eq!(decoded.aggregate_id(), id);
+        assert!(decoded.data().is_none());
+
+        let data = serde_json::to_vec(&serde_json::json!({
+            "specversion": "0.3", "id": "1", "source": "/Invoice", "type": "CloudEventsInvoiceSent",
+        }))
+        .unwrap();
+        assert!(EventCodec::default().unmarshal_event(Context::new(), data).await.is_err());
+    }
+}
@@ -0,0 +1,480 @@
+use std::io::{Read, Write};
+use std::sync::Arc;
+use async_trait::async_trait;
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use mongodb::bson::{self, spec::BinarySubtype, Binary, Bson, Document};
+use serde_json::{Map, Value};
+use thiserror::Error;
+
+use super::EnvelopeFormat;
+use crate::codec_main::{self, CodecError};
+use crate::context::Context;
+
+// Envelope field recording the algorithm used for a compressed payload.
+const COMPRESSION_FIELD: &str = "compression";
+
+// Payload fields of the JSON and BSON envelopes.
+const JSON_EVENT_PAYLOAD: &str = "raw_data";
+const BSON_EVENT_PAYLOAD: &str = "data";
+const COMMAND_PAYLOAD: &str = "command";
+
+// Errors related to compressing payloads.
+#[derive(Error, Debug)]
+pub enum CompressionError {
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+
+    #[error("unknown compression algorithm {0}")]
+    UnknownAlgorithm(String),
+
+    #[error("invalid envelope: {0}")]
+    Format(String),
+}
+
+// Supported compression algorithms.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompressionAlgorithm {
+    Gzip,
+    Zstd,
+}
+
+impl CompressionAlgorithm {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            CompressionAlgorithm::Gzip => "gzip",
+            CompressionAlgorithm::Zstd => "zstd",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Result<Self, CompressionError> {
+        match name {
+            "gzip" => Ok(CompressionAlgorithm::Gzip),
+            "zstd" => Ok(CompressionAlgorithm::Zstd),
+            _ => Err(CompressionError::UnknownAlgorithm(name.to_string())),
+        }
+    }
+
+    pub fn compress(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        match self {
+            CompressionAlgorithm::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+                encoder.write_all(data)?;
+                Ok(encoder.finish()?)
+            }
+            CompressionAlgorithm::Zstd => Ok(zstd::encode_all(data, 0)?),
+        }
+    }
+
+    pub fn decompress(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        match self {
+            CompressionAlgorithm::Gzip => {
+                let mut decompressed = Vec::new();
+                GzDecoder::new(data).read_to_end(&mut decompressed)?;
+                Ok(decompressed)
+            }
+            CompressionAlgorithm::Zstd => Ok(zstd::decode_all(data)?),
+        }
+    }
+}
+
+// Compressor compresses the payloads of JSON and BSON encoded events and
+// commands. Payloads whose encoded size exceeds the threshold are compressed and
+// the algorithm is recorded in the envelope; envelopes without it are passed
+// through as is, so uncompressed data that is already stored stays readable.
+// CompressedEventCodec and CompressedCommandCodec apply it around a codec.
+#[derive(Debug, Clone, Copy)]
+pub struct Compressor {
+    algorithm: CompressionAlgorithm,
+    threshold: usize,
+}
+
+impl Compressor {
+    pub fn new(algorithm: CompressionAlgorithm, threshold: usize) -> Self {
+        Compressor { algorithm, threshold }
+    }
+
+    // Compresses the payload of a JSON encoded event.
+    pub fn compress_json_event(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        self.compress_json(data, JSON_EVENT_PAYLOAD)
+    }
+
+    // Decompresses the payload of a JSON encoded event.
+    pub fn decompress_json_event(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        decompress_json(data, JSON_EVENT_PAYLOAD)
+    }
+
+    // Compresses the payload of a JSON encoded command.
+    pub fn compress_json_command(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        self.compress_json(data, COMMAND_PAYLOAD)
+    }
+
+    // Decompresses the payload of a JSON encoded command.
+    pub fn decompress_json_command(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        decompress_json(data, COMMAND_PAYLOAD)
+    }
+
+    // Compresses the payload of a BSON encoded event.
+    pub fn compress_bson_event(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        self.compress_bson(data, BSON_EVENT_PAYLOAD)
+    }
+
+    // Decompresses the payload of a BSON encoded event.
+    pub fn decompress_bson_event(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        decompress_bson(data, BSON_EVENT_PAYLOAD)
+    }
+
+    // Compresses the payload of a BSON encoded command.
+    pub fn compress_bson_command(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        self.compress_bson(data, COMMAND_PAYLOAD)
+    }
+
+    // Decompresses the payload of a BSON encoded command.
+    pub fn decompress_bson_command(&self, data: &[u8]) -> Result<Vec<u8>, CompressionError> {
+        decompress_bson(data, COMMAND_PAYLOAD)
+    }
+
+    fn compress_json(&self, data: &[u8], field: &str) -> Result<Vec<u8>, CompressionError> {
+        let mut envelope: Map<String, Value> =
+            serde_json::from_slice(data).map_err(|e| CompressionError::Format(e.to_string()))?;
+        let Some(payload) = envelope.get(field) else {
+            return Ok(data.to_vec());
+        };
+        let payload = serde_json::to_vec(payload).map_err(|e| CompressionError::Format(e.to_string()))?;
+        if payload.len() <= self.threshold {
+            return Ok(data.to_vec());
+        }
+
+        let compressed = self.algorithm.compress(&payload)?;
+        envelope.insert(field.to_string(), Value::String(BASE64.encode(compressed)));
+        envelope.insert(COMPRESSION_FIELD.to_string(), Value::String(self.algorithm.as_str().to_string()));
+        serde_json::to_vec(&envelope).map_err(|e| CompressionError::Format(e.to_string()))
+    }
+
+    fn compress_bson(&self, data: &[u8], field: &str) -> Result<Vec<u8>, CompressionError> {
+        let mut envelope: Document = bson::from_slice(data).map_err(|e| CompressionError::Format(e.to_string()))?;
+        let Some(payload) = envelope.get(field) else {
+            return Ok(data.to_vec());
+        };
+        // Wrap the payload in a document so values of any type can be encoded.
+        let payload = bson::to_vec(&bson::doc! { "v": payload.clone() })
+            .map_err(|e| CompressionError::Format(e.to_string()))?;
+        if payload.len() <= self.threshold {
+            return Ok(data.to_vec());
+        }
+
+        let compressed = self.algorithm.compress(&payload)?;
+        envelope.insert(
+            field,
+            Bson::Binary(Binary {
+                subtype: BinarySubtype::Generic,
+                bytes: compressed,
+            }),
+        );
+        envelope.insert(COMPRESSION_FIELD, self.algorithm.as_str());
+        bson::to_vec(&envelope).map_err(|e| CompressionError::Format(e.to_string()))
+    }
+}
+
+// CompressedEventCodec decorates an event codec, compressing the payloads of the
+// events it encodes and decompressing them before they are decoded.
+pub struct CompressedEventCodec {
+    compressor: Compressor,
+    inner: Arc<dyn codec_main::EventCodec>,
+    format: EnvelopeFormat,
+}
+
+impl CompressedEventCodec {
+    // The format must be the envelope format written by the inner codec.
+    pub fn new(compressor: Compressor, inner: Arc<dyn codec_main::EventCodec>, format: EnvelopeFormat) -> Self {
+        CompressedEventCodec { compressor, inner, format }
+    }
+}
+
+#[async_trait]
+impl codec_main::EventCodec for CompressedEventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let data = self.inner.marshal_event(ctx, event).await?;
+        let compressed = match self.format {
+            EnvelopeFormat::Json => self.compressor.compress_json_event(&data),
+            #[cfg(feature = "bson")]
+            EnvelopeFormat::Bson => self.compressor.compress_bson_event(&data),
+        };
+        compressed.map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let decompressed = match self.format {
+            EnvelopeFormat::Json => self.compressor.decompress_json_event(&data),
+            #[cfg(feature = "bson")]
+            EnvelopeFormat::Bson => self.compressor.decompress_bson_event(&data),
+        };
+        let data = decompressed.map_err(|e| CodecError::new(&e.to_string()))?;
+        self.inner.unmarshal_event(ctx, data).await
+    }
+}
+
+// CompressedCommandCodec decorates a command codec, compressing the payloads of
+// the commands it encodes and decompressing them before they are decoded.
+pub struct CompressedCommandCodec {
+    compressor: Compressor,
+    inner: Arc<dyn codec_main::CommandCodec>,
+    format: EnvelopeFormat,
+}
+
+impl CompressedCommandCodec {
+    // The format must be the envelope format written by the inner codec.
+    pub fn new(compressor: Compressor, inner: Arc<dyn codec_main::CommandCodec>, format: EnvelopeFormat) -> Self {
+        CompressedCommandCodec { compressor, inner, format }
+    }
+}
+
+#[async_trait]
+impl codec_main::CommandCodec for CompressedCommandCodec {
+    async fn marshal_command(
+        &self,
+        ctx: &Context,
+        command: Arc<dyn codec_main::Command>,
+    ) -> Result<Vec<u8>, CodecError> {
+        let data = self.inner.marshal_command(ctx, command).await?;
+        let compressed = match self.format {
+            EnvelopeFormat::Json => self.compressor.compress_json_command(&data),
+            #[cfg(feature = "bson")]
+            EnvelopeFormat::Bson => self.compressor.compress_bson_command(&data),
+        };
+        compressed.map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_command(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Command>, Context), CodecError> {
+        let decompressed = match self.format {
+            EnvelopeFormat::Json => self.compressor.decompress_json_command(&data),
+            #[cfg(feature = "bson")]
+            EnvelopeFormat::Bson => self.compressor.decompress_bson_command(&data),
+        };
+        let data = decompressed.map_err(|e| CodecError::new(&e.to_string()))?;
+        self.inner.unmarshal_command(ctx, data).await
+    }
+}
+
+fn decompress_json(data: &[u8], field: &str) -> Result<Vec<u8>, CompressionError> {
+    let mut envelope: Map<String, Value> =
+        serde_json::from_slice(data).map_err(|e| CompressionError::Format(e.to_string()))?;
+    let Some(algorithm) = envelope.remove(COMPRESSION_FIELD) else {
+        return Ok(data.to_vec());
+    };
+    let algorithm = CompressionAlgorithm::from_name(algorithm.as_str().unwrap_or_default())?;
+
+    let payload = envelope
+        .get(field)
+        .and_then(Value::as_str)
+        .ok_or_else(|| CompressionError::Format(format!("missing compressed {}", field)))?;
+    let compressed = BASE64.decode(payload).map_err(|e| CompressionError::Format(e.to_string()))?;
+    let payload: Value = serde_json::from_slice(&algorithm.decompress(&compressed)?)
+        .map_err(|e| CompressionError::Format(e.to_string()))?;
+    envelope.insert(field.to_string(), payload);
+    serde_json::to_vec(&envelope).map_err(|e| CompressionError::Format(e.to_string()))
+}
+
+fn decompress_bson(data: &[u8], field: &str) -> Result<Vec<u8>, CompressionError> {
+    let mut envelope: Document = bson::from_slice(data).map_err(|e| CompressionError::Format(e.to_string()))?;
+    let Some(algorithm) = envelope.remove(COMPRESSION_FIELD) else {
+        return Ok(data.to_vec());
+    };
+    let algorithm = CompressionAlgorithm::from_name(algorithm.as_str().unwrap_or_default())?;
+
+    let compressed = match envelope.get(field) {
+        Some(Bson::Binary(binary)) => &binary.bytes,
+        _ => return Err(CompressionError::Format(format!("missing compressed {}", field))),
+    };
+    let mut wrapper: Document = bson::from_slice(&algorithm.decompress(compressed)?)
+        .map_err(|e| CompressionError::Format(e.to_string()))?;
+    let payload = wrapper.remove("v").unwrap_or(Bson::Null);
+    envelope.insert(field, payload);
+    bson::to_vec(&envelope).map_err(|e| CompressionError::Format(e.to_string()))
+}
+
+#[cfg(all(test, feature = "json", feature = "bson"))]
+mod tests {
+    use super::*;
+    use crate::codec::bson::command::{Command as BsonCommand, CommandCodec as BsonCommandCodec};
+    use crate::codec::bson::event::{Event as BsonEvent, EventCodec as BsonEventCodec};
+    use crate::codec::json::event::EventCodec as JsonEventCodec;
+    use chrono::Utc;
+    use serde_json::json;
+    use std::collections::HashMap;
+    use uuid::Uuid;
+
+    fn large_text() -> String {
+        "lorem ipsum ".repeat(200)
+    }
+
+    fn json_event(text: &str) -> Vec<u8> {
+        JsonEventCodec::marshal_event(
+            "Written".to_string(),
+            Some(json!({ "text": text })),
+            Utc::now(),
+            "Document".to_string(),
+            Uuid::new_v4(),
+            1,
+            crate::upcast::DEFAULT_SCHEMA_VERSION,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn test_json_event_compression() {
+        for algorithm in [CompressionAlgorithm::Gzip, CompressionAlgorithm::Zstd] {
+            let compressor = Compressor::new(algorithm, 256);
+            let encoded = json_event(&large_text());
+
+            let compressed = compressor.compress_json_event(&encoded).unwrap();
+            assert!(compressed.len() < encoded.len());
+            let envelope: Value = serde_json::from_slice(&compressed).unwrap();
+            assert_eq!(envelope["compression"], algorithm.as_str());
+
+            let decompressed = compressor.decompress_json_event(&compressed).unwrap();
+            let event = JsonEventCodec::unmarshal_event(&decompressed).unwrap();
+            assert_eq!(event.data.unwrap()["text"], large_text());
+        }
+    }
+
+    #[test]
+    fn test_small_and_uncompressed_payloads() {
+        let compressor = Compressor::new(CompressionAlgorithm::Gzip, 256);
+
+        // Small payloads are left alone.
+        let encoded = json_event("short");
+        assert_eq!(compressor.compress_json_event(&encoded).unwrap(), encoded);
+
+        // Data stored without compression decodes unchanged.
+        assert_eq!(compressor.decompress_json_event(&encoded).unwrap(), encoded);
+    }
+
+    #[test]
+    fn test_bson_compression() {
+        let compressor = Compressor::new(CompressionAlgorithm::Zstd, 256);
+        let event = BsonEvent::new(
+            "Written".to_string(),
+            Some(Bson::String(large_text())),
+            Utc::now(),
+            "Document".to_string(),
+            Uuid::new_v4(),
+            1,
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let compressed = compressor
+            .compress_bson_event(&BsonEventCodec::marshal_event(&event).unwrap())
+            .unwrap();
+        let decompressed = compressor.decompress_bson_event(&compressed).unwrap();
+        assert_eq!(BsonEventCodec::unmarshal_event(&decompressed).unwrap().data, event.data);
+
+        let command = BsonCommand {
+            command_type: "Write".to_string(),
+            command: Bson::Document(bson::doc! { "text": large_text() }),
+            context: HashMap::new(),
+        };
+        let compressed = compressor
+            .compress_bson_command(&BsonCommandCodec::marshal_command(&command).unwrap())
+            .unwrap();
+        let decompressed = compressor.decompress_bson_command(&compressed).unwrap();
+        assert_eq!(BsonCommandCodec::unmarshal_command(&decompressed).unwrap(), command);
+    }
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct Written {
+        text: String,
+    }
+
+    impl crate::event::EventData for Written {}
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct Write {
+        id: Uuid,
+        text: String,
+    }
+
+    impl crate::command_main::Command for Write {
+        fn aggregate_id(&self) -> Uuid {
+            self.id
+        }
+
+        fn aggregate_type(&self) -> String {
+            "Document".to_string()
+        }
+
+        fn command_type(&self) -> String {
+            "CompressionWrite".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_compressed_codecs() {
+        use crate::codec::bson::command::CommandCodec as BsonCommandCodec;
+        use crate::codec::json::command::CommandCodec as JsonCommandCodec;
+        use crate::codec_main::{BasicEvent, CommandCodec as _, EventCodec as _};
+        use std::any::Any;
+
+        crate::event::register_event_data(
+            "CompressionWritten".to_string(),
+            Box::new(|| Box::new(Written { text: String::new() })),
+        );
+        crate::command_main::register_command(
+            "CompressionWrite".to_string(),
+            Box::new(|| Box::new(Write { id: Uuid::nil(), text: String::new() })),
+        );
+        let compressor = Compressor::new(CompressionAlgorithm::Gzip, 256);
+
+        for (events, commands, format) in [
+            (
+                Arc::new(JsonEventCodec::default()) as Arc<dyn codec_main::EventCodec>,
+                Arc::new(JsonCommandCodec) as Arc<dyn codec_main::CommandCodec>,
+                EnvelopeFormat::Json,
+            ),
+            (Arc::new(BsonEventCodec::default()), Arc::new(BsonCommandCodec), EnvelopeFormat::Bson),
+        ] {
+            let uncompressed = events.clone();
+            let codec = CompressedEventCodec::new(compressor, events, format);
+            let event = Arc::new(BasicEvent::new(
+                "CompressionWritten".to_string(),
+                Some(Box::new(Written { text: large_text() })),
+                std::time::SystemTime::now(),
+                "Document".to_string(),
+                Uuid::new_v4(),
+                1,
+            ));
+            let encoded = codec.marshal_event(&Context::new(), event.clone()).await.unwrap();
+            let plain = uncompressed.marshal_event(&Context::new(), event).await.unwrap();
+            assert!(encoded.len() < plain.len());
+
+            let (decoded, _) = codec.unmarshal_event(Context::new(), encoded).await.unwrap();
+            let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<Written>().unwrap();
+            assert_eq!(data.text, large_text());
+
+            // Events stored before compression was enabled still decode.
+            assert!(codec.unmarshal_event(Context::new(), plain).await.is_ok());
+
+            let codec = CompressedCommandCodec::new(compressor, commands, format);
+            let id = Uuid::new_v4();
+            let encoded = codec
+                .marshal_command(&Context::new(), Arc::new(Write { id, text: large_text() }))
+                .await
+                .unwrap();
+            let (decoded, _) = codec.unmarshal_command(Context::new(), encoded).await.unwrap();
+            let command = (decoded.as_ref() as &dyn Any).downcast_ref::<Write>().unwrap();
+            assert_eq!((command.id, command.text.as_str()), (id, large_text().as_str()));
+        }
+    }
+}
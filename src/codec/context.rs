@@ -0,0 +1,41 @@
+use serde_json::Value;
+use std::collections::HashMap;
+
+use crate::context::{self, CloneableAny, Context};
+
+// Marshal a context through the registered context marshalers into JSON values,
+// used by the codecs whose envelopes hold JSON compatible values.
+pub fn marshal_context(ctx: &Context) -> Result<HashMap<String, Value>, String> {
+    context::marshal_context(ctx)?
+        .into_iter()
+        .map(|(key, value)| {
+            let value = if let Some(value) = value.downcast_ref::<i32>() {
+                Value::from(*value)
+            } else if let Some(value) = value.downcast_ref::<String>() {
+                Value::from(value.clone())
+            } else {
+                return Err(format!("unsupported context value for {}", key));
+            };
+            Ok((key, value))
+        })
+        .collect()
+}
+
+// Unmarshal JSON context values through the registered context unmarshalers.
+pub fn unmarshal_context(ctx: &mut Context, values: HashMap<String, Value>) -> Result<(), String> {
+    let values = values
+        .into_iter()
+        .map(|(key, value)| {
+            let value = match &value {
+                Value::String(value) => CloneableAny::new(value.clone()),
+                Value::Number(number) => match number.as_i64().and_then(|n| i32::try_from(n).ok()) {
+                    Some(value) => CloneableAny::new(value),
+                    None => return Err(format!("unsupported context value for {}", key)),
+                },
+                _ => return Err(format!("unsupported context value for {}", key)),
+            };
+            Ok((key, value))
+        })
+        .collect::<Result<HashMap<_, _>, _>>()?;
+    context::unmarshal_context(ctx, values)
+}
@@ -0,0 +1,432 @@
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use async_trait::async_trait;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, OsRng};
+use aes_gcm::{Aes256Gcm, Nonce};
+use base64::engine::general_purpose::STANDARD as BASE64;
+use base64::Engine;
+#[cfg(feature = "bson")]
+use mongodb::bson::Bson;
+use serde_json::{json, Map, Value};
+use thiserror::Error;
+use uuid::Uuid;
+
+#[cfg(feature = "bson")]
+use super::bson::event::EventCodec as BsonEventCodec;
+use super::EnvelopeFormat;
+use crate::codec_main::{self, CodecError};
+use crate::context::Context;
+
+const ENCRYPTED_FIELD: &str = "$encrypted";
+const SUBJECT_FIELD: &str = "$subject";
+const NONCE_SIZE: usize = 12;
+
+// Errors related to encrypting and decrypting personal data.
+#[derive(Error, Debug)]
+pub enum CryptoError {
+    #[error("key store error: {0}")]
+    KeyStore(String),
+
+    #[error("could not encrypt field {0}")]
+    Encrypt(String),
+
+    #[error("could not decrypt field {0}")]
+    Decrypt(String),
+
+    #[error("invalid event format: {0}")]
+    Format(String),
+}
+
+// KeyStore holds one encryption key per data subject. Deleting the key of a
+// subject makes its encrypted data unreadable, which "forgets" the subject.
+pub trait KeyStore: Send + Sync {
+    // Returns the key of a subject, creating one if it doesn't exist yet.
+    fn get_or_create_key(&self, subject: &str) -> Result<Vec<u8>, CryptoError>;
+
+    // Returns the key of a subject, None if it was never created or deleted.
+    fn get_key(&self, subject: &str) -> Result<Option<Vec<u8>>, CryptoError>;
+
+    fn delete_key(&self, subject: &str) -> Result<(), CryptoError>;
+}
+
+// MemoryKeyStore keeps subject keys in memory.
+#[derive(Default)]
+pub struct MemoryKeyStore {
+    keys: RwLock<HashMap<String, Vec<u8>>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    fn get_or_create_key(&self, subject: &str) -> Result<Vec<u8>, CryptoError> {
+        let mut keys = self.keys.write().unwrap();
+        let key = keys
+            .entry(subject.to_string())
+            .or_insert_with(|| Aes256Gcm::generate_key(OsRng).to_vec());
+        Ok(key.clone())
+    }
+
+    fn get_key(&self, subject: &str) -> Result<Option<Vec<u8>>, CryptoError> {
+        Ok(self.keys.read().unwrap().get(subject).cloned())
+    }
+
+    fn delete_key(&self, subject: &str) -> Result<(), CryptoError> {
+        self.keys.write().unwrap().remove(subject);
+        Ok(())
+    }
+}
+
+// The personal data fields of an event type and where to find their subject.
+struct PersonalData {
+    // Data field holding the subject ID, the aggregate ID is used if None.
+    subject_field: Option<String>,
+    fields: Vec<String>,
+}
+
+// CryptoShredder encrypts the marked fields of event data with the key of their
+// subject before they are stored and decrypts them on load. Encrypted fields are
+// stored as objects holding the subject and the nonce-prefixed ciphertext, so
+// stored events never have to be rewritten when a subject is forgotten. Fields
+// of forgotten subjects decode as null, so the personal data fields of event
+// data types must be Options.
+pub struct CryptoShredder {
+    keys: Arc<dyn KeyStore>,
+    personal_data: RwLock<HashMap<String, PersonalData>>,
+}
+
+impl CryptoShredder {
+    pub fn new(keys: Arc<dyn KeyStore>) -> Self {
+        CryptoShredder {
+            keys,
+            personal_data: RwLock::new(HashMap::new()),
+        }
+    }
+
+    // Marks fields of an event type's data as personal data.
+    pub fn register_personal_data(&self, event_type: String, subject_field: Option<String>, fields: Vec<String>) {
+        let mut personal_data = self.personal_data.write().unwrap();
+        if personal_data.contains_key(&event_type) {
+            panic!("registering duplicate personal data for {}", event_type);
+        }
+        personal_data.insert(event_type, PersonalData { subject_field, fields });
+    }
+
+    // Forget deletes the key of a subject, redacting all of its encrypted data.
+    pub fn forget(&self, subject: &str) -> Result<(), CryptoError> {
+        self.keys.delete_key(subject)
+    }
+
+    // Encrypts the marked fields of event data in place.
+    pub fn encrypt_data(&self, event_type: &str, aggregate_id: Uuid, data: &mut Value) -> Result<(), CryptoError> {
+        let personal_data = self.personal_data.read().unwrap();
+        let (Some(spec), Some(object)) = (personal_data.get(event_type), data.as_object_mut()) else {
+            return Ok(());
+        };
+
+        let subject = match &spec.subject_field {
+            Some(field) => match object.get(field) {
+                Some(Value::String(subject)) => subject.clone(),
+                Some(value) => value.to_string(),
+                None => return Err(CryptoError::Format(format!("missing subject field {}", field))),
+            },
+            None => aggregate_id.to_string(),
+        };
+        let key = self.keys.get_or_create_key(&subject)?;
+        let cipher = Aes256Gcm::new_from_slice(&key).map_err(|e| CryptoError::KeyStore(e.to_string()))?;
+
+        for field in &spec.fields {
+            let Some(value) = object.get_mut(field) else {
+                continue;
+            };
+            let plaintext = serde_json::to_vec(value).map_err(|_| CryptoError::Encrypt(field.clone()))?;
+            let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+            let mut ciphertext = nonce.to_vec();
+            ciphertext.extend(
+                cipher
+                    .encrypt(&nonce, plaintext.as_slice())
+                    .map_err(|_| CryptoError::Encrypt(field.clone()))?,
+            );
+            *value = json!({ SUBJECT_FIELD: subject, ENCRYPTED_FIELD: BASE64.encode(ciphertext) });
+        }
+        Ok(())
+    }
+
+    // Decrypts all encrypted fields of event data in place. Fields of forgotten
+    // subjects are set to null.
+    pub fn decrypt_data(&self, data: &mut Value) -> Result<(), CryptoError> {
+        let Some(object) = data.as_object_mut() else {
+            return Ok(());
+        };
+        for (field, value) in object.iter_mut() {
+            let Some((subject, ciphertext)) = encrypted_field(value) else {
+                continue;
+            };
+            let Some(key) = self.keys.get_key(&subject)? else {
+                *value = Value::Null;
+                continue;
+            };
+
+            let cipher = Aes256Gcm::new_from_slice(&key).map_err(|e| CryptoError::KeyStore(e.to_string()))?;
+            let ciphertext = BASE64.decode(ciphertext).map_err(|_| CryptoError::Decrypt(field.clone()))?;
+            if ciphertext.len() < NONCE_SIZE {
+                return Err(CryptoError::Decrypt(field.clone()));
+            }
+            let (nonce, ciphertext) = ciphertext.split_at(NONCE_SIZE);
+            let plaintext = cipher
+                .decrypt(Nonce::from_slice(nonce), ciphertext)
+                .map_err(|_| CryptoError::Decrypt(field.clone()))?;
+            *value = serde_json::from_slice(&plaintext).map_err(|_| CryptoError::Decrypt(field.clone()))?;
+        }
+        Ok(())
+    }
+
+    // Encrypts the personal data of a JSON encoded event.
+    pub fn encrypt_json(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut event: Map<String, Value> =
+            serde_json::from_slice(data).map_err(|e| CryptoError::Format(e.to_string()))?;
+        let event_type = event.get("event_type").and_then(Value::as_str).unwrap_or_default().to_string();
+        let aggregate_id = event
+            .get("aggregate_id")
+            .and_then(Value::as_str)
+            .and_then(|id| Uuid::parse_str(id).ok())
+            .ok_or_else(|| CryptoError::Format("missing aggregate ID".to_string()))?;
+        if let Some(data) = event.get_mut("raw_data") {
+            self.encrypt_data(&event_type, aggregate_id, data)?;
+        }
+        serde_json::to_vec(&event).map_err(|e| CryptoError::Format(e.to_string()))
+    }
+
+    // Decrypts the personal data of a JSON encoded event.
+    pub fn decrypt_json(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut event: Map<String, Value> =
+            serde_json::from_slice(data).map_err(|e| CryptoError::Format(e.to_string()))?;
+        if let Some(data) = event.get_mut("raw_data") {
+            self.decrypt_data(data)?;
+        }
+        serde_json::to_vec(&event).map_err(|e| CryptoError::Format(e.to_string()))
+    }
+
+    // Encrypts the personal data of a BSON encoded event.
+    #[cfg(feature = "bson")]
+    pub fn encrypt_bson(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut event = BsonEventCodec::unmarshal_event(data).map_err(|e| CryptoError::Format(e.to_string()))?;
+        if let Some(data) = event.data.take() {
+            let mut value = data.into_relaxed_extjson();
+            self.encrypt_data(&event.event_type, event.aggregate_id, &mut value)?;
+            event.data = Some(Bson::try_from(value).map_err(|e| CryptoError::Format(e.to_string()))?);
+        }
+        BsonEventCodec::marshal_event(&event).map_err(|e| CryptoError::Format(e.to_string()))
+    }
+
+    // Decrypts the personal data of a BSON encoded event.
+    #[cfg(feature = "bson")]
+    pub fn decrypt_bson(&self, data: &[u8]) -> Result<Vec<u8>, CryptoError> {
+        let mut event = BsonEventCodec::unmarshal_event(data).map_err(|e| CryptoError::Format(e.to_string()))?;
+        if let Some(data) = event.data.take() {
+            let mut value = data.into_relaxed_extjson();
+            self.decrypt_data(&mut value)?;
+            event.data = Some(Bson::try_from(value).map_err(|e| CryptoError::Format(e.to_string()))?);
+        }
+        BsonEventCodec::marshal_event(&event).map_err(|e| CryptoError::Format(e.to_string()))
+    }
+}
+
+// EncryptedEventCodec decorates an event codec, encrypting the personal data of
+// events after they are encoded and decrypting it before they are decoded.
+pub struct EncryptedEventCodec {
+    shredder: Arc<CryptoShredder>,
+    inner: Arc<dyn codec_main::EventCodec>,
+    format: EnvelopeFormat,
+}
+
+impl EncryptedEventCodec {
+    // The format must be the envelope format written by the inner codec.
+    pub fn new(shredder: Arc<CryptoShredder>, inner: Arc<dyn codec_main::EventCodec>, format: EnvelopeFormat) -> Self {
+        EncryptedEventCodec { shredder, inner, format }
+    }
+}
+
+#[async_trait]
+impl codec_main::EventCodec for EncryptedEventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let data = self.inner.marshal_event(ctx, event).await?;
+        let encrypted = match self.format {
+            EnvelopeFormat::Json => self.shredder.encrypt_json(&data),
+            #[cfg(feature = "bson")]
+            EnvelopeFormat::Bson => self.shredder.encrypt_bson(&data),
+        };
+        encrypted.map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let decrypted = match self.format {
+            EnvelopeFormat::Json => self.shredder.decrypt_json(&data),
+            #[cfg(feature = "bson")]
+            EnvelopeFormat::Bson => self.shredder.decrypt_bson(&data),
+        };
+        let data = decrypted.map_err(|e| CodecError::new(&e.to_string()))?;
+        self.inner.unmarshal_event(ctx, data).await
+    }
+}
+
+// Returns the subject and ciphertext of an encrypted field.
+fn encrypted_field(value: &Value) -> Option<(String, String)> {
+    let object = value.as_object()?;
+    if object.len() != 2 {
+        return None;
+    }
+    let subject = object.get(SUBJECT_FIELD)?.as_str()?;
+    let ciphertext = object.get(ENCRYPTED_FIELD)?.as_str()?;
+    Some((subject.to_string(), ciphertext.to_string()))
+}
+
+#[cfg(all(test, feature = "json", feature = "bson"))]
+mod tests {
+    use super::*;
+    use crate::codec::bson::event::Event as BsonEvent;
+    use crate::codec::json::event::EventCodec as JsonEventCodec;
+    use chrono::Utc;
+    use mongodb::bson::doc;
+
+    fn shredder() -> CryptoShredder {
+        let shredder = CryptoShredder::new(Arc::new(MemoryKeyStore::new()));
+        shredder.register_personal_data(
+            "UserRegistered".to_string(),
+            Some("user_id".to_string()),
+            vec!["name".to_string(), "email".to_string()],
+        );
+        shredder
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_json() {
+        let shredder = shredder();
+        let encoded = JsonEventCodec::marshal_event(
+            "UserRegistered".to_string(),
+            Some(json!({"user_id": "user-1", "name": "Ada", "email": "ada@example.com", "plan": "pro"})),
+            Utc::now(),
+            "User".to_string(),
+            Uuid::new_v4(),
+            1,
+            crate::upcast::DEFAULT_SCHEMA_VERSION,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap();
+
+        let encrypted = shredder.encrypt_json(&encoded).unwrap();
+        let stored = String::from_utf8(encrypted.clone()).unwrap();
+        assert!(!stored.contains("ada@example.com"));
+        assert!(stored.contains("pro"));
+
+        let decrypted = shredder.decrypt_json(&encrypted).unwrap();
+        let event = JsonEventCodec::unmarshal_event(&decrypted).unwrap();
+        assert_eq!(event.data.unwrap()["email"], "ada@example.com");
+
+        // Forgetting the subject redacts its fields without touching the stored event.
+        shredder.forget("user-1").unwrap();
+        let event = JsonEventCodec::unmarshal_event(&shredder.decrypt_json(&encrypted).unwrap()).unwrap();
+        let data = event.data.unwrap();
+        assert_eq!(data["name"], Value::Null);
+        assert_eq!(data["email"], Value::Null);
+        assert_eq!(data["plan"], "pro");
+    }
+
+    #[test]
+    fn test_encrypt_decrypt_bson() {
+        let shredder = CryptoShredder::new(Arc::new(MemoryKeyStore::new()));
+        shredder.register_personal_data("UserRegistered".to_string(), None, vec!["name".to_string()]);
+
+        let aggregate_id = Uuid::new_v4();
+        let event = BsonEvent::new(
+            "UserRegistered".to_string(),
+            Some(Bson::Document(doc! {"name": "Grace", "age": 85})),
+            Utc::now(),
+            "User".to_string(),
+            aggregate_id,
+            1,
+            HashMap::new(),
+            HashMap::new(),
+        );
+        let encrypted = shredder.encrypt_bson(&BsonEventCodec::marshal_event(&event).unwrap()).unwrap();
+        let stored = BsonEventCodec::unmarshal_event(&encrypted).unwrap();
+        assert_ne!(stored.data, event.data);
+
+        let decrypted = BsonEventCodec::unmarshal_event(&shredder.decrypt_bson(&encrypted).unwrap()).unwrap();
+        assert_eq!(decrypted.data, event.data);
+
+        // The aggregate ID is the subject when no subject field is marked.
+        shredder.forget(&aggregate_id.to_string()).unwrap();
+        let redacted = BsonEventCodec::unmarshal_event(&shredder.decrypt_bson(&encrypted).unwrap()).unwrap();
+        assert_eq!(redacted.data, Some(Bson::Document(doc! {"name": Bson::Null, "age": 85})));
+    }
+
+    #[derive(Debug, serde::Serialize, serde::Deserialize)]
+    struct CustomerRegistered {
+        customer_id: String,
+        name: Option<String>,
+        age: Option<u32>,
+    }
+
+    impl crate::event::EventData for CustomerRegistered {}
+
+    #[tokio::test]
+    async fn test_encrypted_event_codec() {
+        use crate::codec_main::{BasicEvent, EventCodec as _};
+        use std::any::Any;
+
+        crate::event::register_event_data(
+            "CryptoCustomerRegistered".to_string(),
+            Box::new(|| Box::new(CustomerRegistered { customer_id: String::new(), name: None, age: None })),
+        );
+        let shredder = Arc::new(CryptoShredder::new(Arc::new(MemoryKeyStore::new())));
+        shredder.register_personal_data(
+            "CryptoCustomerRegistered".to_string(),
+            Some("customer_id".to_string()),
+            vec!["name".to_string(), "age".to_string()],
+        );
+
+        for (inner, format) in [
+            (
+                Arc::new(JsonEventCodec::default()) as Arc<dyn codec_main::EventCodec>,
+                EnvelopeFormat::Json,
+            ),
+            (Arc::new(BsonEventCodec::default()), EnvelopeFormat::Bson),
+        ] {
+            let codec = EncryptedEventCodec::new(shredder.clone(), inner, format);
+            let customer_id = Uuid::new_v4().to_string();
+            let event = BasicEvent::new(
+                "CryptoCustomerRegistered".to_string(),
+                Some(Box::new(CustomerRegistered {
+                    customer_id: customer_id.clone(),
+                    name: Some("Ada".to_string()),
+                    age: Some(36),
+                })),
+                std::time::SystemTime::now(),
+                "Customer".to_string(),
+                Uuid::new_v4(),
+                1,
+            );
+            let encoded = codec.marshal_event(&Context::new(), Arc::new(event)).await.unwrap();
+
+            let (decoded, _) = codec.unmarshal_event(Context::new(), encoded.clone()).await.unwrap();
+            let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<CustomerRegistered>().unwrap();
+            assert_eq!((data.name.as_deref(), data.age), (Some("Ada"), Some(36)));
+
+            // Forgotten fields of any type decode as None.
+            shredder.forget(&customer_id).unwrap();
+            let (decoded, _) = codec.unmarshal_event(Context::new(), encoded).await.unwrap();
+            let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<CustomerRegistered>().unwrap();
+            assert_eq!((data.name.as_deref(), data.age), (None, None));
+            assert_eq!(data.customer_id, customer_id);
+        }
+    }
+}
@@ -0,0 +1,133 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::context::{marshal_context, unmarshal_context};
+use crate::codec_main::{self, BasicEvent, CodecError};
+use crate::command_main::decode_command;
+use crate::context::Context;
+use crate::event::{decode_event_data, EventData};
+use crate::upcast::{RawEvent, UpcasterRegistry, DEFAULT_SCHEMA_VERSION};
+
+// Event envelope shared by the binary codecs, with the same fields as the
+// BSON event. Data and values are kept as JSON values so the registry-driven
+// decoding and upcasting work the same for every format.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Event {
+    pub event_type: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<Value>,
+    pub timestamp: DateTime<Utc>,
+    pub aggregate_type: String,
+    pub aggregate_id: Uuid,
+    pub version: i32,
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub metadata: HashMap<String, Value>,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub context: HashMap<String, Value>,
+}
+
+fn default_schema_version() -> u32 {
+    DEFAULT_SCHEMA_VERSION
+}
+
+impl Event {
+    // Builds the envelope of an event and the context it was handled in,
+    // stamped with the current schema version of its event type.
+    pub fn from_event(
+        ctx: &Context,
+        event: &dyn codec_main::Event,
+        upcasters: &UpcasterRegistry,
+    ) -> Result<Self, CodecError> {
+        let data = event
+            .data()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|e| CodecError::new(&e.to_string()))?;
+        Ok(Event {
+            event_type: event.event_type(),
+            data,
+            timestamp: event.timestamp().into(),
+            aggregate_type: event.aggregate_type(),
+            aggregate_id: event.aggregate_id(),
+            version: event.version(),
+            schema_version: upcasters.schema_version(&event.event_type()),
+            metadata: event.metadata(),
+            context: marshal_context(ctx).map_err(|e| CodecError::new(&e))?,
+        })
+    }
+
+    // Upcasts the data to the current schema, decodes it into its registered
+    // type and restores the context.
+    pub fn into_event(
+        self,
+        ctx: Context,
+        upcasters: &UpcasterRegistry,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let upcasted = upcasters
+            .upcast(RawEvent {
+                event_type: self.event_type,
+                schema_version: self.schema_version,
+                data: self.data,
+            })
+            .map_err(|e| CodecError::new(&e.to_string()))?;
+        let data = match upcasted.data {
+            Some(data) => Some(
+                decode_event_data(&upcasted.event_type, &mut <dyn erased_serde::Deserializer>::erase(data))
+                    .map_err(|e| CodecError::new(&e))? as Box<dyn EventData>,
+            ),
+            None => None,
+        };
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, self.context).map_err(|e| CodecError::new(&e))?;
+
+        let event = BasicEvent::new(
+            upcasted.event_type,
+            data,
+            self.timestamp.into(),
+            self.aggregate_type,
+            self.aggregate_id,
+            self.version,
+        )
+        .with_metadata(self.metadata);
+        Ok((Arc::new(event), ctx))
+    }
+}
+
+// Command envelope shared by the binary codecs.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Command {
+    pub command_type: String,
+    pub command: Value,
+    #[serde(default, skip_serializing_if = "HashMap::is_empty")]
+    pub context: HashMap<String, Value>,
+}
+
+impl Command {
+    // Builds the envelope of a command and the context it was issued in.
+    pub fn from_command(ctx: &Context, command: &dyn codec_main::Command) -> Result<Self, CodecError> {
+        Ok(Command {
+            command_type: command.command_type(),
+            command: serde_json::to_value(command).map_err(|e| CodecError::new(&e.to_string()))?,
+            context: marshal_context(ctx).map_err(|e| CodecError::new(&e))?,
+        })
+    }
+
+    // Decodes the command into its registered type and restores the context.
+    pub fn into_command(self, ctx: Context) -> Result<(Arc<dyn codec_main::Command>, Context), CodecError> {
+        let command = decode_command(
+            &self.command_type,
+            &mut <dyn erased_serde::Deserializer>::erase(self.command),
+        )
+        .map_err(|e| CodecError::new(&e.to_string()))?;
+
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, self.context).map_err(|e| CodecError::new(&e))?;
+        Ok((Arc::from(command as Box<dyn codec_main::Command>), ctx))
+    }
+}
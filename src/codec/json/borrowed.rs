@@ -0,0 +1,351 @@
+use bytes::Bytes;
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+use serde_json::value::RawValue;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::sync::{Arc, OnceLock};
+use uuid::Uuid;
+
+use crate::codec::context::unmarshal_context;
+use crate::codec_main::{self, BasicEvent, CodecError};
+use crate::context::Context;
+use crate::event::{decode_event_data, EventData};
+use crate::upcast::{RawEvent, UpcasterRegistry, DEFAULT_SCHEMA_VERSION};
+
+// Envelope of a JSON encoded event, borrowing its strings and leaving the
+// data, metadata and context unparsed.
+#[derive(Deserialize)]
+struct Envelope<'a> {
+    #[serde(borrow)]
+    event_type: Cow<'a, str>,
+    #[serde(borrow, default)]
+    raw_data: Option<&'a RawValue>,
+    timestamp: DateTime<Utc>,
+    #[serde(borrow)]
+    aggregate_type: Cow<'a, str>,
+    aggregate_id: Uuid,
+    version: i32,
+    #[serde(default = "default_schema_version")]
+    schema_version: u32,
+    #[serde(borrow, default)]
+    metadata: Option<&'a RawValue>,
+    #[serde(borrow, default)]
+    context: Option<&'a RawValue>,
+}
+
+fn default_schema_version() -> u32 {
+    DEFAULT_SCHEMA_VERSION
+}
+
+// Text is a string of the envelope, kept as a range of the input buffer
+// unless it had to be unescaped or was upcast.
+#[derive(Debug)]
+enum Text {
+    Borrowed(Range<usize>),
+    Owned(String),
+}
+
+impl Text {
+    fn new(buf: &[u8], text: Cow<'_, str>) -> Self {
+        match text {
+            Cow::Borrowed(text) => Text::Borrowed(span(buf, text)),
+            Cow::Owned(text) => Text::Owned(text),
+        }
+    }
+}
+
+// Returns the range of a string borrowed from a buffer.
+fn span(buf: &[u8], text: &str) -> Range<usize> {
+    let start = text.as_ptr() as usize - buf.as_ptr() as usize;
+    start..start + text.len()
+}
+
+// BorrowedEvent is a JSON encoded event decoded for replay hot paths. It
+// keeps the input buffer and refers into it instead of copying, the data is
+// only decoded into its registered type when accessed and metadata and
+// context are only parsed when requested. Events stored with a schema that
+// has upcasters are upcast when decoded, other events stay borrowed.
+#[derive(Debug)]
+pub struct BorrowedEvent {
+    buf: Bytes,
+    event_type: Text,
+    aggregate_type: Text,
+    aggregate_id: Uuid,
+    timestamp: DateTime<Utc>,
+    version: i32,
+    schema_version: u32,
+    raw_data: Option<Range<usize>>,
+    // The upcast data, replacing the stored data if the event was upcast.
+    upcast_data: Option<Option<Value>>,
+    metadata: Option<Range<usize>>,
+    context: Option<Range<usize>>,
+    data: OnceLock<Box<dyn EventData>>,
+}
+
+impl BorrowedEvent {
+    pub fn decode(buf: Bytes, upcasters: &UpcasterRegistry) -> Result<Self, CodecError> {
+        let envelope: Envelope = serde_json::from_slice(&buf).map_err(|e| CodecError::new(&e.to_string()))?;
+        let raw = |value: Option<&RawValue>| value.map(|value| span(&buf, value.get()));
+
+        let (event_type, schema_version, upcast_data) =
+            if upcasters.has_upcaster(&envelope.event_type, envelope.schema_version) {
+                let data = envelope
+                    .raw_data
+                    .map(|data| serde_json::from_str(data.get()))
+                    .transpose()
+                    .map_err(|e| CodecError::new(&e.to_string()))?;
+                let upcasted = upcasters
+                    .upcast(RawEvent {
+                        event_type: envelope.event_type.into_owned(),
+                        schema_version: envelope.schema_version,
+                        data,
+                    })
+                    .map_err(|e| CodecError::new(&e.to_string()))?;
+                let data = upcasted.data.filter(|data| !data.is_null());
+                (Text::Owned(upcasted.event_type), upcasted.schema_version, Some(data))
+            } else {
+                (Text::new(&buf, envelope.event_type), envelope.schema_version, None)
+            };
+
+        Ok(BorrowedEvent {
+            event_type,
+            aggregate_type: Text::new(&buf, envelope.aggregate_type),
+            aggregate_id: envelope.aggregate_id,
+            timestamp: envelope.timestamp,
+            version: envelope.version,
+            schema_version,
+            raw_data: raw(envelope.raw_data),
+            upcast_data,
+            metadata: raw(envelope.metadata),
+            context: raw(envelope.context),
+            data: OnceLock::new(),
+            buf,
+        })
+    }
+
+    // Returns a string of the buffer by a range taken from a str of it, which
+    // is valid UTF-8. Checking it again is cheap on these short ranges.
+    fn str(&self, range: &Range<usize>) -> &str {
+        std::str::from_utf8(&self.buf[range.clone()]).expect("ranges are taken from strs of the buffer")
+    }
+
+    fn text<'a>(&'a self, text: &'a Text) -> &'a str {
+        match text {
+            Text::Borrowed(range) => self.str(range),
+            Text::Owned(text) => text,
+        }
+    }
+
+    fn raw(&self, range: &Option<Range<usize>>) -> Option<&str> {
+        range
+            .as_ref()
+            .map(|range| self.str(range))
+            .filter(|raw| *raw != "null")
+    }
+
+    pub fn event_type(&self) -> &str {
+        self.text(&self.event_type)
+    }
+
+    pub fn aggregate_type(&self) -> &str {
+        self.text(&self.aggregate_type)
+    }
+
+    pub fn aggregate_id(&self) -> Uuid {
+        self.aggregate_id
+    }
+
+    pub fn timestamp(&self) -> DateTime<Utc> {
+        self.timestamp
+    }
+
+    pub fn version(&self) -> i32 {
+        self.version
+    }
+
+    pub fn schema_version(&self) -> u32 {
+        self.schema_version
+    }
+
+    // Returns the encoded data as a slice of the input buffer, as it was stored
+    // before any upcasting.
+    pub fn raw_data(&self) -> Option<Bytes> {
+        self.raw(&self.raw_data)?;
+        self.raw_data.clone().map(|range| self.buf.slice(range))
+    }
+
+    // Decodes the data into its registered type on first access.
+    pub fn data(&self) -> Result<Option<&dyn EventData>, CodecError> {
+        if let Some(data) = self.data.get() {
+            return Ok(Some(data.as_ref()));
+        }
+        let data = match &self.upcast_data {
+            Some(None) => return Ok(None),
+            Some(Some(data)) => decode_event_data(self.event_type(), &mut <dyn erased_serde::Deserializer>::erase(data)),
+            None => {
+                let Some(raw) = self.raw(&self.raw_data) else {
+                    return Ok(None);
+                };
+                let mut deserializer = serde_json::Deserializer::from_str(raw);
+                let data =
+                    decode_event_data(self.event_type(), &mut <dyn erased_serde::Deserializer>::erase(&mut deserializer));
+                data
+            }
+        }
+        .map_err(|e| CodecError::new(&e))?;
+        Ok(Some(self.data.get_or_init(|| data).as_ref()))
+    }
+
+    pub fn metadata(&self) -> Result<HashMap<String, Value>, CodecError> {
+        match self.raw(&self.metadata) {
+            Some(raw) => serde_json::from_str(raw).map_err(|e| CodecError::new(&e.to_string())),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    // Restores the context the event was handled in.
+    pub fn unmarshal_context(&self, ctx: &mut Context) -> Result<(), CodecError> {
+        let Some(raw) = self.raw(&self.context) else {
+            return Ok(());
+        };
+        let values = serde_json::from_str(raw).map_err(|e| CodecError::new(&e.to_string()))?;
+        unmarshal_context(ctx, values).map_err(|e| CodecError::new(&e))
+    }
+
+    // Fully decodes the event, like the JSON event codec.
+    pub fn into_event(self, ctx: Context) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let metadata = self.metadata()?;
+        let mut ctx = ctx;
+        self.unmarshal_context(&mut ctx)?;
+        self.data()?;
+
+        let event_type = self.event_type().to_string();
+        let aggregate_type = self.aggregate_type().to_string();
+        let event = BasicEvent::new(
+            event_type,
+            self.data.into_inner(),
+            self.timestamp.into(),
+            aggregate_type,
+            self.aggregate_id,
+            self.version,
+        )
+        .with_metadata(metadata);
+        Ok((Arc::new(event), ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec::json::event::EventCodec;
+    use crate::codec_main::EventCodec as _;
+    use serde::Serialize;
+    use std::any::Any;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct StockCounted {
+        sku: String,
+        count: u32,
+    }
+
+    impl EventData for StockCounted {}
+
+    #[tokio::test]
+    async fn test_borrowed_event() {
+        crate::event::register_event_data(
+            "BorrowedStockCounted".to_string(),
+            Box::new(|| Box::new(StockCounted { sku: String::new(), count: 0 })),
+        );
+
+        let id = Uuid::new_v4();
+        let event = BasicEvent::new(
+            "BorrowedStockCounted".to_string(),
+            Some(Box::new(StockCounted { sku: "A-1".to_string(), count: 9 })),
+            std::time::SystemTime::now(),
+            "Warehouse\n".to_string(),
+            id,
+            7,
+        )
+        .with_metadata(HashMap::from([("source".to_string(), serde_json::json!("test"))]));
+        let buf = Bytes::from(EventCodec::default().marshal_event(&Context::new(), Arc::new(event)).await.unwrap());
+
+        let event = BorrowedEvent::decode(buf.clone(), &UpcasterRegistry::new()).unwrap();
+        assert_eq!(event.event_type(), "BorrowedStockCounted");
+        assert_eq!(event.aggregate_type(), "Warehouse\n");
+        assert!(matches!(event.event_type, Text::Borrowed(_)));
+        assert!(matches!(event.aggregate_type, Text::Owned(_)));
+        assert_eq!((event.aggregate_id(), event.version()), (id, 7));
+        assert!(event.data.get().is_none());
+
+        // The raw data is a slice of the same buffer.
+        let raw_data = event.raw_data().unwrap();
+        assert!(buf.as_ptr_range().contains(&raw_data.as_ptr()));
+
+        let data = (event.data().unwrap().unwrap() as &dyn Any).downcast_ref::<StockCounted>().unwrap();
+        assert_eq!((data.sku.as_str(), data.count), ("A-1", 9));
+        assert_eq!(event.metadata().unwrap()["source"], "test");
+
+        let (decoded, _) = event.into_event(Context::new()).unwrap();
+        assert_eq!(decoded.metadata()["source"], "test");
+        assert!(decoded.data().is_some());
+    }
+
+    #[test]
+    fn test_borrowed_event_without_data() {
+        let buf = crate::codec::json::event::EventCodec::marshal_event(
+            "BorrowedNoData".to_string(),
+            None,
+            Utc::now(),
+            "Warehouse".to_string(),
+            Uuid::new_v4(),
+            1,
+            crate::upcast::DEFAULT_SCHEMA_VERSION,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap();
+        let upcasters = UpcasterRegistry::new();
+        let event = BorrowedEvent::decode(Bytes::from(buf), &upcasters).unwrap();
+        assert!(event.raw_data().is_none());
+        assert!(event.data().unwrap().is_none());
+        assert!(event.metadata().unwrap().is_empty());
+        assert!(BorrowedEvent::decode(Bytes::from_static(b"{}"), &upcasters).is_err());
+    }
+
+    #[test]
+    fn test_borrowed_event_upcasts() {
+        crate::event::register_event_data(
+            "BorrowedStockRecounted".to_string(),
+            Box::new(|| Box::new(StockCounted { sku: String::new(), count: 0 })),
+        );
+        let upcasters = UpcasterRegistry::new();
+        upcasters.register_rename("BorrowedStockTaken".to_string(), 1, "BorrowedStockRecounted".to_string());
+        upcasters.register_fn("BorrowedStockRecounted".to_string(), 1, |data| {
+            Ok(serde_json::json!({ "sku": data["sku"], "count": data["quantity"] }))
+        });
+
+        let buf = crate::codec::json::event::EventCodec::marshal_event(
+            "BorrowedStockTaken".to_string(),
+            Some(serde_json::json!({"sku": "A-1", "quantity": 4})),
+            Utc::now(),
+            "Warehouse".to_string(),
+            Uuid::new_v4(),
+            1,
+            DEFAULT_SCHEMA_VERSION,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap();
+        let event = BorrowedEvent::decode(Bytes::from(buf), &upcasters).unwrap();
+        assert_eq!((event.event_type(), event.schema_version()), ("BorrowedStockRecounted", 2));
+        let data = (event.data().unwrap().unwrap() as &dyn Any).downcast_ref::<StockCounted>().unwrap();
+        assert_eq!((data.sku.as_str(), data.count), ("A-1", 4));
+
+        let (decoded, _) = event.into_event(Context::new()).unwrap();
+        assert_eq!(decoded.event_type(), "BorrowedStockRecounted");
+        assert!((decoded.data().unwrap() as &dyn Any).is::<StockCounted>());
+    }
+}
@@ -2,6 +2,13 @@ use serde::{Deserialize, Serialize};
 use serde_json::{self, Value};
 use std::collections::HashMap;
 use std::error::Error;
+use std::sync::Arc;
+use async_trait::async_trait;
+
+use crate::codec::context::{marshal_context, unmarshal_context};
+use crate::codec_main::{self, CodecError};
+use crate::command_main::decode_command;
+use crate::context::Context;
 
 // Command struct used for internal transport
 #[derive(Serialize, Deserialize, Debug)]
@@ -16,7 +23,7 @@ pub struct CommandCodec;
 
 impl CommandCodec {
     // Marshal a command into JSON bytes
-    pub fn marshal_command<T: Serialize>(
+    pub fn marshal_command<T: Serialize + ?Sized>(
         command_type: String,
         cmd: &T,
         context: HashMap<String, Value>,
@@ -35,6 +42,7 @@ impl CommandCodec {
     }
 
     // Unmarshal JSON bytes into a Command struct
+    #[allow(clippy::type_complexity)]
     pub fn unmarshal_command<T: for<'de> Deserialize<'de>>(
         json_bytes: &[u8],
     ) -> Result<(String, T, HashMap<String, Value>), Box<dyn Error>> {
@@ -48,6 +56,34 @@ impl CommandCodec {
     }
 }
 
+// CommandCodec decodes commands into the concrete type registered with
+// command_main::register_command for their command type.
+#[async_trait]
+impl codec_main::CommandCodec for CommandCodec {
+    async fn marshal_command(&self, ctx: &Context, command: Arc<dyn codec_main::Command>) -> Result<Vec<u8>, CodecError> {
+        let context = marshal_context(ctx).map_err(|e| CodecError::new(&e))?;
+        CommandCodec::marshal_command(command.command_type(), &*command, context)
+            .map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_command(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Command>, Context), CodecError> {
+        let command: Command = serde_json::from_slice(&data).map_err(|e| CodecError::new(&e.to_string()))?;
+        let decoded = decode_command(
+            &command.command_type,
+            &mut <dyn erased_serde::Deserializer>::erase(command.command),
+        )
+        .map_err(|e| CodecError::new(&e.to_string()))?;
+
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, command.context).map_err(|e| CodecError::new(&e))?;
+        Ok((Arc::from(decoded as Box<dyn codec_main::Command>), ctx))
+    }
+}
+
 // Example test for serialization and deserialization
 #[cfg(test)]
 mod tests {
@@ -83,4 +119,48 @@ mod tests {
         assert_eq!(deserialized_command, command);
         assert_eq!(deserialized_context, context);
     }
-}
\ No newline at end of file
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct RenameUser {
+        id: uuid::Uuid,
+        name: String,
+    }
+
+    impl crate::command_main::Command for RenameUser {
+        fn aggregate_id(&self) -> uuid::Uuid {
+            self.id
+        }
+
+        fn aggregate_type(&self) -> String {
+            "User".to_string()
+        }
+
+        fn command_type(&self) -> String {
+            "JsonCodecRenameUser".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_command_codec() {
+        use crate::codec_main::CommandCodec as _;
+
+        crate::command_main::register_command(
+            "JsonCodecRenameUser".to_string(),
+            Box::new(|| Box::new(RenameUser { id: uuid::Uuid::nil(), name: String::new() })),
+        );
+
+        let id = uuid::Uuid::new_v4();
+        let codec = CommandCodec;
+        let data = codec
+            .marshal_command(&Context::new(), Arc::new(RenameUser { id, name: "Ada".to_string() }))
+            .await
+            .unwrap();
+        let (command, _) = codec.unmarshal_command(Context::new(), data).await.unwrap();
+        assert_eq!(command.command_type(), "JsonCodecRenameUser");
+        let command = (command.as_ref() as &dyn std::any::Any).downcast_ref::<RenameUser>().unwrap();
+        assert_eq!((command.id, command.name.as_str()), (id, "Ada"));
+
+        let data = CommandCodec::marshal_command("JsonCodecUnknown".to_string(), &json!({}), HashMap::new()).unwrap();
+        assert!(codec.unmarshal_command(Context::new(), data).await.is_err());
+    }
+}
@@ -4,28 +4,52 @@ use uuid::Uuid;
 use chrono::{DateTime, Utc};
 use std::collections::HashMap;
 use std::error::Error;
+use std::sync::Arc;
+use async_trait::async_trait;
+
+use crate::codec::context::{marshal_context, unmarshal_context};
+use crate::codec_main::{self, BasicEvent, CodecError};
+use crate::context::Context;
+use crate::event::{decode_event_data, EventData};
+use crate::upcast::{RawEvent, UpcastError, UpcasterRegistry, DEFAULT_SCHEMA_VERSION};
 
 // Event struct for internal usage in Rust
 #[derive(Serialize, Deserialize, Debug)]
-struct Event {
-    event_type: String,
+pub struct Event {
+    pub event_type: String,
     #[serde(skip_serializing_if = "Option::is_none")]
     raw_data: Option<Value>,
     #[serde(skip)]
-    data: Option<Value>, // This will hold the deserialized event data
-    timestamp: DateTime<Utc>,
-    aggregate_type: String,
-    aggregate_id: Uuid,
-    version: i32,
-    metadata: HashMap<String, Value>,
-    context: HashMap<String, Value>,
+    pub data: Option<Value>, // This will hold the deserialized event data
+    pub timestamp: DateTime<Utc>,
+    pub aggregate_type: String,
+    pub aggregate_id: Uuid,
+    pub version: i32,
+    #[serde(default = "default_schema_version")]
+    pub schema_version: u32,
+    pub metadata: HashMap<String, Value>,
+    pub context: HashMap<String, Value>,
+}
+
+fn default_schema_version() -> u32 {
+    DEFAULT_SCHEMA_VERSION
 }
 
-// EventCodec responsible for encoding and decoding events in JSON format
-pub struct EventCodec;
+// EventCodec responsible for encoding and decoding events in JSON format.
+// Events are written with the current schema version of their event type and
+// upcast by the registered upcasters when decoded.
+#[derive(Default, Clone)]
+pub struct EventCodec {
+    upcasters: Arc<UpcasterRegistry>,
+}
 
 impl EventCodec {
+    pub fn new(upcasters: Arc<UpcasterRegistry>) -> Self {
+        EventCodec { upcasters }
+    }
+
     // Marshal the event into JSON bytes
+    #[allow(clippy::too_many_arguments)]
     pub fn marshal_event(
         event_type: String,
         data: Option<Value>,
@@ -33,6 +57,7 @@ impl EventCodec {
         aggregate_type: String,
         aggregate_id: Uuid,
         version: i32,
+        schema_version: u32,
         metadata: HashMap<String, Value>,
         context: HashMap<String, Value>,
     ) -> Result<Vec<u8>, Box<dyn Error>> {
@@ -44,6 +69,7 @@ impl EventCodec {
             aggregate_type,
             aggregate_id,
             version,
+            schema_version,
             metadata,
             context,
         };
@@ -67,6 +93,78 @@ impl EventCodec {
 
         Ok(event)
     }
+
+    // Upcast the event type and data of an unmarshaled event to the current schema
+    pub fn upcast_event(event: &mut Event, upcasters: &UpcasterRegistry) -> Result<(), UpcastError> {
+        let upcasted = upcasters.upcast(RawEvent {
+            event_type: event.event_type.clone(),
+            schema_version: event.schema_version,
+            data: event.data.take(),
+        })?;
+        event.event_type = upcasted.event_type;
+        event.schema_version = upcasted.schema_version;
+        event.data = upcasted.data;
+        Ok(())
+    }
+}
+
+// EventCodec encodes any event whose data is registered with
+// event::register_event_data, decoding the data into its concrete type.
+#[async_trait]
+impl codec_main::EventCodec for EventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let data = event
+            .data()
+            .map(serde_json::to_value)
+            .transpose()
+            .map_err(|e| CodecError::new(&e.to_string()))?;
+        let context = marshal_context(ctx).map_err(|e| CodecError::new(&e))?;
+        let event_type = event.event_type();
+        let schema_version = self.upcasters.schema_version(&event_type);
+
+        EventCodec::marshal_event(
+            event_type,
+            data,
+            event.timestamp().into(),
+            event.aggregate_type(),
+            event.aggregate_id(),
+            event.version(),
+            schema_version,
+            event.metadata(),
+            context,
+        )
+        .map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let mut event = EventCodec::unmarshal_event(&data).map_err(|e| CodecError::new(&e.to_string()))?;
+        EventCodec::upcast_event(&mut event, &self.upcasters).map_err(|e| CodecError::new(&e.to_string()))?;
+
+        let data = match event.data {
+            Some(data) => Some(
+                decode_event_data(&event.event_type, &mut <dyn erased_serde::Deserializer>::erase(data))
+                    .map_err(|e| CodecError::new(&e))? as Box<dyn EventData>,
+            ),
+            None => None,
+        };
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, event.context).map_err(|e| CodecError::new(&e))?;
+
+        let decoded = BasicEvent::new(
+            event.event_type,
+            data,
+            event.timestamp.into(),
+            event.aggregate_type,
+            event.aggregate_id,
+            event.version,
+        )
+        .with_metadata(event.metadata);
+        Ok((Arc::new(decoded), ctx))
+    }
 }
 
 // Test Command
@@ -91,6 +189,7 @@ mod tests {
             "TestAggregate".to_string(),
             aggregate_id,
             1,
+            DEFAULT_SCHEMA_VERSION,
             metadata.clone(),
             context.clone(),
         )
@@ -109,4 +208,153 @@ mod tests {
         assert_eq!(deserialized_event.metadata, metadata);
         assert_eq!(deserialized_event.context, context);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_unmarshal_and_upcast_old_event() {
+        let upcasters = UpcasterRegistry::new();
+        upcasters.register_fn("TestEvent".to_string(), 1, |mut data| {
+            data["count"] = json!(data["count"].as_str().unwrap_or("0").parse::<i64>().map_err(|e| e.to_string())?);
+            Ok(data)
+        });
+        upcasters.register_rename("TestEvent".to_string(), 2, "Counted".to_string());
+
+        // Events stored before schema versions were recorded default to v1.
+        let stored = json!({
+            "event_type": "TestEvent",
+            "raw_data": {"count": "3"},
+            "timestamp": Utc::now(),
+            "aggregate_type": "TestAggregate",
+            "aggregate_id": Uuid::new_v4(),
+            "version": 1,
+            "metadata": {},
+            "context": {},
+        });
+        let mut event = EventCodec::unmarshal_event(&serde_json::to_vec(&stored).unwrap()).unwrap();
+        assert_eq!(event.schema_version, 1);
+
+        EventCodec::upcast_event(&mut event, &upcasters).unwrap();
+        assert_eq!(event.event_type, "Counted");
+        assert_eq!(event.schema_version, 2);
+        assert_eq!(event.data, Some(json!({"count": 3})));
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct UserRenamed {
+        name: String,
+    }
+
+    impl crate::event::EventData for UserRenamed {}
+
+    #[tokio::test]
+    async fn test_event_codec() {
+        use crate::codec_main::EventCodec as _;
+        use crate::context::{register_context_marshaler, register_context_unmarshaler, CloneableAny};
+
+        crate::event::register_event_data(
+            "JsonCodecUserRenamed".to_string(),
+            Box::new(|| Box::new(UserRenamed { name: String::new() })),
+        );
+        register_context_marshaler(Box::new(|ctx: &Context| {
+            let mut values = HashMap::new();
+            if let Some(user) = ctx.get("json_codec_user") {
+                values.insert("json_codec_user".to_string(), user.clone());
+            }
+            Ok(values)
+        }));
+        register_context_unmarshaler(Box::new(|ctx: &mut Context, values| {
+            if let Some(user) = values.get("json_codec_user") {
+                ctx.insert("json_codec_user".to_string(), user.clone());
+            }
+            Ok(())
+        }));
+
+        let aggregate_id = Uuid::new_v4();
+        let event = BasicEvent::new(
+            "JsonCodecUserRenamed".to_string(),
+            Some(Box::new(UserRenamed { name: "Ada".to_string() })),
+            std::time::SystemTime::now(),
+            "User".to_string(),
+            aggregate_id,
+            3,
+        )
+        .with_metadata(HashMap::from([("source".to_string(), json!("test"))]));
+        let mut ctx = Context::new();
+        ctx.insert("json_codec_user".to_string(), CloneableAny::new("admin".to_string()));
+
+        let codec = EventCodec::default();
+        let data = codec.marshal_event(&ctx, Arc::new(event)).await.unwrap();
+        let (decoded, ctx) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+
+        assert_eq!(decoded.event_type(), "JsonCodecUserRenamed");
+        assert_eq!(decoded.aggregate_id(), aggregate_id);
+        assert_eq!(decoded.version(), 3);
+        assert_eq!(decoded.metadata()["source"], "test");
+        let data = decoded.data().unwrap() as &dyn std::any::Any;
+        assert_eq!(data.downcast_ref::<UserRenamed>().unwrap().name, "Ada");
+        let user = ctx.get("json_codec_user").unwrap().downcast_ref::<String>().unwrap();
+        assert_eq!(user, "admin");
+
+        // Data of unregistered event types can't be decoded.
+        let data = EventCodec::marshal_event(
+            "JsonCodecUnknown".to_string(),
+            Some(json!({})),
+            Utc::now(),
+            "User".to_string(),
+            aggregate_id,
+            1,
+            DEFAULT_SCHEMA_VERSION,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap();
+        assert!(codec.unmarshal_event(Context::new(), data).await.is_err());
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ItemsCounted {
+        count: i64,
+    }
+
+    impl crate::event::EventData for ItemsCounted {}
+
+    #[tokio::test]
+    async fn test_event_codec_upcasts() {
+        use crate::codec_main::EventCodec as _;
+
+        crate::event::register_event_data(
+            "JsonCodecItemsCounted".to_string(),
+            Box::new(|| Box::new(ItemsCounted { count: 0 })),
+        );
+        let upcasters = Arc::new(UpcasterRegistry::new());
+        upcasters.register_fn("JsonCodecItemsCounted".to_string(), 1, |mut data| {
+            data["count"] = json!(data["count"].as_str().unwrap_or("0").parse::<i64>().map_err(|e| e.to_string())?);
+            Ok(data)
+        });
+        upcasters.set_schema_version("JsonCodecItemsCounted".to_string(), 2);
+        let codec = EventCodec::new(upcasters);
+
+        // Old events are decoded into the current shape.
+        let stored = EventCodec::marshal_event(
+            "JsonCodecItemsCounted".to_string(),
+            Some(json!({"count": "3"})),
+            Utc::now(),
+            "Cart".to_string(),
+            Uuid::new_v4(),
+            1,
+            DEFAULT_SCHEMA_VERSION,
+            HashMap::new(),
+            HashMap::new(),
+        )
+        .unwrap();
+        let (decoded, _) = codec.unmarshal_event(Context::new(), stored).await.unwrap();
+        let data = decoded.data().unwrap() as &dyn std::any::Any;
+        assert_eq!(data.downcast_ref::<ItemsCounted>().unwrap().count, 3);
+
+        // New events are written at the current version and not upcast again.
+        let data = codec.marshal_event(&Context::new(), decoded).await.unwrap();
+        assert_eq!(EventCodec::unmarshal_event(&data).unwrap().schema_version, 2);
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        let data = decoded.data().unwrap() as &dyn std::any::Any;
+        assert_eq!(data.downcast_ref::<ItemsCounted>().unwrap().count, 3);
+    }
+}
@@ -1,2 +1,4 @@
-mod command;
-mod event;
\ No newline at end of file
+pub mod borrowed;
+pub mod command;
+pub mod event;
+pub mod snapshot;
\ No newline at end of file
@@ -0,0 +1,136 @@
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use serde_json::{self, Value};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use crate::codec_main::{self, CodecError};
+use crate::snapshot::{AggregateType, Snapshot as AggregateSnapshot, SnapshotFactoryRegistry};
+
+// Snapshot struct matching the JSON snapshot format. The schema version of the
+// state is stored alongside it so outdated snapshots can be detected on load.
+#[derive(Serialize, Deserialize, Debug)]
+struct Snapshot {
+    version: i32,
+    aggregate_type: String,
+    timestamp: DateTime<Utc>,
+    schema_version: u32,
+    state: Value,
+}
+
+// SnapshotCodec encodes and decodes snapshots in JSON format, creating the
+// concrete snapshot data through the registry.
+pub struct SnapshotCodec {
+    registry: Arc<SnapshotFactoryRegistry>,
+}
+
+impl SnapshotCodec {
+    pub fn new(registry: Arc<SnapshotFactoryRegistry>) -> Self {
+        SnapshotCodec { registry }
+    }
+}
+
+impl codec_main::SnapshotCodec for SnapshotCodec {
+    fn marshal_snapshot(&self, snapshot: &AggregateSnapshot) -> Result<Vec<u8>, CodecError> {
+        let state = serde_json::to_value(&*snapshot.state).map_err(|e| CodecError::new(&e.to_string()))?;
+        let snapshot = Snapshot {
+            version: snapshot.version,
+            aggregate_type: snapshot.aggregate_type.as_str().to_string(),
+            timestamp: snapshot.timestamp.into(),
+            schema_version: self.registry.schema_version(&snapshot.aggregate_type),
+            state,
+        };
+        serde_json::to_vec(&snapshot).map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    fn unmarshal_snapshot(&self, aggregate_id: Uuid, data: &[u8]) -> Result<Option<AggregateSnapshot>, CodecError> {
+        let snapshot: Snapshot = serde_json::from_slice(data).map_err(|e| CodecError::new(&e.to_string()))?;
+        let aggregate_type = AggregateType::new(snapshot.aggregate_type);
+
+        let state = match self
+            .registry
+            .upgrade_state(&aggregate_type, snapshot.schema_version, snapshot.state)
+            .map_err(|e| CodecError::new(&e))?
+        {
+            Some(state) => state,
+            None => return Ok(None),
+        };
+        let state = self
+            .registry
+            .decode_snapshot_data(
+                aggregate_id,
+                aggregate_type.clone(),
+                &mut <dyn erased_serde::Deserializer>::erase(state),
+            )
+            .map_err(|e| CodecError::new(&e))?;
+
+        Ok(Some(AggregateSnapshot {
+            version: snapshot.version,
+            aggregate_type,
+            timestamp: snapshot.timestamp.into(),
+            state,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec_main::SnapshotCodec as _;
+    use crate::snapshot::SnapshotData;
+    use serde_json::json;
+    use std::time::SystemTime;
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct CounterState {
+        total: i32,
+    }
+
+    impl SnapshotData for CounterState {}
+
+    fn registry() -> Arc<SnapshotFactoryRegistry> {
+        let registry = SnapshotFactoryRegistry::new();
+        registry.register_snapshot_data(AggregateType::new("Counter".to_string()), |_| Box::new(CounterState { total: 0 }));
+        Arc::new(registry)
+    }
+
+    fn snapshot(total: i32) -> AggregateSnapshot {
+        AggregateSnapshot {
+            version: 3,
+            aggregate_type: AggregateType::new("Counter".to_string()),
+            timestamp: SystemTime::now(),
+            state: Box::new(CounterState { total }),
+        }
+    }
+
+    #[test]
+    fn test_marshal_unmarshal_snapshot() {
+        let codec = SnapshotCodec::new(registry());
+        let data = codec.marshal_snapshot(&snapshot(42)).unwrap();
+
+        let decoded = codec.unmarshal_snapshot(Uuid::new_v4(), &data).unwrap().unwrap();
+        assert_eq!(decoded.version, 3);
+        assert_eq!(decoded.aggregate_type.as_str(), "Counter");
+        let state = decoded.state.as_any().downcast_ref::<CounterState>().unwrap();
+        assert_eq!(state.total, 42);
+    }
+
+    #[test]
+    fn test_outdated_schema() {
+        let registry = registry();
+        let aggregate_type = AggregateType::new("Counter".to_string());
+        let data = SnapshotCodec::new(registry.clone()).marshal_snapshot(&snapshot(5)).unwrap();
+
+        // Without an upgrader the outdated snapshot is ignored.
+        registry.set_schema_version(aggregate_type.clone(), 2);
+        let codec = SnapshotCodec::new(registry.clone());
+        assert!(codec.unmarshal_snapshot(Uuid::new_v4(), &data).unwrap().is_none());
+
+        registry.register_upgrader(aggregate_type, 1, |state| {
+            Ok(json!({"total": state["total"].as_i64().unwrap_or_default() * 10}))
+        });
+        let decoded = codec.unmarshal_snapshot(Uuid::new_v4(), &data).unwrap().unwrap();
+        let state = decoded.state.as_any().downcast_ref::<CounterState>().unwrap();
+        assert_eq!(state.total, 50);
+    }
+}
@@ -1,2 +1,126 @@
+use std::sync::Arc;
+
+use crate::codec_main::{CodecError, CommandCodec, EventCodec};
+
+#[cfg(feature = "avro")]
+pub mod avro;
+#[cfg(feature = "json")]
 pub mod json;
-pub mod bson;
\ No newline at end of file
+#[cfg(feature = "bson")]
+pub mod bson;
+#[cfg(feature = "cbor")]
+pub mod cbor;
+pub mod claimcheck;
+#[cfg(feature = "cloudevents")]
+pub mod cloudevents;
+pub mod compression;
+pub mod context;
+pub mod crypto;
+pub mod envelope;
+#[cfg(feature = "msgpack")]
+pub mod msgpack;
+pub mod multi;
+#[cfg(feature = "protobuf")]
+pub mod protobuf;
+
+// Codecs encode events and commands through the traits of codec_main, which
+// event stores and outboxes take to persist events as bytes. Stored events are
+// adapted to the codecs with eventstore::EncodableEvent and DecodedEvent.
+
+// Content types of the built-in codecs.
+pub const JSON_CONTENT_TYPE: &str = "application/json";
+pub const BSON_CONTENT_TYPE: &str = "application/bson";
+pub const MSGPACK_CONTENT_TYPE: &str = "application/msgpack";
+pub const CBOR_CONTENT_TYPE: &str = "application/cbor";
+pub const CLOUDEVENTS_CONTENT_TYPE: &str = "application/cloudevents+json";
+pub const PROTOBUF_CONTENT_TYPE: &str = "application/x-protobuf";
+pub const AVRO_CONTENT_TYPE: &str = "application/avro";
+
+// Content types that event_codec and command_codec may know. Avro isn't one of
+// them, its codec needs a schema store and is created with avro::EventCodec::new.
+pub const CONTENT_TYPES: [&str; 6] = [
+    JSON_CONTENT_TYPE,
+    BSON_CONTENT_TYPE,
+    MSGPACK_CONTENT_TYPE,
+    CBOR_CONTENT_TYPE,
+    CLOUDEVENTS_CONTENT_TYPE,
+    PROTOBUF_CONTENT_TYPE,
+];
+
+// Envelope formats of the encoded events and commands that the codec
+// decorators rewrite before passing them on to the wrapped codec.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum EnvelopeFormat {
+    Json,
+    #[cfg(feature = "bson")]
+    Bson,
+}
+
+// Returns the event codec for a content type, if it's compiled in. The Avro
+// codec can't be created without a schema store, add it to a MultiCodec with
+// with_codec instead.
+pub fn event_codec(content_type: &str) -> Result<Arc<dyn EventCodec>, CodecError> {
+    match content_type {
+        #[cfg(feature = "json")]
+        JSON_CONTENT_TYPE => Ok(Arc::new(json::event::EventCodec::default())),
+        #[cfg(feature = "bson")]
+        BSON_CONTENT_TYPE => Ok(Arc::new(bson::event::EventCodec::default())),
+        #[cfg(feature = "msgpack")]
+        MSGPACK_CONTENT_TYPE => Ok(Arc::new(msgpack::EventCodec::default())),
+        #[cfg(feature = "cbor")]
+        CBOR_CONTENT_TYPE => Ok(Arc::new(cbor::EventCodec::default())),
+        #[cfg(feature = "cloudevents")]
+        CLOUDEVENTS_CONTENT_TYPE => Ok(Arc::new(cloudevents::EventCodec::default())),
+        #[cfg(feature = "protobuf")]
+        PROTOBUF_CONTENT_TYPE => Ok(Arc::new(protobuf::EventCodec::default())),
+        AVRO_CONTENT_TYPE => Err(CodecError::new("the avro event codec needs a schema store")),
+        _ => Err(CodecError::new(&format!("no event codec for {}", content_type))),
+    }
+}
+
+// Returns the command codec for a content type, if it's compiled in.
+pub fn command_codec(content_type: &str) -> Result<Arc<dyn CommandCodec>, CodecError> {
+    match content_type {
+        #[cfg(feature = "json")]
+        JSON_CONTENT_TYPE => Ok(Arc::new(json::command::CommandCodec)),
+        #[cfg(feature = "bson")]
+        BSON_CONTENT_TYPE => Ok(Arc::new(bson::command::CommandCodec)),
+        #[cfg(feature = "msgpack")]
+        MSGPACK_CONTENT_TYPE => Ok(Arc::new(msgpack::CommandCodec)),
+        #[cfg(feature = "cbor")]
+        CBOR_CONTENT_TYPE => Ok(Arc::new(cbor::CommandCodec)),
+        #[cfg(feature = "protobuf")]
+        PROTOBUF_CONTENT_TYPE => Ok(Arc::new(protobuf::CommandCodec)),
+        _ => Err(CodecError::new(&format!("no command codec for {}", content_type))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_codec_by_content_type() {
+        #[cfg(feature = "json")]
+        {
+            assert!(event_codec(JSON_CONTENT_TYPE).is_ok());
+            assert!(command_codec(JSON_CONTENT_TYPE).is_ok());
+        }
+        #[cfg(feature = "bson")]
+        {
+            assert!(event_codec(BSON_CONTENT_TYPE).is_ok());
+            assert!(command_codec(BSON_CONTENT_TYPE).is_ok());
+        }
+        #[cfg(feature = "msgpack")]
+        assert!(event_codec(MSGPACK_CONTENT_TYPE).is_ok());
+        #[cfg(feature = "cbor")]
+        assert!(command_codec(CBOR_CONTENT_TYPE).is_ok());
+        #[cfg(feature = "cloudevents")]
+        assert!(event_codec(CLOUDEVENTS_CONTENT_TYPE).is_ok());
+        #[cfg(feature = "protobuf")]
+        assert!(command_codec(PROTOBUF_CONTENT_TYPE).is_ok());
+        assert!(event_codec(AVRO_CONTENT_TYPE).is_err());
+        assert!(event_codec("text/plain").is_err());
+        assert!(command_codec("text/plain").is_err());
+    }
+}
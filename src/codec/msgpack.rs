@@ -0,0 +1,168 @@
+use async_trait::async_trait;
+use std::sync::Arc;
+
+use super::envelope;
+use crate::codec_main::{self, CodecError};
+use crate::context::Context;
+use crate::upcast::UpcasterRegistry;
+
+// EventCodec encodes events as MessagePack maps with the envelope fields, upcasting
+// them with the registered upcasters when decoded.
+#[derive(Default, Clone)]
+pub struct EventCodec {
+    upcasters: Arc<UpcasterRegistry>,
+}
+
+impl EventCodec {
+    pub fn new(upcasters: Arc<UpcasterRegistry>) -> Self {
+        EventCodec { upcasters }
+    }
+}
+
+#[async_trait]
+impl codec_main::EventCodec for EventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let event = envelope::Event::from_event(ctx, event.as_ref(), &self.upcasters)?;
+        rmp_serde::to_vec_named(&event).map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let event: envelope::Event = rmp_serde::from_slice(&data).map_err(|e| CodecError::new(&e.to_string()))?;
+        event.into_event(ctx, &self.upcasters)
+    }
+}
+
+// CommandCodec encodes commands as MessagePack maps with the envelope fields.
+pub struct CommandCodec;
+
+#[async_trait]
+impl codec_main::CommandCodec for CommandCodec {
+    async fn marshal_command(&self, ctx: &Context, command: Arc<dyn codec_main::Command>) -> Result<Vec<u8>, CodecError> {
+        let command = envelope::Command::from_command(ctx, command.as_ref())?;
+        rmp_serde::to_vec_named(&command).map_err(|e| CodecError::new(&e.to_string()))
+    }
+
+    async fn unmarshal_command(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Command>, Context), CodecError> {
+        let command: envelope::Command =
+            rmp_serde::from_slice(&data).map_err(|e| CodecError::new(&e.to_string()))?;
+        command.into_command(ctx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec_main::{BasicEvent, CommandCodec as _, EventCodec as _};
+    use serde::{Deserialize, Serialize};
+    use std::any::Any;
+    use std::collections::HashMap;
+    use std::time::SystemTime;
+    use uuid::Uuid;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct ItemAdded {
+        sku: String,
+        quantity: u32,
+    }
+
+    impl crate::event::EventData for ItemAdded {}
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct AddItem {
+        id: Uuid,
+        sku: String,
+    }
+
+    impl crate::command_main::Command for AddItem {
+        fn aggregate_id(&self) -> Uuid {
+            self.id
+        }
+
+        fn aggregate_type(&self) -> String {
+            "Cart".to_string()
+        }
+
+        fn command_type(&self) -> String {
+            "MsgpackAddItem".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_codec() {
+        crate::event::register_event_data(
+            "MsgpackItemAdded".to_string(),
+            Box::new(|| Box::new(ItemAdded { sku: String::new(), quantity: 0 })),
+        );
+
+        let id = Uuid::new_v4();
+        let event = BasicEvent::new(
+            "MsgpackItemAdded".to_string(),
+            Some(Box::new(ItemAdded { sku: "A-1".to_string(), quantity: 2 })),
+            SystemTime::now(),
+            "Cart".to_string(),
+            id,
+            4,
+        )
+        .with_metadata(HashMap::from([("source".to_string(), serde_json::json!("test"))]));
+
+        let codec = EventCodec::default();
+        let data = codec.marshal_event(&Context::new(), Arc::new(event)).await.unwrap();
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!((decoded.aggregate_id(), decoded.version()), (id, 4));
+        assert_eq!(decoded.metadata()["source"], "test");
+        let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<ItemAdded>().unwrap();
+        assert_eq!((data.sku.as_str(), data.quantity), ("A-1", 2));
+    }
+
+    #[tokio::test]
+    async fn test_event_codec_upcasts() {
+        crate::event::register_event_data(
+            "MsgpackItemsAdded".to_string(),
+            Box::new(|| Box::new(ItemAdded { sku: String::new(), quantity: 0 })),
+        );
+        let upcasters = Arc::new(UpcasterRegistry::new());
+        upcasters.register_rename("MsgpackItemPut".to_string(), 1, "MsgpackItemsAdded".to_string());
+
+        let stored = envelope::Event {
+            event_type: "MsgpackItemPut".to_string(),
+            data: Some(serde_json::json!({"sku": "A-1", "quantity": 1})),
+            timestamp: chrono::Utc::now(),
+            aggregate_type: "Cart".to_string(),
+            aggregate_id: Uuid::new_v4(),
+            version: 1,
+            schema_version: crate::upcast::DEFAULT_SCHEMA_VERSION,
+            metadata: HashMap::new(),
+            context: HashMap::new(),
+        };
+        let data = rmp_serde::to_vec_named(&stored).unwrap();
+        let (decoded, _) = EventCodec::new(upcasters).unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!(decoded.event_type(), "MsgpackItemsAdded");
+        let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<ItemAdded>().unwrap();
+        assert_eq!((data.sku.as_str(), data.quantity), ("A-1", 1));
+    }
+
+    #[tokio::test]
+    async fn test_command_codec() {
+        crate::command_main::register_command(
+            "MsgpackAddItem".to_string(),
+            Box::new(|| Box::new(AddItem { id: Uuid::nil(), sku: String::new() })),
+        );
+
+        let id = Uuid::new_v4();
+        let data = CommandCodec
+            .marshal_command(&Context::new(), Arc::new(AddItem { id, sku: "B-2".to_string() }))
+            .await
+            .unwrap();
+        let (command, _) = CommandCodec.unmarshal_command(Context::new(), data).await.unwrap();
+        let command = (command.as_ref() as &dyn Any).downcast_ref::<AddItem>().unwrap();
+        assert_eq!((command.id, command.sku.as_str()), (id, "B-2"));
+    }
+}
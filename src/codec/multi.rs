@@ -0,0 +1,252 @@
+use async_trait::async_trait;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use crate::codec_main::{self, CodecError};
+use crate::context::Context;
+
+// Magic bytes starting a framed event, followed by the length of the content
+// type, the content type and the payload of its codec. Read as the length prefix
+// of a legacy BSON document they exceed the maximum BSON document size, and no
+// JSON document starts with 0xff, so they can't be mistaken for either.
+const FRAME_MAGIC: [u8; 4] = [0xff, b'E', b'S', b'H'];
+
+// Frames a payload with the content type of the codec that encoded it.
+pub fn frame(content_type: &str, payload: &[u8]) -> Result<Vec<u8>, CodecError> {
+    let length = u8::try_from(content_type.len()).map_err(|_| CodecError::new("content type is too long"))?;
+    let mut data = Vec::with_capacity(payload.len() + content_type.len() + FRAME_MAGIC.len() + 1);
+    data.extend_from_slice(&FRAME_MAGIC);
+    data.push(length);
+    data.extend_from_slice(content_type.as_bytes());
+    data.extend_from_slice(payload);
+    Ok(data)
+}
+
+// Splits framed data into its content type and payload.
+pub fn unframe(data: &[u8]) -> Option<(&str, &[u8])> {
+    let [length, rest @ ..] = data.strip_prefix(&FRAME_MAGIC)? else {
+        return None;
+    };
+    let length = *length as usize;
+    if rest.len() < length {
+        return None;
+    }
+    let (content_type, payload) = rest.split_at(length);
+    Some((std::str::from_utf8(content_type).ok()?, payload))
+}
+
+// Detects the content type of events encoded before they were framed, which
+// can only be the JSON and BSON codecs.
+fn detect_legacy(data: &[u8]) -> Option<&'static str> {
+    if let Some(length) = data.get(..4) {
+        if u32::from_le_bytes(length.try_into().unwrap()) as usize == data.len() && data.last() == Some(&0) {
+            return Some(super::BSON_CONTENT_TYPE);
+        }
+    }
+    match data.iter().find(|byte| !byte.is_ascii_whitespace()) {
+        Some(b'{') => Some(super::JSON_CONTENT_TYPE),
+        _ => None,
+    }
+}
+
+// MultiCodec encodes events with a preferred codec and decodes events encoded
+// with any of its codecs, so a store can hold events in several formats and
+// migrate them lazily. Encoded events are framed with their content type,
+// events without a frame are detected as JSON or BSON.
+pub struct MultiCodec {
+    preferred: String,
+    codecs: HashMap<String, Arc<dyn codec_main::EventCodec>>,
+}
+
+impl MultiCodec {
+    pub fn new(preferred: &str, codec: Arc<dyn codec_main::EventCodec>) -> Self {
+        MultiCodec {
+            preferred: preferred.to_string(),
+            codecs: HashMap::from([(preferred.to_string(), codec)]),
+        }
+    }
+
+    // Creates a multi codec for the built-in codecs, encoding with the preferred one.
+    // Avro isn't built in, add an avro::EventCodec with with_codec.
+    pub fn builtin(preferred: &str) -> Result<Self, CodecError> {
+        let mut codec = MultiCodec::new(preferred, super::event_codec(preferred)?);
+        for content_type in super::CONTENT_TYPES {
+            if let Ok(builtin) = super::event_codec(content_type) {
+                codec.codecs.entry(content_type.to_string()).or_insert(builtin);
+            }
+        }
+        Ok(codec)
+    }
+
+    // Adds a codec that events can be decoded with.
+    pub fn with_codec(mut self, content_type: &str, codec: Arc<dyn codec_main::EventCodec>) -> Self {
+        self.codecs.insert(content_type.to_string(), codec);
+        self
+    }
+
+    pub fn preferred(&self) -> &str {
+        &self.preferred
+    }
+
+    // Returns the content type an encoded event was written with.
+    pub fn content_type<'a>(&self, data: &'a [u8]) -> Result<&'a str, CodecError> {
+        match unframe(data) {
+            Some((content_type, _)) if self.codecs.contains_key(content_type) => Ok(content_type),
+            _ => detect_legacy(data).ok_or_else(|| CodecError::new("unknown event encoding")),
+        }
+    }
+
+    // Returns true if an encoded event isn't framed with the preferred codec.
+    pub fn needs_migration(&self, data: &[u8]) -> bool {
+        !matches!(unframe(data), Some((content_type, _)) if content_type == self.preferred)
+    }
+
+    // Re-encodes an event with the preferred codec if it was written with
+    // another one, returning None if it's already up to date.
+    pub async fn migrate(&self, ctx: Context, data: Vec<u8>) -> Result<Option<Vec<u8>>, CodecError> {
+        if !self.needs_migration(&data) {
+            return Ok(None);
+        }
+        let (event, ctx) = codec_main::EventCodec::unmarshal_event(self, ctx, data).await?;
+        codec_main::EventCodec::marshal_event(self, &ctx, event).await.map(Some)
+    }
+}
+
+#[async_trait]
+impl codec_main::EventCodec for MultiCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let codec = &self.codecs[&self.preferred];
+        frame(&self.preferred, &codec.marshal_event(ctx, event).await?)
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let (content_type, payload) = match unframe(&data) {
+            Some((content_type, payload)) if self.codecs.contains_key(content_type) => {
+                (content_type.to_string(), payload.to_vec())
+            }
+            _ => (self.content_type(&data)?.to_string(), data),
+        };
+        let codec = self
+            .codecs
+            .get(&content_type)
+            .ok_or_else(|| CodecError::new(&format!("no event codec for {}", content_type)))?;
+        codec.unmarshal_event(ctx, payload).await
+    }
+}
+
+#[cfg(all(test, feature = "json", feature = "bson", feature = "cbor"))]
+mod tests {
+    use super::*;
+    use crate::codec_main::{BasicEvent, EventCodec as _};
+    use serde::{Deserialize, Serialize};
+    use std::any::Any;
+    use std::time::SystemTime;
+    use uuid::Uuid;
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct PriceChanged {
+        price: u32,
+    }
+
+    impl crate::event::EventData for PriceChanged {}
+
+    fn event(price: u32) -> Arc<dyn codec_main::Event> {
+        Arc::new(BasicEvent::new(
+            "MultiCodecPriceChanged".to_string(),
+            Some(Box::new(PriceChanged { price })),
+            SystemTime::now(),
+            "Product".to_string(),
+            Uuid::new_v4(),
+            1,
+        ))
+    }
+
+    #[test]
+    fn test_frame() {
+        let data = frame("application/cbor", b"payload").unwrap();
+        assert_eq!(unframe(&data), Some(("application/cbor", &b"payload"[..])));
+        assert_eq!(unframe(b"{}"), None);
+        assert_eq!(unframe(&[0xff, 16, b'a']), None);
+        assert!(frame(&"x".repeat(256), b"").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_mixed_stream() {
+        crate::event::register_event_data(
+            "MultiCodecPriceChanged".to_string(),
+            Box::new(|| Box::new(PriceChanged { price: 0 })),
+        );
+        let ctx = Context::new();
+        let codec = MultiCodec::builtin(super::super::CBOR_CONTENT_TYPE).unwrap();
+
+        // Events written before framing, and one by the multi codec.
+        let stream = vec![
+            crate::codec::json::event::EventCodec::default().marshal_event(&ctx, event(1)).await.unwrap(),
+            crate::codec::bson::event::EventCodec::default().marshal_event(&ctx, event(2)).await.unwrap(),
+            codec.marshal_event(&ctx, event(3)).await.unwrap(),
+        ];
+        let content_types: Vec<_> = stream.iter().map(|data| codec.content_type(data).unwrap()).collect();
+        assert_eq!(content_types, ["application/json", "application/bson", "application/cbor"]);
+
+        for (price, data) in (1..).zip(stream) {
+            let migrated = codec.migrate(Context::new(), data.clone()).await.unwrap();
+            assert_eq!(migrated.is_some(), price != 3);
+
+            for data in [Some(data), migrated].into_iter().flatten() {
+                let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+                let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<PriceChanged>().unwrap();
+                assert_eq!(data.price, price);
+            }
+        }
+
+        assert!(codec.unmarshal_event(Context::new(), b"garbage".to_vec()).await.is_err());
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct NoteAdded {
+        note: String,
+    }
+
+    impl crate::event::EventData for NoteAdded {}
+
+    #[tokio::test]
+    async fn test_legacy_bson_starting_like_a_frame() {
+        crate::event::register_event_data(
+            "MultiCodecNoteAdded".to_string(),
+            Box::new(|| Box::new(NoteAdded { note: String::new() })),
+        );
+        let ctx = Context::new();
+        let bson = crate::codec::bson::event::EventCodec::default();
+
+        // Pad the note until the document length is 255 mod 256, so the
+        // encoded event starts with 0xff like a frame does.
+        let mut note = String::new();
+        let data = loop {
+            let event = Arc::new(BasicEvent::new(
+                "MultiCodecNoteAdded".to_string(),
+                Some(Box::new(NoteAdded { note: note.clone() })),
+                SystemTime::now(),
+                "Product".to_string(),
+                Uuid::new_v4(),
+                1,
+            ));
+            let data = bson.marshal_event(&ctx, event).await.unwrap();
+            if data[0] == 0xff {
+                break data;
+            }
+            note.push('x');
+        };
+
+        assert_eq!(unframe(&data), None);
+        let codec = MultiCodec::builtin(super::super::CBOR_CONTENT_TYPE).unwrap();
+        assert_eq!(codec.content_type(&data).unwrap(), "application/bson");
+        assert!(codec.needs_migration(&data));
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<NoteAdded>().unwrap();
+        assert_eq!(data.note, note);
+    }
+}
@@ -0,0 +1,413 @@
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use lazy_static::lazy_static;
+use prost::Message;
+use serde_json::Value;
+use std::any::Any;
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use uuid::Uuid;
+
+use super::context::{marshal_context, unmarshal_context};
+use crate::codec_main::{self, BasicEvent, CodecError};
+use crate::command_main;
+use crate::context::Context;
+use crate::event::{self, decode_event_data, EventData};
+use crate::upcast::{RawEvent, UpcasterRegistry};
+
+// Event is the protobuf envelope of an event, mirroring the BSON event. The
+// schema is published in proto/codec.proto.
+#[derive(Clone, PartialEq, Message)]
+pub struct Event {
+    #[prost(string, tag = "1")]
+    pub event_type: String,
+    #[prost(bytes = "vec", optional, tag = "2")]
+    pub data: Option<Vec<u8>>,
+    #[prost(message, optional, tag = "3")]
+    pub timestamp: Option<prost_types::Timestamp>,
+    #[prost(string, tag = "4")]
+    pub aggregate_type: String,
+    #[prost(string, tag = "5")]
+    pub aggregate_id: String,
+    #[prost(int32, tag = "6")]
+    pub version: i32,
+    #[prost(uint32, tag = "7")]
+    pub schema_version: u32,
+    #[prost(map = "string, string", tag = "8")]
+    pub metadata: HashMap<String, String>,
+    #[prost(map = "string, string", tag = "9")]
+    pub context: HashMap<String, String>,
+}
+
+// Command is the protobuf envelope of a command.
+#[derive(Clone, PartialEq, Message)]
+pub struct Command {
+    #[prost(string, tag = "1")]
+    pub command_type: String,
+    #[prost(bytes = "vec", tag = "2")]
+    pub command: Vec<u8>,
+    #[prost(map = "string, string", tag = "3")]
+    pub context: HashMap<String, String>,
+}
+
+type EncodeFn = Box<dyn Fn(&dyn Any) -> Option<Vec<u8>> + Send + Sync>;
+type DecodeFn<T> = Box<dyn Fn(&[u8]) -> Result<Box<T>, prost::DecodeError> + Send + Sync>;
+
+struct MessageType<T: ?Sized> {
+    encode: EncodeFn,
+    decode: DecodeFn<T>,
+}
+
+impl<T: ?Sized + 'static> MessageType<T> {
+    fn new<M: Message + Default + 'static>(into: fn(M) -> Box<T>) -> Self {
+        MessageType {
+            encode: Box::new(|value| value.downcast_ref::<M>().map(|message| message.encode_to_vec())),
+            decode: Box::new(move |data| M::decode(data).map(into)),
+        }
+    }
+}
+
+lazy_static! {
+    static ref EVENT_MESSAGES: RwLock<HashMap<String, MessageType<dyn EventData>>> = RwLock::new(HashMap::new());
+    static ref COMMAND_MESSAGES: RwLock<HashMap<String, MessageType<dyn command_main::Command>>> =
+        RwLock::new(HashMap::new());
+}
+
+// Registers a protobuf message as the data of an event type. The type is also
+// registered with event::register_event_data so the other codecs can decode it.
+pub fn register_event_message<M>(event_type: &str)
+where
+    M: EventData + Message + Default,
+{
+    event::register_event_data(event_type.to_string(), Box::new(|| Box::new(M::default())));
+    EVENT_MESSAGES
+        .write()
+        .unwrap()
+        .insert(event_type.to_string(), MessageType::new::<M>(|message| Box::new(message)));
+}
+
+// Registers a protobuf message as a command, also registering it with
+// command_main::register_command.
+pub fn register_command_message<M>()
+where
+    M: command_main::Command + Message + Default,
+{
+    let command_type = M::default().command_type();
+    command_main::register_command(command_type.clone(), Box::new(|| Box::new(M::default())));
+    COMMAND_MESSAGES
+        .write()
+        .unwrap()
+        .insert(command_type, MessageType::new::<M>(|message| Box::new(message)));
+}
+
+fn encode_message<T: ?Sized>(
+    messages: &RwLock<HashMap<String, MessageType<T>>>,
+    kind: &str,
+    name: &str,
+    value: &dyn Any,
+) -> Result<Vec<u8>, CodecError> {
+    let messages = messages.read().unwrap();
+    let message = messages
+        .get(name)
+        .ok_or_else(|| CodecError::new(&format!("no protobuf message registered for {} {}", kind, name)))?;
+    (message.encode)(value).ok_or_else(|| CodecError::new(&format!("{} {} is not its registered message", kind, name)))
+}
+
+fn decode_message<T: ?Sized>(
+    messages: &RwLock<HashMap<String, MessageType<T>>>,
+    kind: &str,
+    name: &str,
+    data: &[u8],
+) -> Result<Box<T>, CodecError> {
+    let messages = messages.read().unwrap();
+    let message = messages
+        .get(name)
+        .ok_or_else(|| CodecError::new(&format!("no protobuf message registered for {} {}", kind, name)))?;
+    (message.decode)(data).map_err(|e| CodecError::new(&e.to_string()))
+}
+
+fn encode_values(values: HashMap<String, Value>) -> HashMap<String, String> {
+    values.into_iter().map(|(key, value)| (key, value.to_string())).collect()
+}
+
+fn decode_values(values: HashMap<String, String>) -> Result<HashMap<String, Value>, CodecError> {
+    values
+        .into_iter()
+        .map(|(key, value)| {
+            serde_json::from_str(&value)
+                .map(|value| (key, value))
+                .map_err(|e| CodecError::new(&e.to_string()))
+        })
+        .collect()
+}
+
+// EventCodec encodes events in the protobuf envelope, with the data encoded
+// as the message registered with register_event_message. Events are stamped
+// with the schema version of their type. Events with upcasters for their
+// schema version are decoded with the message of their stored type, upcast as
+// JSON and decoded into the type registered for the upcast event type.
+#[derive(Default, Clone)]
+pub struct EventCodec {
+    upcasters: Arc<UpcasterRegistry>,
+}
+
+impl EventCodec {
+    pub fn new(upcasters: Arc<UpcasterRegistry>) -> Self {
+        EventCodec { upcasters }
+    }
+
+    fn decode_data(
+        &self,
+        event_type: String,
+        schema_version: u32,
+        data: Option<Vec<u8>>,
+    ) -> Result<(String, Option<Box<dyn EventData>>), CodecError> {
+        let data = match &data {
+            Some(data) => Some(decode_message(&EVENT_MESSAGES, "event", &event_type, data)?),
+            None => None,
+        };
+        if !self.upcasters.has_upcaster(&event_type, schema_version) {
+            return Ok((event_type, data));
+        }
+
+        let data = data
+            .map(|data| serde_json::to_value(&*data))
+            .transpose()
+            .map_err(|e| CodecError::new(&e.to_string()))?;
+        let upcasted = self
+            .upcasters
+            .upcast(RawEvent { event_type, schema_version, data })
+            .map_err(|e| CodecError::new(&e.to_string()))?;
+        let data = match upcasted.data {
+            Some(data) => Some(
+                decode_event_data(&upcasted.event_type, &mut <dyn erased_serde::Deserializer>::erase(data))
+                    .map_err(|e| CodecError::new(&e))? as Box<dyn EventData>,
+            ),
+            None => None,
+        };
+        Ok((upcasted.event_type, data))
+    }
+}
+
+#[async_trait]
+impl codec_main::EventCodec for EventCodec {
+    async fn marshal_event(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+        let data = match event.data() {
+            Some(data) => Some(encode_message(&EVENT_MESSAGES, "event", &event.event_type(), data as &dyn Any)?),
+            None => None,
+        };
+        let timestamp: DateTime<Utc> = event.timestamp().into();
+        let encoded = Event {
+            event_type: event.event_type(),
+            data,
+            timestamp: Some(prost_types::Timestamp {
+                seconds: timestamp.timestamp(),
+                nanos: timestamp.timestamp_subsec_nanos() as i32,
+            }),
+            aggregate_type: event.aggregate_type(),
+            aggregate_id: event.aggregate_id().to_string(),
+            version: event.version(),
+            schema_version: self.upcasters.schema_version(&event.event_type()),
+            metadata: encode_values(event.metadata()),
+            context: encode_values(marshal_context(ctx).map_err(|e| CodecError::new(&e))?),
+        };
+        Ok(encoded.encode_to_vec())
+    }
+
+    async fn unmarshal_event(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+        let event = Event::decode(data.as_slice()).map_err(|e| CodecError::new(&e.to_string()))?;
+
+        let (event_type, data) = self.decode_data(event.event_type, event.schema_version, event.data)?;
+        let timestamp = event
+            .timestamp
+            .and_then(|timestamp| DateTime::from_timestamp(timestamp.seconds, timestamp.nanos as u32))
+            .ok_or_else(|| CodecError::new("invalid event timestamp"))?;
+        let aggregate_id = Uuid::parse_str(&event.aggregate_id).map_err(|e| CodecError::new(&e.to_string()))?;
+
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, decode_values(event.context)?).map_err(|e| CodecError::new(&e))?;
+
+        let decoded = BasicEvent::new(
+            event_type,
+            data,
+            timestamp.into(),
+            event.aggregate_type,
+            aggregate_id,
+            event.version,
+        )
+        .with_metadata(decode_values(event.metadata)?);
+        Ok((Arc::new(decoded), ctx))
+    }
+}
+
+// CommandCodec encodes commands in the protobuf envelope, with the command
+// encoded as the message registered with register_command_message.
+pub struct CommandCodec;
+
+#[async_trait]
+impl codec_main::CommandCodec for CommandCodec {
+    async fn marshal_command(&self, ctx: &Context, command: Arc<dyn codec_main::Command>) -> Result<Vec<u8>, CodecError> {
+        let encoded = Command {
+            command_type: command.command_type(),
+            command: encode_message(&COMMAND_MESSAGES, "command", &command.command_type(), command.as_ref() as &dyn Any)?,
+            context: encode_values(marshal_context(ctx).map_err(|e| CodecError::new(&e))?),
+        };
+        Ok(encoded.encode_to_vec())
+    }
+
+    async fn unmarshal_command(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Command>, Context), CodecError> {
+        let command = Command::decode(data.as_slice()).map_err(|e| CodecError::new(&e.to_string()))?;
+        let decoded = decode_message(&COMMAND_MESSAGES, "command", &command.command_type, &command.command)?;
+
+        let mut ctx = ctx;
+        unmarshal_context(&mut ctx, decode_values(command.context)?).map_err(|e| CodecError::new(&e))?;
+        Ok((Arc::from(decoded), ctx))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::codec_main::{CommandCodec as _, EventCodec as _};
+    use serde::{Deserialize, Serialize};
+    use std::time::SystemTime;
+
+    #[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+    struct ParcelShipped {
+        #[prost(string, tag = "1")]
+        carrier: String,
+        #[prost(uint32, tag = "2")]
+        weight: u32,
+    }
+
+    impl EventData for ParcelShipped {}
+
+    #[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+    struct ShipParcel {
+        #[prost(string, tag = "1")]
+        id: String,
+        #[prost(string, tag = "2")]
+        carrier: String,
+    }
+
+    impl command_main::Command for ShipParcel {
+        fn aggregate_id(&self) -> Uuid {
+            Uuid::parse_str(&self.id).unwrap_or_default()
+        }
+
+        fn aggregate_type(&self) -> String {
+            "Parcel".to_string()
+        }
+
+        fn command_type(&self) -> String {
+            "ProtobufShipParcel".to_string()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_event_codec() {
+        register_event_message::<ParcelShipped>("ProtobufParcelShipped");
+
+        let id = Uuid::new_v4();
+        let event = BasicEvent::new(
+            "ProtobufParcelShipped".to_string(),
+            Some(Box::new(ParcelShipped { carrier: "Post".to_string(), weight: 3 })),
+            SystemTime::now(),
+            "Parcel".to_string(),
+            id,
+            5,
+        )
+        .with_metadata(HashMap::from([("source".to_string(), serde_json::json!("test"))]));
+
+        let codec = EventCodec::default();
+        let data = codec.marshal_event(&Context::new(), Arc::new(event)).await.unwrap();
+        let envelope = Event::decode(data.as_slice()).unwrap();
+        assert_eq!(envelope.schema_version, 1);
+        assert_eq!(
+            ParcelShipped::decode(envelope.data.unwrap().as_slice()).unwrap(),
+            ParcelShipped { carrier: "Post".to_string(), weight: 3 }
+        );
+
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!((decoded.aggregate_id(), decoded.version()), (id, 5));
+        assert_eq!(decoded.metadata()["source"], "test");
+        let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<ParcelShipped>().unwrap();
+        assert_eq!((data.carrier.as_str(), data.weight), ("Post", 3));
+    }
+
+    #[tokio::test]
+    async fn test_command_codec() {
+        register_command_message::<ShipParcel>();
+
+        let id = Uuid::new_v4().to_string();
+        let data = CommandCodec
+            .marshal_command(&Context::new(), Arc::new(ShipParcel { id: id.clone(), carrier: "Post".to_string() }))
+            .await
+            .unwrap();
+        let (command, _) = CommandCodec.unmarshal_command(Context::new(), data).await.unwrap();
+        let command = (command.as_ref() as &dyn Any).downcast_ref::<ShipParcel>().unwrap();
+        assert_eq!((command.id.as_str(), command.carrier.as_str()), (id.as_str(), "Post"));
+    }
+
+    #[tokio::test]
+    async fn test_unregistered_message() {
+        let event = BasicEvent::new(
+            "ProtobufUnregistered".to_string(),
+            Some(Box::new(ParcelShipped::default())),
+            SystemTime::now(),
+            "Parcel".to_string(),
+            Uuid::new_v4(),
+            1,
+        );
+        assert!(EventCodec::default().marshal_event(&Context::new(), Arc::new(event)).await.is_err());
+    }
+
+    #[derive(Clone, PartialEq, Message, Serialize, Deserialize)]
+    struct ParcelWeighed {
+        #[prost(uint32, tag = "1")]
+        grams: u32,
+    }
+
+    impl EventData for ParcelWeighed {}
+
+    #[tokio::test]
+    async fn test_event_codec_upcasts() {
+        register_event_message::<ParcelShipped>("ProtobufParcelWeighedV1");
+        register_event_message::<ParcelWeighed>("ProtobufParcelWeighed");
+
+        // Version 1 of the event stored the weight in kilograms under another type.
+        let stored = BasicEvent::new(
+            "ProtobufParcelWeighedV1".to_string(),
+            Some(Box::new(ParcelShipped { carrier: "Post".to_string(), weight: 3 })),
+            SystemTime::now(),
+            "Parcel".to_string(),
+            Uuid::new_v4(),
+            1,
+        );
+        let data = EventCodec::default().marshal_event(&Context::new(), Arc::new(stored)).await.unwrap();
+
+        let upcasters = Arc::new(UpcasterRegistry::new());
+        upcasters.register_rename("ProtobufParcelWeighedV1".to_string(), 1, "ProtobufParcelWeighed".to_string());
+        upcasters.register_fn("ProtobufParcelWeighed".to_string(), 1, |data| {
+            Ok(serde_json::json!({ "grams": data["weight"].as_u64().unwrap_or_default() * 1000 }))
+        });
+        upcasters.set_schema_version("ProtobufParcelWeighed".to_string(), 2);
+        let codec = EventCodec::new(upcasters);
+        let (decoded, _) = codec.unmarshal_event(Context::new(), data).await.unwrap();
+        assert_eq!(decoded.event_type(), "ProtobufParcelWeighed");
+        let data = (decoded.data().unwrap() as &dyn Any).downcast_ref::<ParcelWeighed>().unwrap();
+        assert_eq!(data.grams, 3000);
+
+        // Newly encoded events carry the current schema version.
+        let data = codec.marshal_event(&Context::new(), decoded).await.unwrap();
+        assert_eq!(Event::decode(data.as_slice()).unwrap().schema_version, 2);
+    }
+}
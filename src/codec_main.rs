@@ -1,15 +1,99 @@
 use async_trait::async_trait;
+use serde_json::Value;
 use std::any::Any;
+use std::collections::HashMap;
 use std::error::Error;
 use std::fmt;
 use std::sync::Arc;
-use tokio::task::block_in_place;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+use crate::context::Context;
+use crate::event::EventData;
+use crate::snapshot::Snapshot;
+
+// Event trait, representing an event that can be encoded by the codecs.
+pub trait Event: Send + Sync + fmt::Debug + Any {
+    fn event_type(&self) -> String;
+    fn data(&self) -> Option<&dyn EventData>;
+    fn timestamp(&self) -> SystemTime;
+    fn aggregate_type(&self) -> String;
+    fn aggregate_id(&self) -> Uuid;
+    fn version(&self) -> i32;
+    fn metadata(&self) -> HashMap<String, Value>;
+}
 
-// Event trait, representing a basic event.
-pub trait Event: Send + Sync + fmt::Debug + Any {}
+// BasicEvent is the event returned when decoding, holding the concrete
+// registered event data.
+#[derive(Debug)]
+pub struct BasicEvent {
+    event_type: String,
+    data: Option<Box<dyn EventData>>,
+    timestamp: SystemTime,
+    aggregate_type: String,
+    aggregate_id: Uuid,
+    version: i32,
+    metadata: HashMap<String, Value>,
+}
 
-// Command trait, representing a basic command.
-pub trait Command: Send + Sync + fmt::Debug + Any {}
+impl BasicEvent {
+    pub fn new(
+        event_type: String,
+        data: Option<Box<dyn EventData>>,
+        timestamp: SystemTime,
+        aggregate_type: String,
+        aggregate_id: Uuid,
+        version: i32,
+    ) -> Self {
+        BasicEvent {
+            event_type,
+            data,
+            timestamp,
+            aggregate_type,
+            aggregate_id,
+            version,
+            metadata: HashMap::new(),
+        }
+    }
+
+    pub fn with_metadata(mut self, metadata: HashMap<String, Value>) -> Self {
+        self.metadata = metadata;
+        self
+    }
+}
+
+impl Event for BasicEvent {
+    fn event_type(&self) -> String {
+        self.event_type.clone()
+    }
+
+    fn data(&self) -> Option<&dyn EventData> {
+        self.data.as_deref()
+    }
+
+    fn timestamp(&self) -> SystemTime {
+        self.timestamp
+    }
+
+    fn aggregate_type(&self) -> String {
+        self.aggregate_type.clone()
+    }
+
+    fn aggregate_id(&self) -> Uuid {
+        self.aggregate_id
+    }
+
+    fn version(&self) -> i32 {
+        self.version
+    }
+
+    fn metadata(&self) -> HashMap<String, Value> {
+        self.metadata.clone()
+    }
+}
+
+// Commands are transported by the codecs as registered in command_main.
+pub use crate::command_main::Command;
 
 // Error type for codec errors.
 #[derive(Debug)]
@@ -23,107 +107,40 @@ impl fmt::Display for CodecError {
 
 impl Error for CodecError {}
 
+impl CodecError {
+    pub fn new(msg: &str) -> Self {
+        CodecError(msg.to_string())
+    }
+}
+
+// EventCodec encodes events together with the marshaled context they were
+// handled in, and restores both when decoding.
 #[async_trait]
 pub trait EventCodec: Send + Sync {
     async fn marshal_event(&self,
-                           ctx: Arc<tokio::sync::Mutex<()>>,
+                           ctx: &Context,
                            event: Arc<dyn Event>) -> Result<Vec<u8>, CodecError>;
     async fn unmarshal_event(&self,
-                           ctx: Arc<tokio::sync::Mutex<()>>,
-                           data: Vec<u8>) -> Result<(Arc<dyn Event>, Arc<tokio::sync::Mutex<()>>), CodecError>;
+                           ctx: Context,
+                           data: Vec<u8>) -> Result<(Arc<dyn Event>, Context), CodecError>;
 }
 
+// CommandCodec encodes commands together with their marshaled context, and
+// decodes them into the concrete type registered for their command type.
 #[async_trait]
 pub trait CommandCodec: Send + Sync {
     async fn marshal_command(&self,
-                             ctx: Arc<tokio::sync::Mutex<()>>,
+                             ctx: &Context,
                              command: Arc<dyn Command>) -> Result<Vec<u8>, CodecError>;
     async fn unmarshal_command(&self,
-                             ctx: Arc<tokio::sync::Mutex<()>>,
-                             data: Vec<u8>) -> Result<(Arc<dyn Command>, Arc<tokio::sync::Mutex<()>>), CodecError>;
+                             ctx: Context,
+                             data: Vec<u8>) -> Result<(Arc<dyn Command>, Context), CodecError>;
 }
 
-// A sample implementation of EventCodec for demonstration purposes.
-pub struct MyEventCodec;
-
-#[async_trait]
-impl EventCodec for MyEventCodec {
-    async fn marshal_event(&self,
-                           _ctx: Arc<tokio::sync::Mutex<()>>,
-                           event: Arc<dyn Event>) -> Result<Vec<u8>, CodecError> {
-        block_in_place(|| {
-            // Here you would implement the real serialization logic, for now we return an empty Vec.
-            println!("Marshaling event: {:?}", event);
-            Ok(vec![])
-        })
-    }
-
-    async fn unmarshal_event(&self, _ctx:
-    Arc<tokio::sync::Mutex<()>>, _data: Vec<u8>) -> Result<(Arc<dyn Event>, Arc<tokio::sync::Mutex<()>>), CodecError> {
-        block_in_place(|| {
-            // Here you would implement the real deserialization logic, for now we return an error.
-            Err(CodecError("Unmarshaling not implemented".to_string()))
-        })
-    }
+// SnapshotCodec encodes and decodes aggregate snapshots. Decoding returns None
+// when the stored snapshot can't be used, e.g. because its schema is outdated
+// and no upgrader is registered; the aggregate is then replayed from events.
+pub trait SnapshotCodec: Send + Sync {
+    fn marshal_snapshot(&self, snapshot: &Snapshot) -> Result<Vec<u8>, CodecError>;
+    fn unmarshal_snapshot(&self, aggregate_id: Uuid, data: &[u8]) -> Result<Option<Snapshot>, CodecError>;
 }
-
-// A sample implementation of CommandCodec for demonstration purposes.
-pub struct MyCommandCodec;
-
-#[async_trait]
-impl CommandCodec for MyCommandCodec {
-    async fn marshal_command(&self, _ctx:
-    Arc<tokio::sync::Mutex<()>>, command: Arc<dyn Command>) -> Result<Vec<u8>, CodecError> {
-        block_in_place(|| {
-            // Here you would implement the real serialization logic, for now we return an empty Vec.
-            println!("Marshaling command: {:?}", command);
-            Ok(vec![])
-        })
-    }
-
-    async fn unmarshal_command(&self, _ctx:
-    Arc<tokio::sync::Mutex<()>>, _data: Vec<u8>) -> Result<(Arc<dyn Command>, Arc<tokio::sync::Mutex<()>>), CodecError> {
-        block_in_place(|| {
-            // Here you would implement the real deserialization logic, for now we return an error.
-            Err(CodecError("Unmarshaling not implemented".to_string()))
-        })
-    }
-}
-
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use tokio::sync::Mutex as AsyncMutex;
-
-    #[derive(Debug)]
-    struct TestEvent;
-
-    impl Event for TestEvent {}
-
-    #[derive(Debug)]
-    struct TestCommand;
-
-    impl Command for TestCommand {}
-
-    // Ensure multi-threaded runtime is used
-    #[tokio::test(flavor = "multi_thread")]
-    async fn test_marshal_event() {
-        let codec = MyEventCodec;
-        let ctx = Arc::new(AsyncMutex::new(()));
-        let event = Arc::new(TestEvent);
-
-        let result = codec.marshal_event(ctx.clone(), event).await;
-        assert!(result.is_ok());
-    }
-
-    // Ensure multi-threaded runtime is used
-    #[tokio::test(flavor = "multi_thread")]
-    async fn test_marshal_command() {
-        let codec = MyCommandCodec;
-        let ctx = Arc::new(AsyncMutex::new(()));
-        let command = Arc::new(TestCommand);
-
-        let result = codec.marshal_command(ctx.clone(), command).await;
-        assert!(result.is_ok());
-    }
-}
\ No newline at end of file
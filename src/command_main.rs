@@ -1,17 +1,38 @@
+use std::any::Any;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use uuid::Uuid;
 use std::error::Error;
 use std::fmt;
 use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
-// Trait representing a Command.
-pub trait Command: Send + Sync {
+// Trait representing a Command. Commands are serializable so that the codecs
+// can transport any registered command.
+pub trait Command: Any + Send + Sync + fmt::Debug + CommandDeserialize + erased_serde::Serialize {
     fn aggregate_id(&self) -> Uuid;
     fn aggregate_type(&self) -> String;
     fn command_type(&self) -> String;
 }
 
+erased_serde::serialize_trait_object!(Command);
+
+// Helper trait for filling commands created by a factory with their serialized form.
+pub trait CommandDeserialize {
+    fn deserialize_from(&mut self, deserializer: &mut dyn erased_serde::Deserializer) -> Result<(), erased_serde::Error>;
+}
+
+impl<T> CommandDeserialize for T
+where
+    T: 'static + Command + DeserializeOwned,
+{
+    fn deserialize_from(&mut self, deserializer: &mut dyn erased_serde::Deserializer) -> Result<(), erased_serde::Error> {
+        *self = erased_serde::deserialize(deserializer)?;
+        Ok(())
+    }
+}
+
 // Custom error for command operations.
 #[derive(Debug)]
 pub struct CommandError {
@@ -35,10 +56,15 @@ impl fmt::Display for CommandError {
 
 impl Error for CommandError {}
 
+// A registered command factory, with the JSON Schema of its type if one was recorded.
+struct CommandRegistration {
+    factory: Box<dyn Fn() -> Box<dyn Command + Send + Sync> + Send + Sync>,
+    schema: Option<fn() -> serde_json::Value>,
+}
+
 // Thread-safe storage for command factories.
 lazy_static! {
-    static ref COMMANDS: Arc<RwLock<HashMap<String, Box<dyn Fn() ->
-    Box<dyn Command + Send + Sync> + Send + Sync>>>> = Arc::new(RwLock::new(HashMap::new()));
+    static ref COMMANDS: Arc<RwLock<HashMap<String, CommandRegistration>>> = Arc::new(RwLock::new(HashMap::new()));
 }
 
 // Register a command factory for a type.
@@ -50,20 +76,56 @@ pub fn register_command(
     if commands.contains_key(&command_type) {
         panic!("Duplicate command type registration for {}", command_type);
     }
-    commands.insert(command_type, factory);
+    commands.insert(command_type, CommandRegistration { factory, schema: None });
+}
+
+// Record the JSON Schema of a registered command type.
+pub fn set_command_schema(command_type: &str, schema: fn() -> serde_json::Value) -> Result<(), CommandError> {
+    let mut commands = COMMANDS.write().unwrap();
+    let registration = commands
+        .get_mut(command_type)
+        .ok_or_else(|| CommandError::new("Command not registered"))?;
+    registration.schema = Some(schema);
+    Ok(())
+}
+
+// Returns the JSON Schema recorded for a registered command type.
+pub fn command_schema(command_type: &str) -> Option<serde_json::Value> {
+    let schema = COMMANDS.read().unwrap().get(command_type)?.schema?;
+    Some(schema())
 }
 
 // Create a command of a specific type using the registered factory.
 pub fn create_command(command_type: &str) -> Result<Box<dyn Command + Send + Sync>, CommandError> {
     let commands = COMMANDS.read().unwrap();
-    if let Some(factory) = commands.get(command_type) {
-        Ok(factory())
+    if let Some(registration) = commands.get(command_type) {
+        Ok((registration.factory)())
     } else {
         Err(CommandError::new("Command not registered"))
     }
 }
 
+// Returns the registered command types, sorted.
+pub fn registered_command_types() -> Vec<String> {
+    let mut command_types: Vec<String> = COMMANDS.read().unwrap().keys().cloned().collect();
+    command_types.sort();
+    command_types
+}
+
+// Create a command of a specific type and fill it with its serialized form.
+pub fn decode_command(
+    command_type: &str,
+    deserializer: &mut dyn erased_serde::Deserializer,
+) -> Result<Box<dyn Command + Send + Sync>, CommandError> {
+    let mut command = create_command(command_type)?;
+    command
+        .deserialize_from(deserializer)
+        .map_err(|e| CommandError::new(e.to_string()))?;
+    Ok(command)
+}
+
 // Example command implementation.
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MyCommand {
     id: Uuid,
 }
@@ -76,6 +138,12 @@ impl MyCommand {
     }
 }
 
+impl Default for MyCommand {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl Command for MyCommand {
     fn aggregate_id(&self) -> Uuid {
         self.id
@@ -113,6 +181,16 @@ mod tests {
         assert_eq!(command.command_type(), "MyCommand");
     }
 
+    #[test]
+    fn test_decode_command() {
+        register_command("DecodedCommand".to_string(), Box::new(|| Box::new(MyCommand::new())));
+
+        let id = Uuid::new_v4();
+        let value = serde_json::json!({ "id": id });
+        let command = decode_command("DecodedCommand", &mut <dyn erased_serde::Deserializer>::erase(value)).unwrap();
+        assert_eq!(command.aggregate_id(), id);
+    }
+
     #[test]
     fn test_create_unregistered_command() {
         let result = create_command("UnregisteredCommand");
@@ -25,6 +25,10 @@ impl CloneableAny {
     pub fn new<T: Any + Clone + Send + Sync>(value: T) -> Self {
         CloneableAny(Box::new(value))
     }
+
+    pub fn downcast_ref<T: Any>(&self) -> Option<&T> {
+        self.0.downcast_ref::<T>()
+    }
 }
 
 // The context is now using CloneableAny instead of Box<dyn Any + Send + Sync>
@@ -1,25 +1,52 @@
+use std::any::Any;
 use std::collections::HashMap;
 use std::sync::{Arc, RwLock};
 use std::time::SystemTime;
 use uuid::Uuid;
 use std::fmt;
 use lazy_static::lazy_static;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
 
-// EventData trait represents data attached to an event.
-pub trait EventData: Send + Sync {}
+// EventData trait represents data attached to an event. Event data is
+// serializable so that the codecs can encode any registered event.
+pub trait EventData: Any + Send + Sync + fmt::Debug + EventDataDeserialize + erased_serde::Serialize {}
+
+erased_serde::serialize_trait_object!(EventData);
+
+// Helper trait for filling EventData trait objects created by a factory with
+// their serialized data.
+pub trait EventDataDeserialize {
+    fn deserialize_from(&mut self, deserializer: &mut dyn erased_serde::Deserializer) -> Result<(), erased_serde::Error>;
+}
+
+impl<T> EventDataDeserialize for T
+where
+    T: 'static + EventData + DeserializeOwned,
+{
+    fn deserialize_from(&mut self, deserializer: &mut dyn erased_serde::Deserializer) -> Result<(), erased_serde::Error> {
+        *self = erased_serde::deserialize(deserializer)?;
+        Ok(())
+    }
+}
 
 // MyEventData struct implements EventData for demonstration.
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub struct MyEventData {
     pub field: String,
 }
 
 impl EventData for MyEventData {}
 
+// Registered event data, with the JSON Schema of its type if one was recorded.
+struct EventDataRegistration {
+    factory: Box<dyn Fn() -> Box<dyn EventData + Send + Sync> + Send + Sync>,
+    schema: Option<fn() -> serde_json::Value>,
+}
+
 lazy_static! {
-    static ref EVENT_DATA_FACTORIES:
-    Arc<RwLock<HashMap<String, Box<dyn Fn() ->
-    Box<dyn EventData + Send + Sync> + Send + Sync>>>> = Arc::new(RwLock::new(HashMap::new()));
+    static ref EVENT_DATA_FACTORIES: Arc<RwLock<HashMap<String, EventDataRegistration>>> =
+        Arc::new(RwLock::new(HashMap::new()));
 }
 
 pub trait Event {
@@ -108,19 +135,50 @@ pub fn register_event_data(event_type: String, factory: Box<dyn Fn() -> Box<dyn
     if factories.contains_key(&event_type) {
         panic!("Duplicate event type registration for {}", event_type);
     }
-    factories.insert(event_type, factory);
+    factories.insert(event_type, EventDataRegistration { factory, schema: None });
+}
+
+// Record the JSON Schema of registered event data
+pub fn set_event_data_schema(event_type: &str, schema: fn() -> serde_json::Value) -> Result<(), EventDataNotRegistered> {
+    let mut factories = EVENT_DATA_FACTORIES.write().unwrap();
+    let registration = factories.get_mut(event_type).ok_or(EventDataNotRegistered)?;
+    registration.schema = Some(schema);
+    Ok(())
+}
+
+// Returns the JSON Schema recorded for registered event data
+pub fn event_data_schema(event_type: &str) -> Option<serde_json::Value> {
+    let schema = EVENT_DATA_FACTORIES.read().unwrap().get(event_type)?.schema?;
+    Some(schema())
 }
 
 // Create event data
 pub fn create_event_data(event_type: &str) -> Result<Box<dyn EventData + Send + Sync>, EventDataNotRegistered> {
     let factories = EVENT_DATA_FACTORIES.read().unwrap();
-    if let Some(factory) = factories.get(event_type) {
-        Ok(factory())
+    if let Some(registration) = factories.get(event_type) {
+        Ok((registration.factory)())
     } else {
         Err(EventDataNotRegistered)
     }
 }
 
+// Returns the registered event types, sorted
+pub fn registered_event_types() -> Vec<String> {
+    let mut event_types: Vec<String> = EVENT_DATA_FACTORIES.read().unwrap().keys().cloned().collect();
+    event_types.sort();
+    event_types
+}
+
+// Create event data and fill it with its serialized form
+pub fn decode_event_data(
+    event_type: &str,
+    deserializer: &mut dyn erased_serde::Deserializer,
+) -> Result<Box<dyn EventData + Send + Sync>, String> {
+    let mut data = create_event_data(event_type).map_err(|e| e.to_string())?;
+    data.deserialize_from(deserializer).map_err(|e| e.to_string())?;
+    Ok(data)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -147,6 +205,15 @@ mod tests {
         assert!(event_data.is_ok());
     }
 
+    #[test]
+    fn test_decode_event_data() {
+        register_event_data("DecodedEvent".to_string(), Box::new(|| Box::new(MyEventData { field: String::new() })));
+        let value = serde_json::json!({"field": "decoded"});
+        let data = decode_event_data("DecodedEvent", &mut <dyn erased_serde::Deserializer>::erase(value)).unwrap();
+        let data = (data.as_ref() as &dyn Any).downcast_ref::<MyEventData>().unwrap();
+        assert_eq!(data.field, "decoded");
+    }
+
     #[test]
     fn test_event_data_not_registered() {
         let result = create_event_data("UnregisteredEvent");
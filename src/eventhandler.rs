@@ -12,6 +12,16 @@ pub trait Event: fmt::Display + Send + Sync {}
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct EventHandlerType(String);
 
+impl EventHandlerType {
+    pub fn new(handler_type: String) -> Self {
+        EventHandlerType(handler_type)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
 impl fmt::Display for EventHandlerType {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(f, "{}", self.0)
@@ -40,7 +50,7 @@ where
     pub fn new(handler_type: String, handler_fn: F) -> Self {
         Self {
             handler_fn,
-            handler_type: EventHandlerType(handler_type),
+            handler_type: EventHandlerType::new(handler_type),
         }
     }
 }
@@ -1,98 +1,167 @@
-use std::error::Error;
-use async_trait::async_trait;
+use std::fmt;
 use std::sync::Arc;
-use tokio::sync::Mutex;
+use std::time::SystemTime;
+use async_trait::async_trait;
+use thiserror::Error;
+use uuid::Uuid;
 
-// Define the Event trait to mimic the Go Event interface
-pub trait Event: Send + Sync + std::fmt::Display {
-    fn event_type(&self) -> String;
-}
+use crate::eventstore::Event;
 
-// EventStoreMaintenance trait, similar to Go's interface
-#[async_trait]
-pub trait EventStoreMaintenance {
-    // Replace an event. The version must match.
-    async fn replace(&self, event: Arc<dyn Event>) -> Result<(), Box<dyn Error + Send + Sync>>;
+// Errors related to event store maintenance.
+#[derive(Error, Debug)]
+pub enum MaintenanceError {
+    #[error("could not find event {aggregate_id}@{version}")]
+    EventNotFound { aggregate_id: Uuid, version: i32 },
+
+    #[error("replacement event does not match {aggregate_id}@{version}")]
+    MismatchedEvent { aggregate_id: Uuid, version: i32 },
 
-    // Rename all instances of an event type
-    async fn rename_event(&self, from: String, to: String) -> Result<(), Box<dyn Error + Send + Sync>>;
+    #[error("event {aggregate_id}@{version} can't be renamed")]
+    NotRenamable { aggregate_id: Uuid, version: i32 },
+
+    #[error("{0}")]
+    Store(String),
+}
+
+// A maintenance operation applied to stored events.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MaintenanceOperation {
+    Replace,
+    Rename { from: String, to: String },
+    DeleteData,
+    Redact,
 }
 
-// A basic implementation of EventStoreMaintenance for testing purposes
-pub struct BasicEventStoreMaintenance {
-    events: Arc<Mutex<Vec<Arc<dyn Event>>>>, // Just a simple in-memory event store
+// AuditRecord records a maintenance operation and the events it affected.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AuditRecord {
+    pub timestamp: SystemTime,
+    pub operation: MaintenanceOperation,
+    // The single targeted event, None for operations across all streams.
+    pub aggregate_id: Option<Uuid>,
+    pub version: Option<i32>,
+    pub affected: usize,
 }
 
-impl BasicEventStoreMaintenance {
-    pub fn new() -> Self {
-        Self {
-            events: Arc::new(Mutex::new(Vec::new())),
+impl AuditRecord {
+    pub fn new(operation: MaintenanceOperation, target: Option<(Uuid, i32)>, affected: usize) -> Self {
+        AuditRecord {
+            timestamp: SystemTime::now(),
+            operation,
+            aggregate_id: target.map(|(id, _)| id),
+            version: target.map(|(_, version)| version),
+            affected,
         }
     }
 }
 
+// Redact returns a redacted copy of an event.
+pub type Redact = dyn Fn(&dyn Event) -> Arc<dyn Event> + Send + Sync;
+
+// EventStoreMaintenance is implemented by event stores that support rewriting
+// stored events. Every successful operation is recorded in the audit trail.
 #[async_trait]
-impl EventStoreMaintenance for BasicEventStoreMaintenance {
-    async fn replace(&self, event: Arc<dyn Event>) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let mut events = self.events.lock().await;
-        for e in events.iter_mut() {
-            if e.event_type() == event.event_type() {
-                // Replace the event
-                *e = event.clone();
-                return Ok(());
-            }
-        }
-        Err("Event not found".into())
-    }
+pub trait EventStoreMaintenance: Send + Sync {
+    // Replace the event with the same aggregate ID and version.
+    async fn replace(&self, event: Arc<dyn Event>) -> Result<(), MaintenanceError>;
+
+    // Rename all instances of an event type in place, returning the number of
+    // renamed events. Nothing is renamed if any of the events can't be renamed.
+    async fn rename_event(&self, from: String, to: String) -> Result<usize, MaintenanceError>;
+
+    // Delete the payload of an event, keeping a placeholder in its stream.
+    async fn delete_event_data(&self, aggregate_id: Uuid, version: i32) -> Result<(), MaintenanceError>;
 
-    async fn rename_event(&self, from: String, to: String) -> Result<(), Box<dyn Error + Send + Sync>> {
-        let mut events = self.events.lock().await;
-        for e in events.iter_mut() {
-            if e.event_type() == from {
-                // In a real system, this might involve more complex operations.
-                println!("Renaming event from {} to {}", from, to);
-            }
+    // Redact an event by replacing it with the result of `redact`.
+    async fn redact_event(&self, aggregate_id: Uuid, version: i32, redact: &Redact) -> Result<(), MaintenanceError>;
+
+    // AuditTrail returns all recorded maintenance operations, oldest first.
+    async fn audit_trail(&self) -> Result<Vec<AuditRecord>, MaintenanceError>;
+}
+
+// PayloadCleanup is notified when maintenance deletes or redacts a stored event,
+// so payloads kept outside the event store, like claim-checked blobs, are
+// deleted together with the event's data.
+pub trait PayloadCleanup: Send + Sync {
+    fn payload_removed(&self, aggregate_id: Uuid, version: i32) -> Result<(), MaintenanceError>;
+}
+
+// DeletedEvent is the placeholder left in a stream when an event's payload was deleted.
+// It keeps the event's place in the stream: replaying an aggregate skips the
+// placeholder without applying it, but still advances the aggregate version, so
+// aggregates must not rely on deleted events to rebuild their state.
+#[derive(Debug, Clone)]
+pub struct DeletedEvent {
+    event_type: String,
+    aggregate_type: String,
+    aggregate_id: Uuid,
+    version: i32,
+    timestamp: SystemTime,
+}
+
+impl DeletedEvent {
+    pub fn new(event: &dyn Event) -> Self {
+        DeletedEvent {
+            event_type: event.event_type(),
+            aggregate_type: event.aggregate_type(),
+            aggregate_id: event.aggregate_id(),
+            version: event.version(),
+            timestamp: event.timestamp(),
         }
-        Ok(())
     }
 }
 
-// A basic test case
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use std::sync::Arc;
+impl Event for DeletedEvent {
+    fn event_type(&self) -> String {
+        self.event_type.clone()
+    }
 
-    struct TestEvent {
-        name: String,
+    fn aggregate_type(&self) -> String {
+        self.aggregate_type.clone()
     }
 
-    impl Event for TestEvent {
-        fn event_type(&self) -> String {
-            self.name.clone()
-        }
+    fn aggregate_id(&self) -> Uuid {
+        self.aggregate_id
     }
 
-    impl std::fmt::Display for TestEvent {
-        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-            write!(f, "{}", self.name)
-        }
+    fn version(&self) -> i32 {
+        self.version
+    }
+
+    fn timestamp(&self) -> SystemTime {
+        self.timestamp
     }
 
-    #[tokio::test]
-    async fn test_event_store_maintenance() {
-        let event_store = BasicEventStoreMaintenance::new();
+    fn with_event_type(&self, event_type: &str) -> Option<Arc<dyn Event>> {
+        Some(Arc::new(DeletedEvent {
+            event_type: event_type.to_string(),
+            ..self.clone()
+        }))
+    }
+}
 
-        let event = Arc::new(TestEvent {
-            name: "OldEvent".to_string(),
-        });
+impl fmt::Display for DeletedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{} (deleted)", self.event_type, self.version)
+    }
+}
 
-        event_store.replace(event.clone()).await.unwrap_err(); // Event not found initially
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eventstore::testutil::event_at;
 
-        // Add the event and replace it
-        event_store.events.lock().await.push(event.clone());
-        event_store.replace(event.clone()).await.unwrap();
+    #[test]
+    fn test_deleted_event() {
+        let event = event_at("OldEvent", Uuid::nil(), 3, SystemTime::UNIX_EPOCH);
 
-        event_store.rename_event("OldEvent".to_string(), "NewEvent".to_string()).await.unwrap();
+        let deleted = DeletedEvent::new(event.as_ref());
+        assert_eq!(deleted.event_type(), "OldEvent");
+        assert_eq!(deleted.version(), 3);
+        assert_eq!(deleted.timestamp(), SystemTime::UNIX_EPOCH);
+        assert_eq!(deleted.to_string(), "OldEvent@3 (deleted)");
+
+        let renamed = deleted.with_event_type("NewEvent").unwrap();
+        assert_eq!(renamed.to_string(), "NewEvent@3 (deleted)");
     }
-}
\ No newline at end of file
+}
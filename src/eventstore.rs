@@ -1,15 +1,166 @@
+use std::any::Any;
+use std::collections::{HashMap, VecDeque};
+use std::pin::Pin;
 use std::sync::Arc;
+use std::time::SystemTime;
 use async_trait::async_trait;
 use std::error::Error;
 use std::fmt;
 use ::uuid::Uuid;
+use futures::stream::{self, Stream};
+use futures::{future, TryStreamExt};
+use thiserror::Error;
+use serde_json::Value;
+use tokio::sync::mpsc::UnboundedReceiver;
+use crate::codec_main::{self, CodecError};
+use crate::event::EventData;
+use crate::matcher::{self, EventMatcher};
+use crate::snapshot::{AggregateType, Snapshot};
+
+pub mod memory;
+
+// Define the Event trait with the fields the store needs to order and filter events.
+// Concrete events can be recovered by upcasting to `dyn Any` and downcasting.
+pub trait Event: Any + Send + Sync + fmt::Display {
+    fn event_type(&self) -> String;
+    fn aggregate_type(&self) -> String;
+    fn aggregate_id(&self) -> Uuid;
+    fn version(&self) -> i32;
+    fn timestamp(&self) -> SystemTime;
+
+    // WithEventType returns a copy of the event stored under another event type,
+    // used by maintenance to rename stored events in place. Events that can't be
+    // renamed return None.
+    fn with_event_type(&self, _event_type: &str) -> Option<Arc<dyn Event>> {
+        None
+    }
+
+    // Data returns the registered event data, which the codecs encode. Events
+    // without data are stored with their type and position only.
+    fn data(&self) -> Option<&dyn EventData> {
+        None
+    }
+
+    // Metadata returns the metadata encoded together with the event.
+    fn metadata(&self) -> HashMap<String, Value> {
+        HashMap::new()
+    }
+}
+
+// EncodableEvent adapts a stored event to the codecs of codec_main.
+pub struct EncodableEvent(Arc<dyn Event>);
+
+impl EncodableEvent {
+    pub fn new(event: Arc<dyn Event>) -> Self {
+        EncodableEvent(event)
+    }
+}
+
+impl fmt::Debug for EncodableEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "EncodableEvent({})", self.0)
+    }
+}
+
+impl codec_main::Event for EncodableEvent {
+    fn event_type(&self) -> String {
+        self.0.event_type()
+    }
+
+    fn data(&self) -> Option<&dyn EventData> {
+        self.0.data()
+    }
+
+    fn timestamp(&self) -> SystemTime {
+        self.0.timestamp()
+    }
+
+    fn aggregate_type(&self) -> String {
+        self.0.aggregate_type()
+    }
+
+    fn aggregate_id(&self) -> Uuid {
+        self.0.aggregate_id()
+    }
+
+    fn version(&self) -> i32 {
+        self.0.version()
+    }
+
+    fn metadata(&self) -> HashMap<String, Value> {
+        self.0.metadata()
+    }
+}
+
+// DecodedEvent adapts an event decoded by a codec to the event store. It can
+// be renamed, sharing the decoded data.
+#[derive(Debug, Clone)]
+pub struct DecodedEvent {
+    event_type: String,
+    event: Arc<dyn codec_main::Event>,
+}
+
+impl DecodedEvent {
+    pub fn new(event: Arc<dyn codec_main::Event>) -> Self {
+        DecodedEvent {
+            event_type: event.event_type(),
+            event,
+        }
+    }
+}
+
+impl Event for DecodedEvent {
+    fn event_type(&self) -> String {
+        self.event_type.clone()
+    }
+
+    fn aggregate_type(&self) -> String {
+        self.event.aggregate_type()
+    }
+
+    fn aggregate_id(&self) -> Uuid {
+        self.event.aggregate_id()
+    }
+
+    fn version(&self) -> i32 {
+        self.event.version()
+    }
+
+    fn timestamp(&self) -> SystemTime {
+        self.event.timestamp()
+    }
+
+    fn with_event_type(&self, event_type: &str) -> Option<Arc<dyn Event>> {
+        Some(Arc::new(DecodedEvent {
+            event_type: event_type.to_string(),
+            event: self.event.clone(),
+        }))
+    }
+
+    fn data(&self) -> Option<&dyn EventData> {
+        self.event.data()
+    }
+
+    fn metadata(&self) -> HashMap<String, Value> {
+        self.event.metadata()
+    }
+}
+
+impl fmt::Display for DecodedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}({}, v{})", self.event_type, self.aggregate_id(), self.version())
+    }
+}
+
+// A stream of events for a single aggregate, in version order.
+pub type EventStream = Pin<Box<dyn Stream<Item = Result<Arc<dyn Event>, EventStoreError>> + Send>>;
 
-// Define the Event trait and add Debug to it
-pub trait Event: Send + Sync + fmt::Display {}
+// A stream of events from the global log, in position order.
+pub type PositionedEventStream = Pin<Box<dyn Stream<Item = Result<PositionedEvent, EventStoreError>> + Send>>;
 
 // EventStore trait, analogous to the Go EventStore interface
 #[async_trait]
-pub trait EventStore {
+pub trait EventStore: Send + Sync {
     // Save appends events to the store
     async fn save(&self, events: Vec<Arc<dyn Event>>, original_version: i32) -> Result<(), EventStoreError>;
 
@@ -19,19 +170,223 @@ pub trait EventStore {
     // LoadFrom retrieves events starting from a specific version
     async fn load_from(&self, aggregate_id: Uuid, version: i32) -> Result<Vec<Arc<dyn Event>>, EventStoreError>;
 
+    // HeadVersion returns the version of the last event of an aggregate, or 0 if it has no events.
+    // Stores should override it with a check that doesn't load the whole stream.
+    async fn head_version(&self, aggregate_id: Uuid) -> Result<i32, EventStoreError> {
+        Ok(self.load(aggregate_id).await?.last().map_or(0, |e| e.version()))
+    }
+
+    // LoadStream streams all events for a given aggregate ID
+    async fn load_stream(&self, aggregate_id: Uuid) -> Result<EventStream, EventStoreError> {
+        self.load_stream_from(aggregate_id, 1).await
+    }
+
+    // LoadStreamFrom streams events starting from a specific version. Stores that
+    // can't stream fall back to loading all events at once.
+    async fn load_stream_from(&self, aggregate_id: Uuid, version: i32) -> Result<EventStream, EventStoreError> {
+        let events = self.load_from(aggregate_id, version).await?;
+        Ok(Box::pin(stream::iter(events.into_iter().map(Ok))))
+    }
+
+    // LoadTo retrieves events up to and including a specific version
+    async fn load_to(&self, aggregate_id: Uuid, version: i32) -> Result<Vec<Arc<dyn Event>>, EventStoreError> {
+        self.load_range(aggregate_id, 1, version).await
+    }
+
+    // LoadRange retrieves events within an inclusive version range
+    async fn load_range(&self, aggregate_id: Uuid, from: i32, to: i32) -> Result<Vec<Arc<dyn Event>>, EventStoreError> {
+        let events = self
+            .load_stream_from(aggregate_id, from)
+            .await?
+            .try_take_while(|e| future::ready(Ok(e.version() <= to)))
+            .try_collect()
+            .await?;
+        Ok(events)
+    }
+
+    // LoadAsOf retrieves the events that had been stored at the given time
+    async fn load_as_of(&self, aggregate_id: Uuid, timestamp: SystemTime) -> Result<Vec<Arc<dyn Event>>, EventStoreError> {
+        let events = self
+            .load_stream(aggregate_id)
+            .await?
+            .try_take_while(|e| future::ready(Ok(e.timestamp() <= timestamp)))
+            .try_collect()
+            .await?;
+        Ok(events)
+    }
+
     // Close the event store
     async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>>;
 }
 
-// SnapshotStore trait
+// PositionedEvent is an event together with its position in the global log.
+// Positions start at 1 and increase monotonically across all aggregates.
+#[derive(Clone)]
+pub struct PositionedEvent {
+    pub position: u64,
+    pub event: Arc<dyn Event>,
+}
+
+impl fmt::Display for PositionedEvent {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}@{}", self.event, self.position)
+    }
+}
+
+// GlobalEventStore is an event store that also orders every event in a single log,
+// so that readers can consume all streams from a given position.
 #[async_trait]
-pub trait SnapshotStore {
-    async fn load_snapshot(&self, aggregate_id: Uuid) -> Result<Snapshot, Box<dyn Error + Send + Sync>>;
-    async fn save_snapshot(&self, aggregate_id: Uuid, snapshot: Snapshot) -> Result<(), Box<dyn Error + Send + Sync>>;
+pub trait GlobalEventStore: EventStore {
+    // HeadPosition returns the position of the last stored event, or 0 if the store is empty
+    async fn head_position(&self) -> Result<u64, EventStoreError>;
+
+    // LoadAll retrieves all events after the given position, optionally filtered by a matcher
+    async fn load_all(
+        &self,
+        from: u64,
+        matcher: Option<Arc<dyn EventMatcher>>,
+    ) -> Result<Vec<PositionedEvent>, EventStoreError>;
+
+    // LoadAllStream streams all events after the given position, optionally filtered by a matcher
+    async fn load_all_stream(
+        &self,
+        from: u64,
+        matcher: Option<Arc<dyn EventMatcher>>,
+    ) -> Result<PositionedEventStream, EventStoreError> {
+        let events = self.load_all(from, matcher).await?;
+        Ok(Box::pin(stream::iter(events.into_iter().map(Ok))))
+    }
+
+    // Subscribe replays all events after the given position and then continues with live events
+    async fn subscribe(
+        &self,
+        from: u64,
+        matcher: Option<Arc<dyn EventMatcher>>,
+    ) -> Result<CatchUpSubscription, EventStoreError>;
+}
+
+// CatchUpSubscription first yields the stored history and then switches to live events.
+// Live events already seen during the replay are skipped, so no event is delivered twice.
+pub struct CatchUpSubscription {
+    history: VecDeque<PositionedEvent>,
+    live: UnboundedReceiver<PositionedEvent>,
+    matcher: Option<Arc<dyn EventMatcher>>,
+    position: u64,
 }
 
-// Snapshot struct placeholder (you can customize this as needed)
-pub struct Snapshot;
+impl CatchUpSubscription {
+    // The live receiver must be registered before the history is read, otherwise
+    // events saved in between would be lost.
+    pub fn new(
+        from: u64,
+        history: Vec<PositionedEvent>,
+        live: UnboundedReceiver<PositionedEvent>,
+        matcher: Option<Arc<dyn EventMatcher>>,
+    ) -> Self {
+        Self {
+            history: history.into(),
+            live,
+            matcher,
+            position: from,
+        }
+    }
+
+    // Next waits for the next matching event, returning None once the store is closed
+    pub async fn next(&mut self) -> Option<PositionedEvent> {
+        if let Some(event) = self.history.pop_front() {
+            self.position = event.position;
+            return Some(event);
+        }
+
+        while let Some(event) = self.live.recv().await {
+            if event.position <= self.position {
+                continue;
+            }
+            self.position = event.position;
+            if matches_event(self.matcher.as_deref(), event.event.as_ref()) {
+                return Some(event);
+            }
+        }
+        None
+    }
+
+    // IsLive reports whether the replayed history has been fully consumed
+    pub fn is_live(&self) -> bool {
+        self.history.is_empty()
+    }
+
+    // Position returns the position of the last event seen by the subscription
+    pub fn position(&self) -> u64 {
+        self.position
+    }
+}
+
+// MatcherEvent adapts a stored event to the matcher module's Event trait.
+struct MatcherEvent<'a>(&'a dyn Event);
+
+impl matcher::Event for MatcherEvent<'_> {
+    fn event_type(&self) -> matcher::EventType {
+        matcher::EventType::from_name(&self.0.event_type())
+    }
+
+    fn aggregate_type(&self) -> matcher::AggregateType {
+        matcher::AggregateType::from_name(&self.0.aggregate_type())
+    }
+}
+
+// MatchesEvent checks an event against an optional matcher, where no matcher matches everything
+pub fn matches_event(matcher: Option<&dyn EventMatcher>, event: &dyn Event) -> bool {
+    match matcher {
+        Some(matcher) => matcher.matches(&MatcherEvent(event)),
+        None => true,
+    }
+}
+
+// Errors returned by event store implementations, wrapped in an EventStoreError
+#[derive(Error, Debug)]
+pub enum StoreError {
+    #[error("missing events")]
+    MissingEvents,
+
+    #[error("mismatching event aggregate IDs")]
+    MismatchedEventAggregateIds,
+
+    #[error("mismatching event aggregate types")]
+    MismatchedEventAggregateTypes,
+
+    #[error("incorrect event version")]
+    IncorrectEventVersion,
+
+    #[error("event conflict from other save")]
+    EventConflictFromOtherSave,
+
+    #[error(transparent)]
+    Codec(#[from] CodecError),
+}
+
+// SnapshotStore trait, snapshots are keyed by aggregate ID and type
+#[async_trait]
+pub trait SnapshotStore: Send + Sync {
+    // LoadSnapshot retrieves the latest snapshot of an aggregate, if any
+    async fn load_snapshot(
+        &self,
+        aggregate_id: Uuid,
+        aggregate_type: &AggregateType,
+    ) -> Result<Option<Snapshot>, Box<dyn Error + Send + Sync>> {
+        self.load_snapshot_at(aggregate_id, aggregate_type, i32::MAX).await
+    }
+
+    // LoadSnapshotAt retrieves the latest snapshot at or below the given version
+    async fn load_snapshot_at(
+        &self,
+        aggregate_id: Uuid,
+        aggregate_type: &AggregateType,
+        version: i32,
+    ) -> Result<Option<Snapshot>, Box<dyn Error + Send + Sync>>;
+
+    // SaveSnapshot stores a snapshot, replacing any snapshot of the same version
+    async fn save_snapshot(&self, aggregate_id: Uuid, snapshot: Snapshot) -> Result<(), Box<dyn Error + Send + Sync>>;
+}
 
 // Define custom EventStoreError for handling event store errors
 pub struct EventStoreError {
@@ -129,11 +484,69 @@ impl EventStoreError {
     }
 }
 
+// Test fixtures shared by the event store and the modules built on top of it.
+#[cfg(test)]
+pub(crate) mod testutil {
+    use super::*;
+
+    pub(crate) struct TestEvent {
+        pub event_type: String,
+        pub aggregate_id: Uuid,
+        pub version: i32,
+        pub timestamp: SystemTime,
+    }
+
+    impl Event for TestEvent {
+        fn event_type(&self) -> String {
+            self.event_type.clone()
+        }
+
+        fn aggregate_type(&self) -> String {
+            "TestAggregate".to_string()
+        }
+
+        fn aggregate_id(&self) -> Uuid {
+            self.aggregate_id
+        }
+
+        fn version(&self) -> i32 {
+            self.version
+        }
+
+        fn timestamp(&self) -> SystemTime {
+            self.timestamp
+        }
+
+        fn with_event_type(&self, event_type: &str) -> Option<Arc<dyn Event>> {
+            Some(event_at(event_type, self.aggregate_id, self.version, self.timestamp))
+        }
+    }
+
+    impl fmt::Display for TestEvent {
+        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+            write!(f, "{}({}, v{})", self.event_type, self.aggregate_id, self.version)
+        }
+    }
+
+    pub(crate) fn event(event_type: &str, aggregate_id: Uuid, version: i32) -> Arc<dyn Event> {
+        event_at(event_type, aggregate_id, version, SystemTime::now())
+    }
+
+    pub(crate) fn event_at(event_type: &str, aggregate_id: Uuid, version: i32, timestamp: SystemTime) -> Arc<dyn Event> {
+        Arc::new(TestEvent {
+            event_type: event_type.to_string(),
+            aggregate_id,
+            version,
+            timestamp,
+        })
+    }
+}
 
 // Test cases
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::testutil::event;
     use tokio::sync::Mutex;
 
 
@@ -213,40 +626,13 @@ mod tests {
         }
     }
 
-    // Define a simple test event
-    #[derive(Debug)]
-    struct TestEvent {
-        name: String,
-    }
-
-    impl Event for TestEvent {}
-
-    impl fmt::Display for TestEvent {
-        fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-            write!(f, "{}", self.name)
-        }
-    }
-
-
-
     #[tokio::test]
     async fn test_event_store_save_and_load() {
         let store = InMemoryEventStore::new();
 
-        let event1 = Arc::new(TestEvent {
-            name: "Event1".to_string(),
-        });
-        let event2 = Arc::new(TestEvent {
-            name: "Event2".to_string(),
-        });
+        let events = vec![event("Event1", Uuid::nil(), 1), event("Event2", Uuid::nil(), 1)];
 
-        // Convert Vec<Arc<TestEvent>> to Vec<Arc<dyn Event>>
-        let events: Vec<Arc<dyn Event>> = vec![event1.clone(), event2.clone()]
-            .into_iter()
-            .map(|event| event as Arc<dyn Event>)
-            .collect();
-
-        // Save events (now using Vec<Arc<dyn Event>>)
+        // Save events
         assert!(store.save(events.clone(), 1).await.is_ok());
 
         let aggregate_id = Uuid::new_v4(); // Simulating loading with a new random UUID
@@ -299,15 +685,7 @@ mod tests {
     async fn test_debug_implementation() {
         let store = InMemoryEventStore::new();
 
-        let event = Arc::new(TestEvent {
-            name: "TestDebugEvent".to_string(),
-        });
-
-        // Convert Vec<Arc<TestEvent>> to Vec<Arc<dyn Event>> by mapping
-        let events: Vec<Arc<dyn Event>> = vec![event.clone()]
-            .into_iter()
-            .map(|event| event as Arc<dyn Event>)
-            .collect();
+        let events = vec![event("TestDebugEvent", Uuid::nil(), 1)];
 
         let save_result = store.save(events.clone(), 1).await;
         assert!(save_result.is_ok());
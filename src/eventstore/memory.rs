@@ -0,0 +1,993 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::sync::{Arc, RwLock};
+use std::time::SystemTime;
+use async_trait::async_trait;
+use futures::stream::{self, Stream};
+use tokio::sync::mpsc::{self, UnboundedSender};
+use tokio::sync::Mutex;
+use uuid::Uuid;
+
+use super::{
+    matches_event, CatchUpSubscription, Event, EventStore, EventStoreError, EventStream, GlobalEventStore,
+    PositionedEvent, PositionedEventStream, SnapshotStore, StoreError,
+};
+use super::{DecodedEvent, EncodableEvent};
+use crate::codec_main::{self, CodecError, SnapshotCodec};
+use crate::context::Context;
+use crate::eventmaintenance::{
+    AuditRecord, DeletedEvent, EventStoreMaintenance, MaintenanceError, MaintenanceOperation, PayloadCleanup,
+    Redact,
+};
+use crate::matcher::EventMatcher;
+use crate::snapshot::{AggregateType, Snapshot};
+use crate::snapshotmaintenance::{SnapshotInfo, SnapshotStoreMaintenance};
+
+// Default number of events buffered ahead of a stream consumer.
+pub const DEFAULT_STREAM_BUFFER: usize = 64;
+
+// MemoryEventStore keeps all events in memory, ordered in a single global log.
+// With a codec the events are encoded when saved and the store keeps the events
+// decoded from the encoded bytes, so they are read back like a persistent store
+// would return them.
+pub struct MemoryEventStore {
+    inner: Arc<Mutex<MemoryLog>>,
+    stream_buffer: usize,
+    payload_cleanup: Option<Arc<dyn PayloadCleanup>>,
+    codec: Option<Arc<dyn codec_main::EventCodec>>,
+}
+
+#[derive(Default)]
+struct MemoryLog {
+    // All events in save order, the position of an event is its index + 1.
+    events: Vec<PositionedEvent>,
+    // Events per aggregate, in version order.
+    streams: HashMap<Uuid, Vec<PositionedEvent>>,
+    // Live subscribers, closed senders are dropped on the next save.
+    subscribers: Vec<UnboundedSender<PositionedEvent>>,
+    // Maintenance operations applied to the stored events.
+    audit_trail: Vec<AuditRecord>,
+}
+
+impl MemoryLog {
+    // Version of the last event of an aggregate, 0 if it has no events.
+    fn current_version(&self, aggregate_id: Uuid) -> i32 {
+        self.streams
+            .get(&aggregate_id)
+            .and_then(|stream| stream.last())
+            .map_or(0, |e| e.event.version())
+    }
+
+    // Find the global position of an event by aggregate ID and version.
+    fn position_of(&self, aggregate_id: Uuid, version: i32) -> Option<u64> {
+        let index = usize::try_from(version.checked_sub(1)?).ok()?;
+        self.streams.get(&aggregate_id)?.get(index).map(|e| e.position)
+    }
+
+    // Replace the event at a global position in both the log and its stream.
+    fn set_event(&mut self, position: u64, event: Arc<dyn Event>) {
+        let index = (event.version() - 1) as usize;
+        let stream = self.streams.get_mut(&event.aggregate_id()).unwrap();
+        stream[index].event = event.clone();
+        self.events[(position - 1) as usize].event = event;
+    }
+
+    fn find(&self, aggregate_id: Uuid, version: i32) -> Result<(u64, Arc<dyn Event>), MaintenanceError> {
+        let position = self
+            .position_of(aggregate_id, version)
+            .ok_or(MaintenanceError::EventNotFound { aggregate_id, version })?;
+        Ok((position, self.events[(position - 1) as usize].event.clone()))
+    }
+}
+
+impl MemoryEventStore {
+    pub fn new() -> Self {
+        Self::with_stream_buffer(DEFAULT_STREAM_BUFFER)
+    }
+
+    // Create a store whose streams buffer at most `stream_buffer` events ahead of the consumer.
+    pub fn with_stream_buffer(stream_buffer: usize) -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(MemoryLog::default())),
+            stream_buffer: stream_buffer.max(1),
+            payload_cleanup: None,
+            codec: None,
+        }
+    }
+
+    // Encode saved events with a codec.
+    pub fn with_codec(mut self, codec: Arc<dyn codec_main::EventCodec>) -> Self {
+        self.codec = Some(codec);
+        self
+    }
+
+    // Pass events through the codec, if any, returning them as they are stored.
+    async fn encode(&self, events: Vec<Arc<dyn Event>>) -> Result<Vec<Arc<dyn Event>>, CodecError> {
+        let Some(codec) = &self.codec else {
+            return Ok(events);
+        };
+        let mut encoded = Vec::with_capacity(events.len());
+        for event in events {
+            let data = codec
+                .marshal_event(&Context::new(), Arc::new(EncodableEvent::new(event)))
+                .await?;
+            let (decoded, _) = codec.unmarshal_event(Context::new(), data).await?;
+            encoded.push(Arc::new(DecodedEvent::new(decoded)) as Arc<dyn Event>);
+        }
+        Ok(encoded)
+    }
+
+    // Delete payloads stored outside the store when events are deleted or redacted.
+    pub fn with_payload_cleanup(mut self, cleanup: Arc<dyn PayloadCleanup>) -> Self {
+        self.payload_cleanup = Some(cleanup);
+        self
+    }
+
+    fn remove_payload(&self, aggregate_id: Uuid, version: i32) -> Result<(), MaintenanceError> {
+        match &self.payload_cleanup {
+            Some(cleanup) => cleanup.payload_removed(aggregate_id, version),
+            None => Ok(()),
+        }
+    }
+
+    // Stream items in chunks of at most `stream_buffer` events. `read` gets the
+    // offset and size of the next chunk and returns how many events it consumed
+    // together with the items to send, so the lock is only held while a chunk is
+    // copied and a slow consumer never blocks writers.
+    fn stream_chunks<T, F>(&self, read: F) -> impl Stream<Item = Result<T, EventStoreError>> + Send
+    where
+        T: Send + 'static,
+        F: Fn(&MemoryLog, usize, usize) -> (usize, Vec<T>) + Send + 'static,
+    {
+        let inner = self.inner.clone();
+        let buffer = self.stream_buffer;
+        let (tx, rx) = mpsc::channel(buffer);
+        tokio::spawn(async move {
+            let mut offset = 0;
+            loop {
+                let log = inner.lock().await;
+                let (consumed, items) = read(&log, offset, buffer);
+                drop(log);
+                if consumed == 0 {
+                    return;
+                }
+                offset += consumed;
+                for item in items {
+                    if tx.send(Ok(item)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        stream::unfold(rx, |mut rx| async move { rx.recv().await.map(|item| (item, rx)) })
+    }
+}
+
+impl Default for MemoryEventStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Build an EventStoreError for the memory store.
+fn store_error(
+    err: StoreError,
+    op: &str,
+    aggregate_type: Option<String>,
+    aggregate_id: Option<Uuid>,
+    aggregate_version: Option<i32>,
+    events: Vec<Arc<dyn Event>>,
+) -> EventStoreError {
+    EventStoreError::new(
+        Some(Box::new(err)),
+        Some(op.to_string()),
+        aggregate_type,
+        aggregate_id,
+        aggregate_version,
+        events,
+    )
+}
+
+#[async_trait]
+impl EventStore for MemoryEventStore {
+    async fn save(&self, events: Vec<Arc<dyn Event>>, original_version: i32) -> Result<(), EventStoreError> {
+        let first = match events.first() {
+            Some(first) => first.clone(),
+            None => return Err(store_error(StoreError::MissingEvents, "save", None, None, None, Vec::new())),
+        };
+        let aggregate_id = first.aggregate_id();
+        let aggregate_type = first.aggregate_type();
+
+        // Validate that all events belong to the same aggregate and follow each other.
+        for (i, event) in events.iter().enumerate() {
+            let err = if event.aggregate_id() != aggregate_id {
+                Some(StoreError::MismatchedEventAggregateIds)
+            } else if event.aggregate_type() != aggregate_type {
+                Some(StoreError::MismatchedEventAggregateTypes)
+            } else if event.version() != original_version + i as i32 + 1 {
+                Some(StoreError::IncorrectEventVersion)
+            } else {
+                None
+            };
+            if let Some(err) = err {
+                return Err(store_error(err, "save", Some(aggregate_type), Some(aggregate_id), Some(original_version), events));
+            }
+        }
+
+        let events = match self.encode(events.clone()).await {
+            Ok(encoded) => encoded,
+            Err(err) => {
+                return Err(store_error(
+                    err.into(),
+                    "save",
+                    Some(aggregate_type),
+                    Some(aggregate_id),
+                    Some(original_version),
+                    events,
+                ))
+            }
+        };
+
+        let mut log = self.inner.lock().await;
+        if log.current_version(aggregate_id) != original_version {
+            return Err(store_error(
+                StoreError::EventConflictFromOtherSave,
+                "save",
+                Some(aggregate_type),
+                Some(aggregate_id),
+                Some(original_version),
+                events,
+            ));
+        }
+
+        let mut saved = Vec::with_capacity(events.len());
+        for event in events {
+            let positioned = PositionedEvent {
+                position: log.events.len() as u64 + 1,
+                event,
+            };
+            log.events.push(positioned.clone());
+            saved.push(positioned);
+        }
+        log.streams.entry(aggregate_id).or_default().extend(saved.iter().cloned());
+
+        // Notify live subscribers, forgetting the ones that went away.
+        log.subscribers
+            .retain(|tx| saved.iter().all(|event| tx.send(event.clone()).is_ok()));
+        Ok(())
+    }
+
+    async fn load(&self, aggregate_id: Uuid) -> Result<Vec<Arc<dyn Event>>, EventStoreError> {
+        self.load_from(aggregate_id, 1).await
+    }
+
+    async fn load_from(&self, aggregate_id: Uuid, version: i32) -> Result<Vec<Arc<dyn Event>>, EventStoreError> {
+        let log = self.inner.lock().await;
+        Ok(log
+            .streams
+            .get(&aggregate_id)
+            .map(|stream| {
+                stream
+                    .iter()
+                    .filter(|e| e.event.version() >= version)
+                    .map(|e| e.event.clone())
+                    .collect()
+            })
+            .unwrap_or_default())
+    }
+
+    async fn head_version(&self, aggregate_id: Uuid) -> Result<i32, EventStoreError> {
+        let log = self.inner.lock().await;
+        Ok(log.current_version(aggregate_id))
+    }
+
+    async fn load_stream_from(&self, aggregate_id: Uuid, version: i32) -> Result<EventStream, EventStoreError> {
+        Ok(Box::pin(self.stream_chunks(move |log, offset, limit| {
+            let chunk = log
+                .streams
+                .get(&aggregate_id)
+                .map(|stream| &stream[offset.min(stream.len())..(offset + limit).min(stream.len())])
+                .unwrap_or_default();
+            let events = chunk
+                .iter()
+                .filter(|e| e.event.version() >= version)
+                .map(|e| e.event.clone())
+                .collect();
+            (chunk.len(), events)
+        })))
+    }
+
+    async fn close(&self) -> Result<(), Box<dyn Error + Send + Sync>> {
+        // Dropping the senders ends all live subscriptions.
+        self.inner.lock().await.subscribers.clear();
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl GlobalEventStore for MemoryEventStore {
+    async fn head_position(&self) -> Result<u64, EventStoreError> {
+        Ok(self.inner.lock().await.events.len() as u64)
+    }
+
+    async fn load_all(
+        &self,
+        from: u64,
+        matcher: Option<Arc<dyn EventMatcher>>,
+    ) -> Result<Vec<PositionedEvent>, EventStoreError> {
+        let log = self.inner.lock().await;
+        Ok(log
+            .events
+            .iter()
+            .skip(from as usize)
+            .filter(|e| matches_event(matcher.as_deref(), e.event.as_ref()))
+            .cloned()
+            .collect())
+    }
+
+    async fn load_all_stream(
+        &self,
+        from: u64,
+        matcher: Option<Arc<dyn EventMatcher>>,
+    ) -> Result<PositionedEventStream, EventStoreError> {
+        let from = from as usize;
+        Ok(Box::pin(self.stream_chunks(move |log, offset, limit| {
+            let start = (from + offset).min(log.events.len());
+            let chunk = &log.events[start..(start + limit).min(log.events.len())];
+            let events = chunk
+                .iter()
+                .filter(|e| matches_event(matcher.as_deref(), e.event.as_ref()))
+                .cloned()
+                .collect();
+            (chunk.len(), events)
+        })))
+    }
+
+    async fn subscribe(
+        &self,
+        from: u64,
+        matcher: Option<Arc<dyn EventMatcher>>,
+    ) -> Result<CatchUpSubscription, EventStoreError> {
+        // Register for live events and read the history under the same lock, so
+        // that no event can be saved in between.
+        let mut log = self.inner.lock().await;
+        let (tx, rx) = mpsc::unbounded_channel();
+        log.subscribers.push(tx);
+        let history = log
+            .events
+            .iter()
+            .skip(from as usize)
+            .filter(|e| matches_event(matcher.as_deref(), e.event.as_ref()))
+            .cloned()
+            .collect();
+        Ok(CatchUpSubscription::new(from, history, rx, matcher))
+    }
+}
+
+#[async_trait]
+impl EventStoreMaintenance for MemoryEventStore {
+    async fn replace(&self, event: Arc<dyn Event>) -> Result<(), MaintenanceError> {
+        let (aggregate_id, version) = (event.aggregate_id(), event.version());
+        let mut log = self.inner.lock().await;
+        let (position, current) = log.find(aggregate_id, version)?;
+        if current.aggregate_type() != event.aggregate_type() {
+            return Err(MaintenanceError::MismatchedEvent { aggregate_id, version });
+        }
+        log.set_event(position, event);
+        log.audit_trail
+            .push(AuditRecord::new(MaintenanceOperation::Replace, Some((aggregate_id, version)), 1));
+        Ok(())
+    }
+
+    async fn rename_event(&self, from: String, to: String) -> Result<usize, MaintenanceError> {
+        let mut log = self.inner.lock().await;
+        let renamed = log
+            .events
+            .iter()
+            .filter(|e| e.event.event_type() == from)
+            .map(|e| {
+                let renamed = e.event.with_event_type(&to).ok_or(MaintenanceError::NotRenamable {
+                    aggregate_id: e.event.aggregate_id(),
+                    version: e.event.version(),
+                })?;
+                Ok((e.position, renamed))
+            })
+            .collect::<Result<Vec<_>, MaintenanceError>>()?;
+        let count = renamed.len();
+        for (position, event) in renamed {
+            log.set_event(position, event);
+        }
+        log.audit_trail
+            .push(AuditRecord::new(MaintenanceOperation::Rename { from, to }, None, count));
+        Ok(count)
+    }
+
+    async fn delete_event_data(&self, aggregate_id: Uuid, version: i32) -> Result<(), MaintenanceError> {
+        let mut log = self.inner.lock().await;
+        let (position, current) = log.find(aggregate_id, version)?;
+        // Payloads are removed first, so a failed cleanup leaves the event untouched.
+        self.remove_payload(aggregate_id, version)?;
+        log.set_event(position, Arc::new(DeletedEvent::new(current.as_ref())));
+        log.audit_trail
+            .push(AuditRecord::new(MaintenanceOperation::DeleteData, Some((aggregate_id, version)), 1));
+        Ok(())
+    }
+
+    async fn redact_event(&self, aggregate_id: Uuid, version: i32, redact: &Redact) -> Result<(), MaintenanceError> {
+        let mut log = self.inner.lock().await;
+        let (position, current) = log.find(aggregate_id, version)?;
+        let redacted = redact(current.as_ref());
+        if redacted.aggregate_id() != aggregate_id || redacted.version() != version {
+            return Err(MaintenanceError::MismatchedEvent { aggregate_id, version });
+        }
+        self.remove_payload(aggregate_id, version)?;
+        log.set_event(position, redacted);
+        log.audit_trail
+            .push(AuditRecord::new(MaintenanceOperation::Redact, Some((aggregate_id, version)), 1));
+        Ok(())
+    }
+
+    async fn audit_trail(&self) -> Result<Vec<AuditRecord>, MaintenanceError> {
+        Ok(self.inner.lock().await.audit_trail.clone())
+    }
+}
+
+// MemorySnapshotStore keeps snapshots in memory, ordered by version per aggregate.
+// With a codec the snapshots are stored encoded, like a persistent store would.
+#[derive(Default)]
+pub struct MemorySnapshotStore {
+    snapshots: RwLock<HashMap<(Uuid, AggregateType), Vec<StoredSnapshot>>>,
+    codec: Option<Arc<dyn SnapshotCodec>>,
+}
+
+struct StoredSnapshot {
+    version: i32,
+    timestamp: SystemTime,
+    data: StoredData,
+}
+
+enum StoredData {
+    Decoded(Snapshot),
+    Encoded(Vec<u8>),
+}
+
+impl MemorySnapshotStore {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_codec(codec: Arc<dyn SnapshotCodec>) -> Self {
+        Self {
+            snapshots: RwLock::default(),
+            codec: Some(codec),
+        }
+    }
+
+    fn decode(&self, aggregate_id: Uuid, stored: &StoredSnapshot) -> Result<Option<Snapshot>, CodecError> {
+        match (&stored.data, &self.codec) {
+            (StoredData::Decoded(snapshot), _) => Ok(Some(snapshot.clone())),
+            (StoredData::Encoded(data), Some(codec)) => codec.unmarshal_snapshot(aggregate_id, data),
+            (StoredData::Encoded(_), None) => Err(CodecError::new("no snapshot codec")),
+        }
+    }
+}
+
+#[async_trait]
+impl SnapshotStore for MemorySnapshotStore {
+    async fn load_snapshot_at(
+        &self,
+        aggregate_id: Uuid,
+        aggregate_type: &AggregateType,
+        version: i32,
+    ) -> Result<Option<Snapshot>, Box<dyn Error + Send + Sync>> {
+        let snapshots = self.snapshots.read().unwrap();
+        let Some(stored) = snapshots.get(&(aggregate_id, aggregate_type.clone())) else {
+            return Ok(None);
+        };
+        // Snapshots that can't be used anymore are skipped in favour of older ones.
+        for stored in stored.iter().rev().filter(|s| s.version <= version) {
+            if let Some(snapshot) = self.decode(aggregate_id, stored)? {
+                return Ok(Some(snapshot));
+            }
+        }
+        Ok(None)
+    }
+
+    async fn save_snapshot(&self, aggregate_id: Uuid, snapshot: Snapshot) -> Result<(), Box<dyn Error + Send + Sync>> {
+        let key = (aggregate_id, snapshot.aggregate_type.clone());
+        let snapshot = StoredSnapshot {
+            version: snapshot.version,
+            timestamp: snapshot.timestamp,
+            data: match &self.codec {
+                Some(codec) => StoredData::Encoded(codec.marshal_snapshot(&snapshot)?),
+                None => StoredData::Decoded(snapshot),
+            },
+        };
+
+        let mut snapshots = self.snapshots.write().unwrap();
+        let stored = snapshots.entry(key).or_default();
+        match stored.binary_search_by_key(&snapshot.version, |s| s.version) {
+            Ok(i) => stored[i] = snapshot,
+            Err(i) => stored.insert(i, snapshot),
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl SnapshotStoreMaintenance for MemorySnapshotStore {
+    async fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>, Box<dyn Error + Send + Sync>> {
+        let snapshots = self.snapshots.read().unwrap();
+        Ok(snapshots
+            .iter()
+            .flat_map(|((aggregate_id, aggregate_type), stored)| {
+                stored.iter().map(move |s| SnapshotInfo {
+                    aggregate_id: *aggregate_id,
+                    aggregate_type: aggregate_type.clone(),
+                    version: s.version,
+                    timestamp: s.timestamp,
+                })
+            })
+            .collect())
+    }
+
+    async fn delete_snapshots(&self, snapshots: &[SnapshotInfo]) -> Result<usize, Box<dyn Error + Send + Sync>> {
+        let mut stored = self.snapshots.write().unwrap();
+        let mut deleted = 0;
+        for info in snapshots {
+            let key = (info.aggregate_id, info.aggregate_type.clone());
+            let Some(versions) = stored.get_mut(&key) else {
+                continue;
+            };
+            if let Ok(i) = versions.binary_search_by_key(&info.version, |s| s.version) {
+                versions.remove(i);
+                deleted += 1;
+            }
+            if versions.is_empty() {
+                stored.remove(&key);
+            }
+        }
+        Ok(deleted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eventstore::testutil::{event, event_at, TestEvent};
+    use crate::matcher::{EventType, MatchEvents};
+    use futures::StreamExt;
+    use std::any::Any;
+    use std::time::{Duration, SystemTime};
+    use serde::{Deserialize, Serialize};
+
+    #[tokio::test]
+    async fn test_save_and_load() {
+        let store = MemoryEventStore::new();
+        let id = Uuid::new_v4();
+
+        store.save(vec![event("Created", id, 1), event("Updated", id, 2)], 0).await.unwrap();
+        store.save(vec![event("Updated", id, 3)], 2).await.unwrap();
+
+        let events = store.load(id).await.unwrap();
+        assert_eq!(events.len(), 3);
+        assert_eq!(store.head_version(id).await.unwrap(), 3);
+        assert_eq!(store.head_version(Uuid::new_v4()).await.unwrap(), 0);
+        let events = store.load_from(id, 2).await.unwrap();
+        assert_eq!(events.len(), 2);
+        assert_eq!(events[0].version(), 2);
+        assert!(store.load(Uuid::new_v4()).await.unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_save_errors() {
+        let store = MemoryEventStore::new();
+        let id = Uuid::new_v4();
+
+        let err = store.save(vec![], 0).await.unwrap_err();
+        assert_eq!(err.to_string(), "event store: save: missing events");
+
+        let err = store.save(vec![event("Created", id, 2)], 0).await.unwrap_err();
+        assert!(err.to_string().starts_with("event store: save: incorrect event version"));
+
+        let err = store
+            .save(vec![event("Created", id, 1), event("Created", Uuid::new_v4(), 2)], 0)
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("event store: save: mismatching event aggregate IDs"));
+
+        store.save(vec![event("Created", id, 1)], 0).await.unwrap();
+        let err = store.save(vec![event("Created", id, 1)], 0).await.unwrap_err();
+        assert!(err.to_string().starts_with("event store: save: event conflict from other save"));
+    }
+
+    #[tokio::test]
+    async fn test_global_positions() {
+        let store = MemoryEventStore::new();
+        let id1 = Uuid::new_v4();
+        let id2 = Uuid::new_v4();
+
+        store.save(vec![event("Created", id1, 1)], 0).await.unwrap();
+        store.save(vec![event("Created", id2, 1)], 0).await.unwrap();
+        store.save(vec![event("Updated", id1, 2)], 1).await.unwrap();
+        assert_eq!(store.head_position().await.unwrap(), 3);
+
+        let all = store.load_all(0, None).await.unwrap();
+        let positions: Vec<u64> = all.iter().map(|e| e.position).collect();
+        assert_eq!(positions, vec![1, 2, 3]);
+        assert_eq!(all[1].event.aggregate_id(), id2);
+
+        let rest = store.load_all(2, None).await.unwrap();
+        assert_eq!(rest.len(), 1);
+        assert_eq!(rest[0].position, 3);
+
+        let matcher: Arc<dyn EventMatcher> = Arc::new(MatchEvents::new(vec![EventType::from_name("Updated")]));
+        let updated = store.load_all(0, Some(matcher)).await.unwrap();
+        assert_eq!(updated.len(), 1);
+        assert_eq!(updated[0].position, 3);
+    }
+
+    #[tokio::test]
+    async fn test_load_stream() {
+        let store = MemoryEventStore::with_stream_buffer(2);
+        let id = Uuid::new_v4();
+        let events: Vec<Arc<dyn Event>> = (1..=5).map(|v| event("Updated", id, v)).collect();
+        store.save(events, 0).await.unwrap();
+        store.save(vec![event("Created", Uuid::new_v4(), 1)], 0).await.unwrap();
+
+        let versions: Vec<i32> = store
+            .load_stream(id)
+            .await
+            .unwrap()
+            .map(|e| e.unwrap().version())
+            .collect()
+            .await;
+        assert_eq!(versions, vec![1, 2, 3, 4, 5]);
+
+        let versions: Vec<i32> = store
+            .load_stream_from(id, 4)
+            .await
+            .unwrap()
+            .map(|e| e.unwrap().version())
+            .collect()
+            .await;
+        assert_eq!(versions, vec![4, 5]);
+
+        let mut empty = store.load_stream(Uuid::new_v4()).await.unwrap();
+        assert!(empty.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_load_ranges() {
+        let store = MemoryEventStore::with_stream_buffer(2);
+        let id = Uuid::new_v4();
+        let start = SystemTime::UNIX_EPOCH;
+        let events: Vec<Arc<dyn Event>> = (1..=5)
+            .map(|v| event_at("Updated", id, v, start + Duration::from_secs(v as u64 * 10)))
+            .collect();
+        store.save(events, 0).await.unwrap();
+
+        let versions = |events: Vec<Arc<dyn Event>>| events.iter().map(|e| e.version()).collect::<Vec<_>>();
+        assert_eq!(versions(store.load_to(id, 2).await.unwrap()), vec![1, 2]);
+        assert_eq!(versions(store.load_range(id, 2, 4).await.unwrap()), vec![2, 3, 4]);
+        assert_eq!(versions(store.load_range(id, 4, 2).await.unwrap()), Vec::<i32>::new());
+        assert_eq!(versions(store.load_as_of(id, start + Duration::from_secs(35)).await.unwrap()), vec![1, 2, 3]);
+        assert_eq!(versions(store.load_as_of(id, start).await.unwrap()), Vec::<i32>::new());
+    }
+
+    #[tokio::test]
+    async fn test_load_all_stream() {
+        let store = MemoryEventStore::with_stream_buffer(1);
+        let id = Uuid::new_v4();
+        store.save(vec![event("Created", id, 1), event("Updated", id, 2), event("Updated", id, 3)], 0).await.unwrap();
+
+        let matcher: Arc<dyn EventMatcher> = Arc::new(MatchEvents::new(vec![EventType::from_name("Updated")]));
+        let positions: Vec<u64> = store
+            .load_all_stream(0, Some(matcher))
+            .await
+            .unwrap()
+            .map(|e| e.unwrap().position)
+            .collect()
+            .await;
+        assert_eq!(positions, vec![2, 3]);
+
+        let positions: Vec<u64> = store
+            .load_all_stream(2, None)
+            .await
+            .unwrap()
+            .map(|e| e.unwrap().position)
+            .collect()
+            .await;
+        assert_eq!(positions, vec![3]);
+    }
+
+    #[tokio::test]
+    async fn test_catch_up_subscription() {
+        let store = MemoryEventStore::new();
+        let id = Uuid::new_v4();
+        store.save(vec![event("Created", id, 1), event("Updated", id, 2)], 0).await.unwrap();
+
+        let matcher: Arc<dyn EventMatcher> = Arc::new(MatchEvents::new(vec![EventType::from_name("Updated")]));
+        let mut subscription = store.subscribe(0, Some(matcher)).await.unwrap();
+        assert!(!subscription.is_live());
+
+        // History first.
+        assert_eq!(subscription.next().await.unwrap().position, 2);
+        assert!(subscription.is_live());
+
+        // Then live events, still filtered.
+        store.save(vec![event("Deleted", id, 3), event("Updated", id, 4)], 2).await.unwrap();
+        let live = subscription.next().await.unwrap();
+        assert_eq!(live.position, 4);
+        assert_eq!(live.event.version(), 4);
+        assert_eq!(subscription.position(), 4);
+
+        store.close().await.unwrap();
+        assert!(subscription.next().await.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_maintenance() {
+        let store = MemoryEventStore::new();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        store.save(vec![event("Created", a, 1), event("Updated", a, 2)], 0).await.unwrap();
+        store.save(vec![event("Created", b, 1)], 0).await.unwrap();
+
+        // Replace by aggregate ID and version.
+        store.replace(event("Corrected", a, 2)).await.unwrap();
+        let err = store.replace(event("Corrected", a, 3)).await.unwrap_err();
+        assert!(matches!(err, MaintenanceError::EventNotFound { version: 3, .. }));
+
+        // Rename across all streams.
+        assert_eq!(store.rename_event("Created".to_string(), "Started".to_string()).await.unwrap(), 2);
+        let loaded = store.load(a).await.unwrap();
+        let types: Vec<String> = loaded.iter().map(|e| e.event_type()).collect();
+        assert_eq!(types, vec!["Started", "Corrected"]);
+        assert!((loaded[0].as_ref() as &dyn Any).is::<TestEvent>());
+        let all = store.load_all(0, None).await.unwrap();
+        assert_eq!(all[2].event.event_type(), "Started");
+
+        // Delete and redact payloads.
+        store.delete_event_data(b, 1).await.unwrap();
+        let deleted = store.load(b).await.unwrap().remove(0);
+        assert!((deleted.as_ref() as &dyn Any).is::<DeletedEvent>());
+        assert_eq!(deleted.event_type(), "Started");
+
+        store
+            .redact_event(a, 1, &|e: &dyn Event| event("Redacted", e.aggregate_id(), e.version()))
+            .await
+            .unwrap();
+        assert_eq!(store.load(a).await.unwrap()[0].event_type(), "Redacted");
+        let err = store
+            .redact_event(a, 1, &|e: &dyn Event| event("Redacted", e.aggregate_id(), 5))
+            .await
+            .unwrap_err();
+        assert!(matches!(err, MaintenanceError::MismatchedEvent { .. }));
+
+        let trail: Vec<(MaintenanceOperation, usize)> = store
+            .audit_trail()
+            .await
+            .unwrap()
+            .into_iter()
+            .map(|r| (r.operation, r.affected))
+            .collect();
+        assert_eq!(
+            trail,
+            vec![
+                (MaintenanceOperation::Replace, 1),
+                (
+                    MaintenanceOperation::Rename {
+                        from: "Created".to_string(),
+                        to: "Started".to_string()
+                    },
+                    2
+                ),
+                (MaintenanceOperation::DeleteData, 1),
+                (MaintenanceOperation::Redact, 1),
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_rename_deleted_event() {
+        let store = MemoryEventStore::new();
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        store.save(vec![event("Created", a, 1)], 0).await.unwrap();
+        store.save(vec![event("Created", b, 1)], 0).await.unwrap();
+        store.delete_event_data(a, 1).await.unwrap();
+
+        assert_eq!(store.rename_event("Created".to_string(), "Started".to_string()).await.unwrap(), 2);
+        let deleted = store.load(a).await.unwrap().remove(0);
+        assert!((deleted.as_ref() as &dyn Any).is::<DeletedEvent>());
+        assert_eq!(deleted.event_type(), "Started");
+        assert_eq!(store.load(b).await.unwrap()[0].event_type(), "Started");
+    }
+
+    #[derive(Debug, Serialize, Deserialize)]
+    struct Deposited {
+        amount: u32,
+    }
+
+    impl crate::event::EventData for Deposited {}
+
+    struct DepositEvent {
+        aggregate_id: Uuid,
+        version: i32,
+        data: Deposited,
+    }
+
+    impl Event for DepositEvent {
+        fn event_type(&self) -> String {
+            "MemoryStoreDeposited".to_string()
+        }
+
+        fn aggregate_type(&self) -> String {
+            "Account".to_string()
+        }
+
+        fn aggregate_id(&self) -> Uuid {
+            self.aggregate_id
+        }
+
+        fn version(&self) -> i32 {
+            self.version
+        }
+
+        fn timestamp(&self) -> SystemTime {
+            SystemTime::UNIX_EPOCH
+        }
+
+        fn data(&self) -> Option<&dyn crate::event::EventData> {
+            Some(&self.data)
+        }
+    }
+
+    impl std::fmt::Display for DepositEvent {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            write!(f, "Deposited({})", self.data.amount)
+        }
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_save_with_codec() {
+        crate::event::register_event_data(
+            "MemoryStoreDeposited".to_string(),
+            Box::new(|| Box::new(Deposited { amount: 0 })),
+        );
+        let store = MemoryEventStore::new().with_codec(Arc::new(crate::codec::json::event::EventCodec::default()));
+        let id = Uuid::new_v4();
+        let deposit = DepositEvent { aggregate_id: id, version: 1, data: Deposited { amount: 5 } };
+        store.save(vec![Arc::new(deposit)], 0).await.unwrap();
+
+        let stored = store.load(id).await.unwrap().remove(0);
+        assert!((stored.as_ref() as &dyn Any).is::<DecodedEvent>());
+        assert_eq!((stored.event_type(), stored.version()), ("MemoryStoreDeposited".to_string(), 1));
+        assert_eq!(stored.timestamp(), SystemTime::UNIX_EPOCH);
+        let data = (stored.data().unwrap() as &dyn Any).downcast_ref::<Deposited>().unwrap();
+        assert_eq!(data.amount, 5);
+
+        // Decoded events can be renamed by maintenance.
+        assert_eq!(store.rename_event("MemoryStoreDeposited".to_string(), "Credited".to_string()).await.unwrap(), 1);
+        assert_eq!(store.load(id).await.unwrap()[0].event_type(), "Credited");
+    }
+
+    struct FailingCodec;
+
+    #[async_trait]
+    impl codec_main::EventCodec for FailingCodec {
+        async fn marshal_event(&self, _ctx: &Context, _event: Arc<dyn codec_main::Event>) -> Result<Vec<u8>, CodecError> {
+            Err(CodecError::new("not encodable"))
+        }
+
+        async fn unmarshal_event(
+            &self,
+            _ctx: Context,
+            _data: Vec<u8>,
+        ) -> Result<(Arc<dyn codec_main::Event>, Context), CodecError> {
+            Err(CodecError::new("not decodable"))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_save_with_failing_codec() {
+        let store = MemoryEventStore::new().with_codec(Arc::new(FailingCodec));
+        let id = Uuid::new_v4();
+        let deposit = DepositEvent { aggregate_id: id, version: 1, data: Deposited { amount: 1 } };
+        let err = store.save(vec![Arc::new(deposit)], 0).await.unwrap_err();
+        assert!(err.to_string().contains("not encodable"));
+        assert_eq!(store.head_version(id).await.unwrap(), 0);
+    }
+
+    struct FailingCleanup;
+
+    impl PayloadCleanup for FailingCleanup {
+        fn payload_removed(&self, _aggregate_id: Uuid, _version: i32) -> Result<(), MaintenanceError> {
+            Err(MaintenanceError::Store("blob store unavailable".to_string()))
+        }
+    }
+
+    #[tokio::test]
+    async fn test_failed_payload_cleanup() {
+        let store = MemoryEventStore::new().with_payload_cleanup(Arc::new(FailingCleanup));
+        let a = Uuid::new_v4();
+        store.save(vec![event("Created", a, 1)], 0).await.unwrap();
+
+        assert!(store.delete_event_data(a, 1).await.is_err());
+        assert!(store
+            .redact_event(a, 1, &|e: &dyn Event| event("Redacted", e.aggregate_id(), e.version()))
+            .await
+            .is_err());
+        let stored = store.load(a).await.unwrap().remove(0);
+        assert!((stored.as_ref() as &dyn Any).is::<TestEvent>());
+        assert_eq!(stored.event_type(), "Created");
+        assert!(store.audit_trail().await.unwrap().is_empty());
+    }
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct TestSnapshotData {
+        total: i32,
+    }
+
+    impl crate::snapshot::SnapshotData for TestSnapshotData {}
+
+    fn snapshot(version: i32, total: i32) -> Snapshot {
+        Snapshot {
+            version,
+            aggregate_type: AggregateType::new("TestAggregate".to_string()),
+            timestamp: SystemTime::now(),
+            state: Box::new(TestSnapshotData { total }),
+        }
+    }
+
+    #[tokio::test]
+    async fn test_snapshot_store() {
+        let store = MemorySnapshotStore::new();
+        let id = Uuid::new_v4();
+        let aggregate_type = AggregateType::new("TestAggregate".to_string());
+        assert!(store.load_snapshot(id, &aggregate_type).await.unwrap().is_none());
+
+        store.save_snapshot(id, snapshot(10, 1)).await.unwrap();
+        store.save_snapshot(id, snapshot(5, 2)).await.unwrap();
+        store.save_snapshot(id, snapshot(20, 3)).await.unwrap();
+
+        let latest = store.load_snapshot(id, &aggregate_type).await.unwrap().unwrap();
+        assert_eq!(latest.version, 20);
+
+        let at = store.load_snapshot_at(id, &aggregate_type, 15).await.unwrap().unwrap();
+        assert_eq!(at.version, 10);
+        let data = at.state.as_any().downcast_ref::<TestSnapshotData>().unwrap();
+        assert_eq!(data.total, 1);
+        assert!(store.load_snapshot_at(id, &aggregate_type, 4).await.unwrap().is_none());
+
+        // Snapshots are keyed by aggregate type too.
+        let other = AggregateType::new("OtherAggregate".to_string());
+        assert!(store.load_snapshot(id, &other).await.unwrap().is_none());
+
+        // Saving the same version replaces the snapshot.
+        store.save_snapshot(id, snapshot(20, 4)).await.unwrap();
+        let latest = store.load_snapshot(id, &aggregate_type).await.unwrap().unwrap();
+        assert_eq!(latest.state.as_any().downcast_ref::<TestSnapshotData>().unwrap().total, 4);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_snapshot_store_with_codec() {
+        let registry = Arc::new(crate::snapshot::SnapshotFactoryRegistry::new());
+        let aggregate_type = AggregateType::new("TestAggregate".to_string());
+        registry.register_snapshot_data(aggregate_type.clone(), |_| Box::new(TestSnapshotData { total: 0 }));
+        let store = MemorySnapshotStore::with_codec(Arc::new(crate::codec::json::snapshot::SnapshotCodec::new(
+            registry.clone(),
+        )));
+
+        let id = Uuid::new_v4();
+        store.save_snapshot(id, snapshot(3, 7)).await.unwrap();
+        let loaded = store.load_snapshot(id, &aggregate_type).await.unwrap().unwrap();
+        let data = loaded.state.as_any().downcast_ref::<TestSnapshotData>().unwrap();
+        assert_eq!(data.total, 7);
+
+        // Outdated snapshots are ignored so the aggregate is replayed from events.
+        registry.set_schema_version(aggregate_type.clone(), 2);
+        assert!(store.load_snapshot(id, &aggregate_type).await.unwrap().is_none());
+    }
+}
@@ -1,24 +1,32 @@
 mod uuid;
-mod codec_main;
-mod aggregatestore;
-mod aggregate;
+pub mod codec_main;
+pub mod codec;
+pub mod aggregatestore;
+pub mod aggregate;
+pub mod catalog;
+pub mod checkpoint;
 mod entity;
-mod event;
-mod command_main;
+pub mod event;
+pub mod command_main;
 mod command_check;
 mod commandhandler;
 mod compare;
-mod context;
+pub mod context;
 mod eventbus;
-mod eventhandler;
-mod eventmaintenance;
+pub mod eventhandler;
+pub mod eventmaintenance;
 mod eventsource;
-mod eventstore;
-mod matcher;
+pub mod eventstore;
+pub mod matcher;
 mod middleware;
-mod outbox;
-mod repo;
-mod snapshot;
+pub mod outbox;
+pub mod projector;
+pub mod repo;
+#[cfg(feature = "schema")]
+pub mod schema;
+pub mod snapshot;
+pub mod snapshotmaintenance;
+pub mod upcast;
 
 pub fn add(left: usize, right: usize) -> usize {
     left + right
@@ -7,6 +7,20 @@ pub struct EventType(Uuid);
 #[derive(Debug, PartialEq, Eq, Clone, Copy)]
 pub struct AggregateType(Uuid);
 
+impl EventType {
+    // Derive a stable event type from its name using a name-based (v5) UUID.
+    pub fn from_name(name: &str) -> Self {
+        EventType(Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()))
+    }
+}
+
+impl AggregateType {
+    // Derive a stable aggregate type from its name using a name-based (v5) UUID.
+    pub fn from_name(name: &str) -> Self {
+        AggregateType(Uuid::new_v5(&Uuid::NAMESPACE_OID, name.as_bytes()))
+    }
+}
+
 // Event trait with basic methods for EventType and AggregateType.
 pub trait Event {
     fn event_type(&self) -> EventType;
@@ -14,7 +28,7 @@ pub trait Event {
 }
 
 // Trait for EventMatcher.
-pub trait EventMatcher {
+pub trait EventMatcher: Send + Sync {
     fn matches(&self, event: &dyn Event) -> bool;
 }
 
@@ -174,4 +188,14 @@ mod tests {
         let event = TestEvent::new(EventType(Uuid::new_v4()), aggregate_type1);
         assert!(!matcher_all.matches(&event));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_types_from_name() {
+        assert_eq!(EventType::from_name("Created"), EventType::from_name("Created"));
+        assert_ne!(EventType::from_name("Created"), EventType::from_name("Deleted"));
+
+        let matcher = MatchEvents::new(vec![EventType::from_name("Created")]);
+        let event = TestEvent::new(EventType::from_name("Created"), AggregateType::from_name("Order"));
+        assert!(matcher.matches(&event));
+    }
+}
@@ -1,13 +1,23 @@
 use crossbeam_channel::{unbounded, Receiver, Sender};
+use std::collections::VecDeque;
 use std::fmt;
 use std::error::Error;
 use std::sync::{Arc, Mutex};
 use std::thread;
 
+use crate::codec_main::{self, CodecError};
+use crate::context::Context;
+use crate::event::EventData;
+
 // Define the Event trait with Clone for cloning events.
 pub trait Event: EventClone + fmt::Debug {
     fn event_type(&self) -> String;
     fn to_string(&self) -> String;
+
+    // Data returns the registered event data, if the event carries any.
+    fn data(&self) -> Option<&dyn EventData> {
+        None
+    }
 }
 
 // Helper trait to allow cloning trait objects.
@@ -79,29 +89,121 @@ impl Error for OutboxError {
     }
 }
 
+// CodecEvent adapts an event of the codecs to the outbox.
+#[derive(Debug, Clone)]
+pub struct CodecEvent(Arc<dyn codec_main::Event>);
+
+impl CodecEvent {
+    pub fn new(event: Arc<dyn codec_main::Event>) -> Self {
+        CodecEvent(event)
+    }
+}
+
+impl Event for CodecEvent {
+    fn event_type(&self) -> String {
+        self.0.event_type()
+    }
+
+    fn to_string(&self) -> String {
+        format!("{}({}, v{})", self.0.event_type(), self.0.aggregate_id(), self.0.version())
+    }
+
+    fn data(&self) -> Option<&dyn EventData> {
+        self.0.data()
+    }
+}
+
+// Handlers of the outbox with the matchers selecting their events.
+type Handlers = Vec<(Box<dyn EventMatcher>, Box<dyn EventHandler>)>;
+
+// An event added to the outbox and not yet handed to the handlers.
+enum Pending {
+    Event(Arc<dyn codec_main::Event>),
+    Encoded(Vec<u8>),
+}
+
 // Basic implementation of an Outbox using crossbeam-channel for error handling.
+// Events added to it are queued until flushed to the handlers. With a codec they
+// are queued encoded, like a persistent outbox stores them, and decoded when flushed.
 pub struct SimpleOutbox {
-    handlers: Arc<Mutex<Vec<(Box<dyn EventMatcher>, Box<dyn EventHandler>)>>>,
+    handlers: Mutex<Handlers>,
     error_channel: Sender<Box<dyn Error>>,
     error_receiver: Receiver<Box<dyn Error>>,
+    pending: Mutex<VecDeque<Pending>>,
+    codec: Option<Arc<dyn codec_main::EventCodec>>,
 }
 
 impl SimpleOutbox {
     pub fn new() -> Self {
         let (sender, receiver) = unbounded(); // Use crossbeam channel for multiple receivers.
         SimpleOutbox {
-            handlers: Arc::new(Mutex::new(Vec::new())),
+            handlers: Mutex::new(Vec::new()),
             error_channel: sender,
             error_receiver: receiver,
+            pending: Mutex::new(VecDeque::new()),
+            codec: None,
+        }
+    }
+
+    // Queue events encoded with a codec.
+    pub fn with_codec(mut self, codec: Arc<dyn codec_main::EventCodec>) -> Self {
+        self.codec = Some(codec);
+        self
+    }
+
+    // Add queues an event, encoding it with the codec if there is one.
+    pub async fn add(&self, ctx: &Context, event: Arc<dyn codec_main::Event>) -> Result<(), CodecError> {
+        let pending = match &self.codec {
+            Some(codec) => Pending::Encoded(codec.marshal_event(ctx, event).await?),
+            None => Pending::Event(event),
+        };
+        self.pending.lock().unwrap().push_back(pending);
+        Ok(())
+    }
+
+    // Flush hands the queued events to the matching handlers in the order they
+    // were added, returning how many were flushed. An event that can't be
+    // decoded stays queued together with the events after it.
+    pub async fn flush(&self) -> Result<usize, CodecError> {
+        let mut flushed = 0;
+        loop {
+            let Some(pending) = self.pending.lock().unwrap().pop_front() else {
+                return Ok(flushed);
+            };
+            let event = match pending {
+                Pending::Event(event) => event,
+                Pending::Encoded(data) => match self.decode(data.clone()).await {
+                    Ok(event) => event,
+                    Err(err) => {
+                        self.pending.lock().unwrap().push_front(Pending::Encoded(data));
+                        return Err(err);
+                    }
+                },
+            };
+            // Handler errors are reported on the error channel.
+            let _ = self.handle_event(&CodecEvent::new(event));
+            flushed += 1;
         }
     }
 
+    async fn decode(&self, data: Vec<u8>) -> Result<Arc<dyn codec_main::Event>, CodecError> {
+        let codec = self.codec.as_ref().ok_or_else(|| CodecError::new("no event codec"))?;
+        let (event, _) = codec.unmarshal_event(Context::new(), data).await?;
+        Ok(event)
+    }
+
     // Simulates sending errors to the channel.
     fn send_error(&self, err: Box<dyn Error>) {
         self.error_channel.send(err).unwrap();
     }
 }
 
+impl Default for SimpleOutbox {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
 impl EventHandler for SimpleOutbox {
     fn handle_event(&self, event: &dyn Event) -> Result<(), Box<dyn Error>> {
         let handlers = self.handlers.lock().unwrap();
@@ -289,6 +391,64 @@ mod tests {
         assert!(result.is_ok());
     }
 
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_flush_encoded_events() {
+        use crate::codec_main::BasicEvent;
+        use crate::eventstore::testutil;
+        use crate::eventstore::EncodableEvent;
+        use uuid::Uuid;
+
+        #[derive(Debug, serde::Serialize, serde::Deserialize)]
+        struct Shipped {
+            parcels: u32,
+        }
+
+        impl EventData for Shipped {}
+
+        // Handler recording the events it handled with their data.
+        struct RecordingHandler {
+            sender: mpsc::Sender<(String, Option<u32>)>,
+        }
+
+        impl EventHandler for RecordingHandler {
+            fn handle_event(&self, event: &dyn Event) -> Result<(), Box<dyn Error>> {
+                let parcels = event
+                    .data()
+                    .and_then(|data| (data as &dyn std::any::Any).downcast_ref::<Shipped>())
+                    .map(|data| data.parcels);
+                self.sender.send((event.event_type(), parcels)).unwrap();
+                Ok(())
+            }
+        }
+
+        crate::event::register_event_data("OutboxShipped".to_string(), Box::new(|| Box::new(Shipped { parcels: 0 })));
+        let outbox = SimpleOutbox::new().with_codec(Arc::new(crate::codec::json::event::EventCodec::default()));
+        let (sender, receiver) = mpsc::channel();
+        outbox
+            .add_handler(Box::new(MockEventMatcher::new("OutboxShipped".to_string())), Box::new(RecordingHandler { sender }))
+            .unwrap();
+
+        let shipped = BasicEvent::new(
+            "OutboxShipped".to_string(),
+            Some(Box::new(Shipped { parcels: 2 })),
+            std::time::SystemTime::now(),
+            "Order".to_string(),
+            Uuid::new_v4(),
+            1,
+        );
+        outbox.add(&Context::new(), Arc::new(shipped)).await.unwrap();
+        // Stored events are added through the codec bridge of the event store.
+        let stored = testutil::event("OutboxShipped", Uuid::new_v4(), 1);
+        outbox.add(&Context::new(), Arc::new(EncodableEvent::new(stored))).await.unwrap();
+        assert!(receiver.try_recv().is_err());
+
+        assert_eq!(outbox.flush().await.unwrap(), 2);
+        let handled: Vec<_> = receiver.try_iter().collect();
+        assert_eq!(handled, vec![("OutboxShipped".to_string(), Some(2)), ("OutboxShipped".to_string(), None)]);
+        assert_eq!(outbox.flush().await.unwrap(), 0);
+    }
+
     // Test case for starting and closing the outbox.
     #[test]
     fn test_start_and_close_outbox() {
@@ -0,0 +1,473 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, RwLock};
+use futures::StreamExt;
+use thiserror::Error;
+use tokio::sync::watch;
+use uuid::Uuid;
+
+use crate::eventstore::{matches_event, Event, EventStoreError, GlobalEventStore, PositionedEvent};
+use crate::matcher::EventMatcher;
+use crate::repo::{Entity, ReadRepo, ReadWriteRepo, RepoError, WriteRepo};
+
+// A read model repository that can be shared between tasks.
+pub type SharedRepo = Arc<dyn ReadWriteRepo + Send + Sync>;
+
+// Errors related to projecting events into read models.
+#[derive(Error, Debug)]
+pub enum ProjectorError {
+    #[error("could not project event: {0}")]
+    Projection(String),
+
+    #[error("{0}")]
+    Repo(String),
+
+    #[error(transparent)]
+    EventStore(#[from] EventStoreError),
+
+    #[error("projection rebuild aborted")]
+    Aborted,
+}
+
+// Projector turns events into read model entities.
+pub trait Projector: Send + Sync {
+    fn projector_type(&self) -> String;
+
+    // Project applies an event to the current entity, if any. Returning None removes the entity.
+    fn project(
+        &self,
+        event: &dyn Event,
+        entity: Option<Box<dyn Entity>>,
+    ) -> Result<Option<Box<dyn Entity>>, ProjectorError>;
+}
+
+// Apply a single event to the entity with the event's aggregate ID in the repo.
+pub fn project_event(projector: &dyn Projector, repo: &dyn ReadWriteRepo, event: &dyn Event) -> Result<(), ProjectorError> {
+    let id = event.aggregate_id();
+    let current = match repo.find(id) {
+        Ok(entity) => Some(entity),
+        Err(err) if err.is_not_found() => None,
+        Err(err) => return Err(ProjectorError::Repo(err.to_string())),
+    };
+
+    let existed = current.is_some();
+    let result = match projector.project(event, current)? {
+        Some(entity) => repo.save(entity),
+        None if existed => repo.remove(id),
+        None => Ok(()),
+    };
+    result.map_err(|err| ProjectorError::Repo(err.to_string()))
+}
+
+// SwappableRepo serves reads and writes from the current repo, which can be
+// replaced atomically by a rebuilt one. Live projectors write through project,
+// which records the global position of the last projected event so that a
+// rebuilt repo is only swapped in once it caught up with them.
+pub struct SwappableRepo {
+    current: RwLock<Current>,
+}
+
+struct Current {
+    repo: SharedRepo,
+    position: u64,
+}
+
+impl SwappableRepo {
+    pub fn new(repo: SharedRepo) -> Self {
+        Self {
+            current: RwLock::new(Current { repo, position: 0 }),
+        }
+    }
+
+    // Current returns the repo currently serving requests.
+    pub fn current(&self) -> SharedRepo {
+        self.current.read().unwrap().repo.clone()
+    }
+
+    // Position returns the global position of the last event projected into the current repo.
+    pub fn position(&self) -> u64 {
+        self.current.read().unwrap().position
+    }
+
+    // Swap replaces the current repo and returns the previous one.
+    pub fn swap(&self, repo: SharedRepo) -> SharedRepo {
+        std::mem::replace(&mut self.current.write().unwrap().repo, repo)
+    }
+
+    // SwapAt replaces the current repo with one holding all events up to and
+    // including `position`, and returns the previous one. Nothing is swapped if
+    // events after the position were already projected into the current repo.
+    pub fn swap_at(&self, repo: SharedRepo, position: u64) -> Option<SharedRepo> {
+        let mut current = self.current.write().unwrap();
+        if current.position > position {
+            return None;
+        }
+        current.position = position;
+        Some(std::mem::replace(&mut current.repo, repo))
+    }
+
+    // Project applies an event to the current repo, skipping events at or before
+    // the position of the current repo. Returns whether the event was applied.
+    pub fn project(&self, projector: &dyn Projector, event: &PositionedEvent) -> Result<bool, ProjectorError> {
+        let mut current = self.current.write().unwrap();
+        if event.position <= current.position {
+            return Ok(false);
+        }
+        project_event(projector, current.repo.as_ref(), event.event.as_ref())?;
+        current.position = event.position;
+        Ok(true)
+    }
+}
+
+impl ReadRepo for SwappableRepo {
+    fn inner_repo(&self) -> Option<Box<dyn ReadRepo>> {
+        None
+    }
+
+    fn find(&self, id: Uuid) -> Result<Box<dyn Entity>, RepoError> {
+        self.current().find(id)
+    }
+
+    fn find_all(&self) -> Result<Vec<Box<dyn Entity>>, RepoError> {
+        self.current().find_all()
+    }
+
+    fn close(&self) -> Result<(), RepoError> {
+        self.current().close()
+    }
+}
+
+impl WriteRepo for SwappableRepo {
+    fn save(&self, entity: Box<dyn Entity>) -> Result<(), RepoError> {
+        self.current().save(entity)
+    }
+
+    fn remove(&self, id: Uuid) -> Result<(), RepoError> {
+        self.current().remove(id)
+    }
+}
+
+impl ReadWriteRepo for SwappableRepo {}
+
+// Progress of a projection rebuild.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RebuildProgress {
+    // Global position of the last replayed event.
+    pub position: u64,
+    // Head of the event log when progress was last reported.
+    pub head: u64,
+    // Number of events passed to the projector.
+    pub projected: usize,
+    // Set once the rebuilt repo has been swapped in.
+    pub done: bool,
+}
+
+// Handle used to abort a running rebuild from another task.
+#[derive(Clone)]
+pub struct AbortHandle(Arc<AtomicBool>);
+
+impl AbortHandle {
+    pub fn abort(&self) {
+        self.0.store(true, Ordering::SeqCst);
+    }
+}
+
+// ProjectionRebuild replays all matching events into a fresh repo while the
+// current one keeps serving reads, and swaps them once the replay caught up
+// with the head of the log and with the live projector writing through the
+// SwappableRepo. Events after the returned position are left to the live
+// projector, which skips the ones it sees again after the swap.
+pub struct ProjectionRebuild {
+    store: Arc<dyn GlobalEventStore>,
+    projector: Arc<dyn Projector>,
+    matcher: Option<Arc<dyn EventMatcher>>,
+    target: Arc<SwappableRepo>,
+    progress: watch::Sender<RebuildProgress>,
+    aborted: Arc<AtomicBool>,
+}
+
+impl ProjectionRebuild {
+    pub fn new(
+        store: Arc<dyn GlobalEventStore>,
+        projector: Arc<dyn Projector>,
+        matcher: Option<Arc<dyn EventMatcher>>,
+        target: Arc<SwappableRepo>,
+    ) -> Self {
+        Self {
+            store,
+            projector,
+            matcher,
+            target,
+            progress: watch::Sender::new(RebuildProgress::default()),
+            aborted: Arc::new(AtomicBool::new(false)),
+        }
+    }
+
+    // Progress returns a receiver that is updated after every replayed event.
+    pub fn progress(&self) -> watch::Receiver<RebuildProgress> {
+        self.progress.subscribe()
+    }
+
+    pub fn abort_handle(&self) -> AbortHandle {
+        AbortHandle(self.aborted.clone())
+    }
+
+    // Run replays the log into the fresh repo and swaps it in. The fresh repo is
+    // only swapped in after it caught up, so if the rebuild is aborted or fails
+    // the current repo is left untouched.
+    pub async fn run(&self, fresh: SharedRepo) -> Result<RebuildProgress, ProjectorError> {
+        let mut progress = RebuildProgress::default();
+        loop {
+            // Keep streaming until no new events showed up since the last pass.
+            while self.replay(fresh.as_ref(), &mut progress).await? {}
+
+            if self.aborted.load(Ordering::SeqCst) {
+                return Err(ProjectorError::Aborted);
+            }
+            // The live projector may have projected events saved after the last
+            // pass into the current repo, replay them before swapping.
+            if self.target.swap_at(fresh.clone(), progress.position).is_some() {
+                break;
+            }
+        }
+        progress.done = true;
+        self.progress.send_replace(progress);
+        Ok(progress)
+    }
+
+    // Replay projects all events after the current position into the repo,
+    // returning whether any event was replayed.
+    async fn replay(&self, repo: &dyn ReadWriteRepo, progress: &mut RebuildProgress) -> Result<bool, ProjectorError> {
+        progress.head = self.store.head_position().await?;
+        let mut events = self.store.load_all_stream(progress.position, None).await?;
+        let mut replayed = false;
+        while let Some(event) = events.next().await {
+            let event = event?;
+            replayed = true;
+            if self.aborted.load(Ordering::SeqCst) {
+                return Err(ProjectorError::Aborted);
+            }
+            if matches_event(self.matcher.as_deref(), event.event.as_ref()) {
+                project_event(self.projector.as_ref(), repo, event.event.as_ref())?;
+                progress.projected += 1;
+            }
+            progress.position = event.position;
+            self.progress.send_replace(*progress);
+        }
+        Ok(replayed)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eventstore::memory::MemoryEventStore;
+    use crate::eventstore::testutil::event;
+    use crate::eventstore::{CatchUpSubscription, EventStore, PositionedEvent, PositionedEventStream};
+    use crate::matcher::{EventType, MatchEvents};
+    use crate::repo::memory::MemoryRepo;
+    use async_trait::async_trait;
+    use futures::stream;
+    use std::any::Any;
+    use std::error::Error as StdError;
+    use std::sync::Mutex;
+
+    // Read model counting the events of an aggregate.
+    #[derive(Debug, Clone)]
+    struct Counter {
+        id: Uuid,
+        count: usize,
+    }
+
+    impl Entity for Counter {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+    }
+
+    struct CounterProjector;
+
+    impl Projector for CounterProjector {
+        fn projector_type(&self) -> String {
+            "counter".to_string()
+        }
+
+        fn project(
+            &self,
+            event: &dyn Event,
+            entity: Option<Box<dyn Entity>>,
+        ) -> Result<Option<Box<dyn Entity>>, ProjectorError> {
+            let count = match entity {
+                Some(entity) => {
+                    let any: Box<dyn Any> = entity;
+                    any.downcast::<Counter>()
+                        .map_err(|_| ProjectorError::Projection("unexpected entity".to_string()))?
+                        .count
+                }
+                None => 0,
+            };
+            Ok(Some(Box::new(Counter {
+                id: event.aggregate_id(),
+                count: count + 1,
+            })))
+        }
+    }
+
+    fn count(repo: &dyn ReadRepo, id: Uuid) -> usize {
+        let any: Box<dyn Any> = repo.find(id).unwrap();
+        any.downcast::<Counter>().unwrap().count
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_swaps_repo() {
+        let store = Arc::new(MemoryEventStore::new());
+        let id = Uuid::new_v4();
+        store
+            .save(vec![event("Created", id, 1), event("Updated", id, 2), event("Updated", id, 3)], 0)
+            .await
+            .unwrap();
+
+        let old: SharedRepo = Arc::new(MemoryRepo::new());
+        old.save(Box::new(Counter { id, count: 100 })).unwrap();
+        let target = Arc::new(SwappableRepo::new(old));
+
+        let matcher: Arc<dyn EventMatcher> = Arc::new(MatchEvents::new(vec![EventType::from_name("Updated")]));
+        let rebuild = ProjectionRebuild::new(store, Arc::new(CounterProjector), Some(matcher), target.clone());
+        let progress = rebuild.progress();
+
+        let result = rebuild.run(Arc::new(MemoryRepo::new())).await.unwrap();
+        assert_eq!(result, RebuildProgress { position: 3, head: 3, projected: 2, done: true });
+        assert_eq!(*progress.borrow(), result);
+        assert_eq!(count(target.as_ref(), id), 2);
+    }
+
+    #[tokio::test]
+    async fn test_aborted_rebuild_keeps_old_repo() {
+        let store = Arc::new(MemoryEventStore::new());
+        let id = Uuid::new_v4();
+        store.save(vec![event("Created", id, 1)], 0).await.unwrap();
+
+        let old: SharedRepo = Arc::new(MemoryRepo::new());
+        old.save(Box::new(Counter { id, count: 100 })).unwrap();
+        let target = Arc::new(SwappableRepo::new(old));
+
+        let rebuild = ProjectionRebuild::new(store, Arc::new(CounterProjector), None, target.clone());
+        rebuild.abort_handle().abort();
+        let err = rebuild.run(Arc::new(MemoryRepo::new())).await.unwrap_err();
+        assert!(matches!(err, ProjectorError::Aborted));
+        assert_eq!(count(target.as_ref(), id), 100);
+    }
+
+    // Store that saves a pending event right after the rebuild read the log
+    // without finding new events, just before the rebuilt repo is swapped in.
+    // With a live repo the event is also projected into it, like a live
+    // projector would.
+    struct AppendingStore {
+        inner: MemoryEventStore,
+        pending: Mutex<Option<Arc<dyn Event>>>,
+        live: Option<Arc<SwappableRepo>>,
+    }
+
+    #[async_trait]
+    impl EventStore for AppendingStore {
+        async fn save(&self, events: Vec<Arc<dyn Event>>, original_version: i32) -> Result<(), EventStoreError> {
+            self.inner.save(events, original_version).await
+        }
+
+        async fn load(&self, aggregate_id: Uuid) -> Result<Vec<Arc<dyn Event>>, EventStoreError> {
+            self.inner.load(aggregate_id).await
+        }
+
+        async fn load_from(&self, aggregate_id: Uuid, version: i32) -> Result<Vec<Arc<dyn Event>>, EventStoreError> {
+            self.inner.load_from(aggregate_id, version).await
+        }
+
+        async fn close(&self) -> Result<(), Box<dyn StdError + Send + Sync>> {
+            self.inner.close().await
+        }
+    }
+
+    #[async_trait]
+    impl GlobalEventStore for AppendingStore {
+        async fn head_position(&self) -> Result<u64, EventStoreError> {
+            self.inner.head_position().await
+        }
+
+        async fn load_all(
+            &self,
+            from: u64,
+            matcher: Option<Arc<dyn EventMatcher>>,
+        ) -> Result<Vec<PositionedEvent>, EventStoreError> {
+            self.inner.load_all(from, matcher).await
+        }
+
+        async fn load_all_stream(
+            &self,
+            from: u64,
+            matcher: Option<Arc<dyn EventMatcher>>,
+        ) -> Result<PositionedEventStream, EventStoreError> {
+            let events = self.inner.load_all(from, matcher).await?;
+            if events.is_empty() {
+                let pending = self.pending.lock().unwrap().take();
+                if let Some(pending) = pending {
+                    let version = pending.version();
+                    self.inner.save(vec![pending], version - 1).await?;
+                    if let Some(live) = &self.live {
+                        let saved = self.inner.load_all(from, None).await?.remove(0);
+                        live.project(&CounterProjector, &saved).unwrap();
+                    }
+                }
+            }
+            Ok(Box::pin(stream::iter(events.into_iter().map(Ok))))
+        }
+
+        async fn subscribe(
+            &self,
+            from: u64,
+            matcher: Option<Arc<dyn EventMatcher>>,
+        ) -> Result<CatchUpSubscription, EventStoreError> {
+            self.inner.subscribe(from, matcher).await
+        }
+    }
+
+    #[tokio::test]
+    async fn test_live_projector_continues_after_swap() {
+        let id = Uuid::new_v4();
+        let store = Arc::new(AppendingStore {
+            inner: MemoryEventStore::new(),
+            pending: Mutex::new(Some(event("Updated", id, 3))),
+            live: None,
+        });
+        store.save(vec![event("Created", id, 1), event("Updated", id, 2)], 0).await.unwrap();
+
+        let target = Arc::new(SwappableRepo::new(Arc::new(MemoryRepo::new())));
+        let rebuild = ProjectionRebuild::new(store.clone(), Arc::new(CounterProjector), None, target.clone());
+
+        let result = rebuild.run(Arc::new(MemoryRepo::new())).await.unwrap();
+        assert_eq!((result.position, result.projected, result.done), (2, 2, true));
+        assert_eq!(target.position(), 2);
+
+        // The live projector skips the events the rebuild replayed.
+        for event in store.load_all(0, None).await.unwrap() {
+            target.project(&CounterProjector, &event).unwrap();
+        }
+        assert_eq!(target.position(), 3);
+        assert_eq!(count(target.as_ref(), id), 3);
+    }
+
+    #[tokio::test]
+    async fn test_rebuild_catches_up_with_live_projector() {
+        let id = Uuid::new_v4();
+        let target = Arc::new(SwappableRepo::new(Arc::new(MemoryRepo::new())));
+        let store = Arc::new(AppendingStore {
+            inner: MemoryEventStore::new(),
+            pending: Mutex::new(Some(event("Updated", id, 3))),
+            live: Some(target.clone()),
+        });
+        store.save(vec![event("Created", id, 1), event("Updated", id, 2)], 0).await.unwrap();
+
+        let rebuild = ProjectionRebuild::new(store, Arc::new(CounterProjector), None, target.clone());
+        let result = rebuild.run(Arc::new(MemoryRepo::new())).await.unwrap();
+        assert_eq!((result.position, result.projected, result.done), (3, 3, true));
+        assert_eq!(count(target.as_ref(), id), 3);
+    }
+}
@@ -1,9 +1,13 @@
 use uuid::Uuid;
+use std::any::Any;
 use std::fmt;
 use std::error::Error;
 
+pub mod memory;
+
 // Define the Entity trait and make it cloneable using a helper trait.
-pub trait Entity: EntityClone + fmt::Debug {
+// Concrete entities can be recovered by upcasting to `dyn Any` and downcasting.
+pub trait Entity: EntityClone + fmt::Debug + Any + Send + Sync {
     fn id(&self) -> Uuid;
 }
 
@@ -98,8 +102,25 @@ impl RepoError {
     pub fn new(op: RepoOperation, err: Option<Box<dyn Error>>, entity_id: Option<Uuid>) -> Self {
         RepoError { err, op, entity_id }
     }
+
+    // Check if the error was caused by a missing entity.
+    pub fn is_not_found(&self) -> bool {
+        self.err.as_ref().is_some_and(|err| err.is::<EntityNotFound>())
+    }
+}
+
+// Error returned when an entity could not be found.
+#[derive(Debug)]
+pub struct EntityNotFound;
+
+impl fmt::Display for EntityNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "could not find entity")
+    }
 }
 
+impl Error for EntityNotFound {}
+
 
 
 // Unit tests for repository functionalities.
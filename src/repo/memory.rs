@@ -0,0 +1,89 @@
+use std::collections::HashMap;
+use std::sync::RwLock;
+use uuid::Uuid;
+
+use super::{Entity, EntityNotFound, ReadRepo, ReadWriteRepo, RepoError, RepoOperation, WriteRepo};
+
+// MemoryRepo is a read/write repository keeping entities in memory.
+#[derive(Default)]
+pub struct MemoryRepo {
+    entities: RwLock<HashMap<Uuid, Box<dyn Entity>>>,
+}
+
+impl MemoryRepo {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+impl ReadRepo for MemoryRepo {
+    fn inner_repo(&self) -> Option<Box<dyn ReadRepo>> {
+        None
+    }
+
+    fn find(&self, id: Uuid) -> Result<Box<dyn Entity>, RepoError> {
+        let entities = self.entities.read().unwrap();
+        entities
+            .get(&id)
+            .cloned()
+            .ok_or_else(|| RepoError::new(RepoOperation::Find, Some(Box::new(EntityNotFound)), Some(id)))
+    }
+
+    fn find_all(&self) -> Result<Vec<Box<dyn Entity>>, RepoError> {
+        Ok(self.entities.read().unwrap().values().cloned().collect())
+    }
+
+    fn close(&self) -> Result<(), RepoError> {
+        Ok(())
+    }
+}
+
+impl WriteRepo for MemoryRepo {
+    fn save(&self, entity: Box<dyn Entity>) -> Result<(), RepoError> {
+        self.entities.write().unwrap().insert(entity.id(), entity);
+        Ok(())
+    }
+
+    fn remove(&self, id: Uuid) -> Result<(), RepoError> {
+        match self.entities.write().unwrap().remove(&id) {
+            Some(_) => Ok(()),
+            None => Err(RepoError::new(RepoOperation::Remove, Some(Box::new(EntityNotFound)), Some(id))),
+        }
+    }
+}
+
+impl ReadWriteRepo for MemoryRepo {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[derive(Debug, Clone)]
+    struct TestEntity {
+        id: Uuid,
+        name: String,
+    }
+
+    impl Entity for TestEntity {
+        fn id(&self) -> Uuid {
+            self.id
+        }
+    }
+
+    #[test]
+    fn test_save_find_remove() {
+        let repo = MemoryRepo::new();
+        let id = Uuid::new_v4();
+        repo.save(Box::new(TestEntity { id, name: "first".to_string() })).unwrap();
+
+        let found = repo.find(id).unwrap();
+        let any: Box<dyn std::any::Any> = found;
+        assert_eq!(any.downcast_ref::<TestEntity>().unwrap().name, "first");
+        assert_eq!(repo.find_all().unwrap().len(), 1);
+
+        repo.remove(id).unwrap();
+        let err = repo.find(id).unwrap_err();
+        assert!(err.is_not_found());
+        assert!(repo.remove(id).unwrap_err().is_not_found());
+    }
+}
@@ -0,0 +1,251 @@
+#[cfg(feature = "json")]
+use async_trait::async_trait;
+use jsonschema::JSONSchema;
+use lazy_static::lazy_static;
+use schemars::JsonSchema;
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+use std::sync::{Arc, RwLock};
+use thiserror::Error;
+
+#[cfg(feature = "json")]
+use crate::codec_main::{self, CodecError};
+use crate::command_main::{self, Command};
+#[cfg(feature = "json")]
+use crate::context::Context;
+use crate::event::{self, EventData};
+
+// Errors related to JSON Schema generation and validation.
+#[derive(Error, Debug)]
+pub enum SchemaError {
+    #[error("{0} is not registered")]
+    NotRegistered(String),
+
+    #[error("invalid schema for {0}: {1}")]
+    Schema(String, String),
+
+    #[error("invalid {command_type} command: {}", .errors.join(", "))]
+    Invalid { command_type: String, errors: Vec<String> },
+}
+
+lazy_static! {
+    // Validators compiled from the schemas of registered command types.
+    static ref COMMAND_VALIDATORS: RwLock<HashMap<String, Arc<JSONSchema>>> = RwLock::new(HashMap::new());
+}
+
+fn schema_for<T: JsonSchema>() -> Value {
+    serde_json::to_value(schemars::schema_for!(T)).unwrap()
+}
+
+// Records the schema of event data registered with event::register_event_data.
+pub fn register_event_data_schema<T>(event_type: &str) -> Result<(), SchemaError>
+where
+    T: EventData + JsonSchema,
+{
+    event::set_event_data_schema(event_type, schema_for::<T>)
+        .map_err(|_| SchemaError::NotRegistered(event_type.to_string()))
+}
+
+// Records the schema of a command registered with command_main::register_command,
+// which incoming commands of the type are validated against.
+pub fn register_command_schema<T>(command_type: &str) -> Result<(), SchemaError>
+where
+    T: Command + JsonSchema,
+{
+    let validator = compile(command_type, &schema_for::<T>())?;
+    command_main::set_command_schema(command_type, schema_for::<T>)
+        .map_err(|_| SchemaError::NotRegistered(command_type.to_string()))?;
+    COMMAND_VALIDATORS
+        .write()
+        .unwrap()
+        .insert(command_type.to_string(), validator);
+    Ok(())
+}
+
+fn compile(command_type: &str, schema: &Value) -> Result<Arc<JSONSchema>, SchemaError> {
+    JSONSchema::compile(schema)
+        .map(Arc::new)
+        .map_err(|e| SchemaError::Schema(command_type.to_string(), e.to_string()))
+}
+
+// Returns the validator of a command type, compiling it from the schema
+// recorded in the command registry the first time it's needed.
+fn command_validator(command_type: &str) -> Result<Option<Arc<JSONSchema>>, SchemaError> {
+    if let Some(validator) = COMMAND_VALIDATORS.read().unwrap().get(command_type) {
+        return Ok(Some(validator.clone()));
+    }
+    let Some(schema) = command_main::command_schema(command_type) else {
+        return Ok(None);
+    };
+    let validator = compile(command_type, &schema)?;
+    COMMAND_VALIDATORS
+        .write()
+        .unwrap()
+        .insert(command_type.to_string(), validator.clone());
+    Ok(Some(validator))
+}
+
+// Generates a document with the schemas of every registered event type and
+// command type. Types registered without a schema accept any value.
+pub fn generate() -> Value {
+    let events: Map<String, Value> = event::registered_event_types()
+        .into_iter()
+        .map(|event_type| {
+            let schema = event::event_data_schema(&event_type).unwrap_or(Value::Bool(true));
+            (event_type, schema)
+        })
+        .collect();
+
+    let commands: Map<String, Value> = command_main::registered_command_types()
+        .into_iter()
+        .map(|command_type| {
+            let schema = command_main::command_schema(&command_type).unwrap_or(Value::Bool(true));
+            (command_type, schema)
+        })
+        .collect();
+
+    json!({
+        "$schema": "http://json-schema.org/draft-07/schema#",
+        "events": events,
+        "commands": commands,
+    })
+}
+
+// Validates a JSON command against the schema of its type. Commands of types
+// registered without a schema are not validated.
+pub fn validate_command(command_type: &str, command: &Value) -> Result<(), SchemaError> {
+    let Some(validator) = command_validator(command_type)? else {
+        return Ok(());
+    };
+    validator.validate(command).map_err(|errors| SchemaError::Invalid {
+        command_type: command_type.to_string(),
+        errors: errors
+            .map(|error| format!("{} at {}", error, error.instance_path))
+            .collect(),
+    })
+}
+
+// ValidatingCommandCodec decodes JSON commands with the JSON command codec,
+// after validating them against the schema of their type.
+#[cfg(feature = "json")]
+pub struct ValidatingCommandCodec;
+
+#[cfg(feature = "json")]
+#[async_trait]
+impl codec_main::CommandCodec for ValidatingCommandCodec {
+    async fn marshal_command(&self, ctx: &Context, command: Arc<dyn codec_main::Command>) -> Result<Vec<u8>, CodecError> {
+        crate::codec::json::command::CommandCodec.marshal_command(ctx, command).await
+    }
+
+    async fn unmarshal_command(
+        &self,
+        ctx: Context,
+        data: Vec<u8>,
+    ) -> Result<(Arc<dyn codec_main::Command>, Context), CodecError> {
+        let envelope: Value = serde_json::from_slice(&data).map_err(|e| CodecError::new(&e.to_string()))?;
+        let command_type = envelope
+            .get("command_type")
+            .and_then(Value::as_str)
+            .ok_or_else(|| CodecError::new("missing command type"))?;
+        validate_command(command_type, envelope.get("command").unwrap_or(&Value::Null))
+            .map_err(|e| CodecError::new(&e.to_string()))?;
+        crate::codec::json::command::CommandCodec.unmarshal_command(ctx, data).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde::{Deserialize, Serialize};
+    use uuid::Uuid;
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    struct InvoiceSent {
+        amount: u32,
+        note: Option<String>,
+    }
+
+    impl EventData for InvoiceSent {}
+
+    #[derive(Debug, Serialize, Deserialize, JsonSchema)]
+    struct SendInvoice {
+        id: Uuid,
+        amount: u32,
+    }
+
+    impl Command for SendInvoice {
+        fn aggregate_id(&self) -> Uuid {
+            self.id
+        }
+
+        fn aggregate_type(&self) -> String {
+            "Invoice".to_string()
+        }
+
+        fn command_type(&self) -> String {
+            "SchemaSendInvoice".to_string()
+        }
+    }
+
+    #[test]
+    fn test_generate() {
+        event::register_event_data(
+            "SchemaInvoiceSent".to_string(),
+            Box::new(|| Box::new(InvoiceSent { amount: 0, note: None })),
+        );
+        register_event_data_schema::<InvoiceSent>("SchemaInvoiceSent").unwrap();
+        event::register_event_data(
+            "SchemaUntypedEvent".to_string(),
+            Box::new(|| Box::new(event::MyEventData { field: String::new() })),
+        );
+        assert!(matches!(
+            register_event_data_schema::<InvoiceSent>("SchemaUnregistered"),
+            Err(SchemaError::NotRegistered(_))
+        ));
+
+        let schema = event::event_data_schema("SchemaInvoiceSent").unwrap();
+        assert_eq!(schema["properties"]["amount"]["type"], "integer");
+        assert_eq!(schema["required"], json!(["amount"]));
+
+        let document = generate();
+        assert_eq!(document["events"]["SchemaInvoiceSent"], schema);
+        assert_eq!(document["events"]["SchemaUntypedEvent"], true);
+    }
+
+    #[cfg(feature = "json")]
+    #[tokio::test]
+    async fn test_validate_command() {
+        use crate::codec_main::CommandCodec as _;
+
+        command_main::register_command(
+            "SchemaSendInvoice".to_string(),
+            Box::new(|| Box::new(SendInvoice { id: Uuid::nil(), amount: 0 })),
+        );
+        register_command_schema::<SendInvoice>("SchemaSendInvoice").unwrap();
+        assert!(command_main::command_schema("SchemaSendInvoice").is_some());
+        assert_eq!(generate()["commands"]["SchemaSendInvoice"]["required"], json!(["amount", "id"]));
+        assert!(matches!(
+            register_command_schema::<SendInvoice>("SchemaUnregistered"),
+            Err(SchemaError::NotRegistered(_))
+        ));
+
+        let id = Uuid::new_v4();
+        assert!(validate_command("SchemaSendInvoice", &json!({"id": id, "amount": 10})).is_ok());
+        let err = validate_command("SchemaSendInvoice", &json!({"amount": -1})).unwrap_err();
+        assert!(matches!(err, SchemaError::Invalid { ref errors, .. } if errors.len() == 2));
+        assert!(validate_command("SchemaUnknown", &json!(1)).is_ok());
+
+        let valid = serde_json::to_vec(&json!({
+            "command_type": "SchemaSendInvoice", "command": {"id": id, "amount": 10}, "context": {}
+        }))
+        .unwrap();
+        let (command, _) = ValidatingCommandCodec.unmarshal_command(Context::new(), valid).await.unwrap();
+        assert_eq!(command.aggregate_id(), id);
+
+        let invalid = serde_json::to_vec(&json!({
+            "command_type": "SchemaSendInvoice", "command": {"id": id}, "context": {}
+        }))
+        .unwrap();
+        assert!(ValidatingCommandCodec.unmarshal_command(Context::new(), invalid).await.is_err());
+    }
+}
@@ -4,6 +4,8 @@ use std::time::SystemTime;
 use uuid::Uuid;
 use std::fmt;
 use std::any::Any;
+use serde::de::DeserializeOwned;
+use serde_json::Value;
 
 // Trait for Snapshotable entities.
 pub trait Snapshotable {
@@ -20,8 +22,14 @@ pub struct Snapshot {
     pub state: Box<dyn SnapshotData>,
 }
 
-// Define the SnapshotData trait for the state in snapshots.
-pub trait SnapshotData: SnapshotDataClone + fmt::Debug + AsAny {}
+// Define the SnapshotData trait for the state in snapshots. Snapshot data is
+// serializable so that snapshots can be persisted through the codecs.
+pub trait SnapshotData:
+    SnapshotDataClone + SnapshotDataDeserialize + erased_serde::Serialize + fmt::Debug + AsAny + Send + Sync
+{
+}
+
+erased_serde::serialize_trait_object!(SnapshotData);
 
 // Helper trait for enabling cloning of SnapshotData trait objects.
 pub trait SnapshotDataClone {
@@ -43,6 +51,22 @@ impl Clone for Box<dyn SnapshotData> {
     }
 }
 
+// Helper trait for filling SnapshotData trait objects created by a factory
+// with their serialized state.
+pub trait SnapshotDataDeserialize {
+    fn deserialize_from(&mut self, deserializer: &mut dyn erased_serde::Deserializer) -> Result<(), erased_serde::Error>;
+}
+
+impl<T> SnapshotDataDeserialize for T
+where
+    T: 'static + SnapshotData + DeserializeOwned,
+{
+    fn deserialize_from(&mut self, deserializer: &mut dyn erased_serde::Deserializer) -> Result<(), erased_serde::Error> {
+        *self = erased_serde::deserialize(deserializer)?;
+        Ok(())
+    }
+}
+
 // A trait that allows downcasting of SnapshotData to concrete types.
 pub trait AsAny {
     fn as_any(&self) -> &dyn Any;
@@ -58,22 +82,47 @@ impl<T: 'static + SnapshotData> AsAny for T {
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub struct AggregateType(String);
 
+impl AggregateType {
+    pub fn new(aggregate_type: String) -> Self {
+        AggregateType(aggregate_type)
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for AggregateType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+type SnapshotDataFactory = Box<dyn Fn(Uuid) -> Box<dyn SnapshotData> + Send + Sync>;
+
+// Upgrades serialized snapshot state from one schema version to the next.
+type SnapshotUpgrader = Box<dyn Fn(Value) -> Result<Value, String> + Send + Sync>;
+
 // Snapshot factory registry for different aggregate types.
 pub struct SnapshotFactoryRegistry {
-    factories: Arc<RwLock<HashMap<AggregateType, Box<dyn Fn(Uuid) -> Box<dyn SnapshotData>>>>>,
+    factories: Arc<RwLock<HashMap<AggregateType, SnapshotDataFactory>>>,
+    schema_versions: Arc<RwLock<HashMap<AggregateType, u32>>>,
+    upgraders: Arc<RwLock<HashMap<(AggregateType, u32), SnapshotUpgrader>>>,
 }
 
 impl SnapshotFactoryRegistry {
     pub fn new() -> Self {
         SnapshotFactoryRegistry {
             factories: Arc::new(RwLock::new(HashMap::new())),
+            schema_versions: Arc::new(RwLock::new(HashMap::new())),
+            upgraders: Arc::new(RwLock::new(HashMap::new())),
         }
     }
 
     // Registers a snapshot factory for a specific aggregate type.
     pub fn register_snapshot_data<F>(&self, aggregate_type: AggregateType, factory: F)
     where
-        F: 'static + Fn(Uuid) -> Box<dyn SnapshotData>,
+        F: 'static + Fn(Uuid) -> Box<dyn SnapshotData> + Send + Sync,
     {
         if aggregate_type.0.is_empty() {
             panic!("attempt to register empty aggregate type");
@@ -102,6 +151,80 @@ impl SnapshotFactoryRegistry {
             Err("snapshot data not registered".to_string())
         }
     }
+
+    // Creates a concrete instance and fills it with serialized state.
+    pub fn decode_snapshot_data(
+        &self,
+        aggregate_id: Uuid,
+        aggregate_type: AggregateType,
+        deserializer: &mut dyn erased_serde::Deserializer,
+    ) -> Result<Box<dyn SnapshotData>, String> {
+        let mut data = self.create_snapshot_data(aggregate_id, aggregate_type)?;
+        data.deserialize_from(deserializer).map_err(|e| e.to_string())?;
+        Ok(data)
+    }
+
+    // Returns the aggregate types with registered snapshot data, sorted.
+    pub fn aggregate_types(&self) -> Vec<AggregateType> {
+        let mut aggregate_types: Vec<AggregateType> = self.factories.read().unwrap().keys().cloned().collect();
+        aggregate_types.sort_by(|a, b| a.0.cmp(&b.0));
+        aggregate_types
+    }
+
+    // Sets the current schema version of the snapshot data for an aggregate type.
+    pub fn set_schema_version(&self, aggregate_type: AggregateType, schema_version: u32) {
+        self.schema_versions.write().unwrap().insert(aggregate_type, schema_version);
+    }
+
+    // Returns the current schema version for an aggregate type, 1 unless set.
+    pub fn schema_version(&self, aggregate_type: &AggregateType) -> u32 {
+        self.schema_versions.read().unwrap().get(aggregate_type).copied().unwrap_or(1)
+    }
+
+    // Registers an upgrader that migrates serialized state from `from_version` to `from_version + 1`.
+    pub fn register_upgrader<F>(&self, aggregate_type: AggregateType, from_version: u32, upgrader: F)
+    where
+        F: 'static + Fn(Value) -> Result<Value, String> + Send + Sync,
+    {
+        let mut upgraders = self.upgraders.write().unwrap();
+        if upgraders.contains_key(&(aggregate_type.clone(), from_version)) {
+            panic!(
+                "registering duplicate upgraders for {} v{}",
+                aggregate_type.0, from_version
+            );
+        }
+        upgraders.insert((aggregate_type, from_version), Box::new(upgrader));
+    }
+
+    // Migrates serialized state to the current schema version. Returns None if the
+    // state can't be migrated, in which case the snapshot should be ignored.
+    pub fn upgrade_state(
+        &self,
+        aggregate_type: &AggregateType,
+        schema_version: u32,
+        state: Value,
+    ) -> Result<Option<Value>, String> {
+        let current = self.schema_version(aggregate_type);
+        if schema_version > current {
+            return Ok(None);
+        }
+
+        let upgraders = self.upgraders.read().unwrap();
+        let mut state = state;
+        for version in schema_version..current {
+            match upgraders.get(&(aggregate_type.clone(), version)) {
+                Some(upgrader) => state = upgrader(state)?,
+                None => return Ok(None),
+            }
+        }
+        Ok(Some(state))
+    }
+}
+
+impl Default for SnapshotFactoryRegistry {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 // Unit tests for the Snapshot functionality.
@@ -110,8 +233,10 @@ mod tests {
     use super::*;
     use uuid::Uuid;
     use std::time::SystemTime;
+    use serde::{Deserialize, Serialize};
+    use serde_json::json;
 
-    #[derive(Debug, Clone)]
+    #[derive(Debug, Clone, Serialize, Deserialize)]
     struct MySnapshotData {
         pub id: Uuid,
         pub value: String,
@@ -160,4 +285,44 @@ mod tests {
 
         assert_eq!(my_data.value, "test_value");
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_decode_snapshot_data() {
+        let registry = SnapshotFactoryRegistry::new();
+        let aggregate_type = AggregateType("MyAggregate".to_string());
+        registry.register_snapshot_data(aggregate_type.clone(), |id| {
+            Box::new(MySnapshotData {
+                id,
+                value: String::new(),
+            })
+        });
+
+        let id = Uuid::new_v4();
+        let state = json!({"id": id, "value": "restored"});
+        let data = registry
+            .decode_snapshot_data(id, aggregate_type, &mut <dyn erased_serde::Deserializer>::erase(state))
+            .unwrap();
+        let my_data = data.as_any().downcast_ref::<MySnapshotData>().unwrap();
+        assert_eq!(my_data.value, "restored");
+    }
+
+    #[test]
+    fn test_upgrade_state() {
+        let registry = SnapshotFactoryRegistry::new();
+        let aggregate_type = AggregateType("MyAggregate".to_string());
+        assert_eq!(registry.schema_version(&aggregate_type), 1);
+
+        registry.set_schema_version(aggregate_type.clone(), 3);
+        registry.register_upgrader(aggregate_type.clone(), 2, |mut state| {
+            state["value"] = json!(format!("{}!", state["name"].as_str().unwrap_or_default()));
+            Ok(state)
+        });
+
+        // v2 can be migrated, v1 has no upgrader and is ignored.
+        let upgraded = registry.upgrade_state(&aggregate_type, 2, json!({"name": "old"})).unwrap();
+        assert_eq!(upgraded.unwrap()["value"], "old!");
+        assert!(registry.upgrade_state(&aggregate_type, 1, json!({})).unwrap().is_none());
+        assert!(registry.upgrade_state(&aggregate_type, 4, json!({})).unwrap().is_none());
+        assert_eq!(registry.upgrade_state(&aggregate_type, 3, json!({"a": 1})).unwrap(), Some(json!({"a": 1})));
+    }
+}
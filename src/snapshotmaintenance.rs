@@ -0,0 +1,315 @@
+use std::collections::{BTreeMap, HashSet};
+use std::error::Error;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use async_trait::async_trait;
+use thiserror::Error;
+use tokio::sync::watch;
+use tokio::task::JoinHandle;
+use uuid::Uuid;
+
+use crate::eventstore::{EventStore, SnapshotStore};
+use crate::snapshot::AggregateType;
+
+// SnapshotInfo describes a stored snapshot without decoding its state.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct SnapshotInfo {
+    pub aggregate_id: Uuid,
+    pub aggregate_type: AggregateType,
+    pub version: i32,
+    pub timestamp: SystemTime,
+}
+
+// SnapshotStoreMaintenance is implemented by snapshot stores that can be pruned.
+#[async_trait]
+pub trait SnapshotStoreMaintenance: SnapshotStore {
+    // ListSnapshots returns all stored snapshots, ordered by version per aggregate
+    async fn list_snapshots(&self) -> Result<Vec<SnapshotInfo>, Box<dyn Error + Send + Sync>>;
+
+    // DeleteSnapshots removes the given snapshots and returns how many were removed
+    async fn delete_snapshots(&self, snapshots: &[SnapshotInfo]) -> Result<usize, Box<dyn Error + Send + Sync>>;
+}
+
+// RetentionPolicy selects the snapshots of one aggregate that should be deleted.
+// The snapshots are passed ordered by version, oldest first.
+#[async_trait]
+pub trait RetentionPolicy: Send + Sync {
+    async fn select(&self, snapshots: &[SnapshotInfo]) -> Result<Vec<SnapshotInfo>, Box<dyn Error + Send + Sync>>;
+}
+
+// KeepLastPolicy keeps the newest K snapshots of every aggregate.
+pub struct KeepLastPolicy {
+    keep: usize,
+}
+
+impl KeepLastPolicy {
+    pub fn new(keep: usize) -> Self {
+        KeepLastPolicy { keep }
+    }
+}
+
+#[async_trait]
+impl RetentionPolicy for KeepLastPolicy {
+    async fn select(&self, snapshots: &[SnapshotInfo]) -> Result<Vec<SnapshotInfo>, Box<dyn Error + Send + Sync>> {
+        let expired = snapshots.len().saturating_sub(self.keep);
+        Ok(snapshots[..expired].to_vec())
+    }
+}
+
+// MaxAgePolicy drops snapshots older than a duration.
+pub struct MaxAgePolicy {
+    max_age: Duration,
+}
+
+impl MaxAgePolicy {
+    pub fn new(max_age: Duration) -> Self {
+        MaxAgePolicy { max_age }
+    }
+}
+
+#[async_trait]
+impl RetentionPolicy for MaxAgePolicy {
+    async fn select(&self, snapshots: &[SnapshotInfo]) -> Result<Vec<SnapshotInfo>, Box<dyn Error + Send + Sync>> {
+        Ok(snapshots
+            .iter()
+            .filter(|s| s.timestamp.elapsed().is_ok_and(|age| age > self.max_age))
+            .cloned()
+            .collect())
+    }
+}
+
+// DeletedAggregatesPolicy drops all snapshots of aggregates that no longer have
+// any events in the event store.
+pub struct DeletedAggregatesPolicy {
+    store: Arc<dyn EventStore>,
+}
+
+impl DeletedAggregatesPolicy {
+    pub fn new(store: Arc<dyn EventStore>) -> Self {
+        DeletedAggregatesPolicy { store }
+    }
+}
+
+#[async_trait]
+impl RetentionPolicy for DeletedAggregatesPolicy {
+    async fn select(&self, snapshots: &[SnapshotInfo]) -> Result<Vec<SnapshotInfo>, Box<dyn Error + Send + Sync>> {
+        let Some(first) = snapshots.first() else {
+            return Ok(Vec::new());
+        };
+        if self.store.head_version(first.aggregate_id).await? == 0 {
+            Ok(snapshots.to_vec())
+        } else {
+            Ok(Vec::new())
+        }
+    }
+}
+
+// Errors related to pruning snapshots.
+#[derive(Error, Debug)]
+pub enum SnapshotRetentionError {
+    #[error("could not list snapshots: {0}")]
+    List(Box<dyn Error + Send + Sync>),
+
+    #[error("retention policy failed: {0}")]
+    Policy(Box<dyn Error + Send + Sync>),
+
+    #[error("could not delete snapshots: {0}")]
+    Delete(Box<dyn Error + Send + Sync>),
+}
+
+// Result of a pruning run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct PruneReport {
+    // Number of snapshots inspected.
+    pub examined: usize,
+    // Number of snapshots deleted.
+    pub deleted: usize,
+}
+
+// SnapshotRetention applies retention policies to a snapshot store. A snapshot
+// is deleted if any of the policies selects it.
+pub struct SnapshotRetention {
+    store: Arc<dyn SnapshotStoreMaintenance>,
+    policies: Vec<Arc<dyn RetentionPolicy>>,
+    reports: watch::Sender<Option<PruneReport>>,
+    errors: watch::Sender<Option<Arc<SnapshotRetentionError>>>,
+}
+
+impl SnapshotRetention {
+    pub fn new(store: Arc<dyn SnapshotStoreMaintenance>, policies: Vec<Arc<dyn RetentionPolicy>>) -> Self {
+        Self {
+            store,
+            policies,
+            reports: watch::Sender::new(None),
+            errors: watch::Sender::new(None),
+        }
+    }
+
+    // Reports returns a receiver that is updated after every successful run.
+    pub fn reports(&self) -> watch::Receiver<Option<PruneReport>> {
+        self.reports.subscribe()
+    }
+
+    // Errors returns a receiver that is updated after every failed run of a spawned task.
+    pub fn errors(&self) -> watch::Receiver<Option<Arc<SnapshotRetentionError>>> {
+        self.errors.subscribe()
+    }
+
+    // Prune runs all policies once and deletes the selected snapshots.
+    pub async fn prune(&self) -> Result<PruneReport, SnapshotRetentionError> {
+        let snapshots = self.store.list_snapshots().await.map_err(SnapshotRetentionError::List)?;
+
+        let mut aggregates: BTreeMap<(Uuid, String), Vec<SnapshotInfo>> = BTreeMap::new();
+        for snapshot in &snapshots {
+            aggregates
+                .entry((snapshot.aggregate_id, snapshot.aggregate_type.as_str().to_string()))
+                .or_default()
+                .push(snapshot.clone());
+        }
+
+        let mut expired = HashSet::new();
+        for snapshots in aggregates.values_mut() {
+            snapshots.sort_by_key(|s| s.version);
+            for policy in &self.policies {
+                let selected = policy.select(snapshots).await.map_err(SnapshotRetentionError::Policy)?;
+                expired.extend(selected);
+            }
+        }
+
+        let expired: Vec<SnapshotInfo> = expired.into_iter().collect();
+        let deleted = if expired.is_empty() {
+            0
+        } else {
+            self.store.delete_snapshots(&expired).await.map_err(SnapshotRetentionError::Delete)?
+        };
+
+        let report = PruneReport {
+            examined: snapshots.len(),
+            deleted,
+        };
+        self.reports.send_replace(Some(report));
+        Ok(report)
+    }
+
+    // Spawn runs prune every period until the returned task is aborted. Failed
+    // runs are published to the errors receiver and retried on the next tick.
+    pub fn spawn(self: Arc<Self>, period: Duration) -> JoinHandle<()> {
+        tokio::spawn(async move {
+            let mut interval = tokio::time::interval(period);
+            loop {
+                interval.tick().await;
+                if let Err(err) = self.prune().await {
+                    self.errors.send_replace(Some(Arc::new(err)));
+                }
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::eventstore::memory::{MemoryEventStore, MemorySnapshotStore};
+    use crate::eventstore::testutil::event;
+    use crate::snapshot::{Snapshot, SnapshotData};
+    use serde::{Deserialize, Serialize};
+
+    #[derive(Debug, Clone, Serialize, Deserialize)]
+    struct TestSnapshotData;
+
+    impl SnapshotData for TestSnapshotData {}
+
+    fn snapshot(version: i32, timestamp: SystemTime) -> Snapshot {
+        Snapshot {
+            version,
+            aggregate_type: AggregateType::new("TestAggregate".to_string()),
+            timestamp,
+            state: Box::new(TestSnapshotData),
+        }
+    }
+
+    async fn versions(store: &MemorySnapshotStore, id: Uuid) -> Vec<i32> {
+        store
+            .list_snapshots()
+            .await
+            .unwrap()
+            .into_iter()
+            .filter(|s| s.aggregate_id == id)
+            .map(|s| s.version)
+            .collect()
+    }
+
+    #[tokio::test]
+    async fn test_keep_last_and_max_age() {
+        let store = Arc::new(MemorySnapshotStore::new());
+        let id = Uuid::new_v4();
+        let old = SystemTime::now() - Duration::from_secs(3600);
+        store.save_snapshot(id, snapshot(1, old)).await.unwrap();
+        for version in 2..=5 {
+            store.save_snapshot(id, snapshot(version, SystemTime::now())).await.unwrap();
+        }
+
+        // Only the old snapshot is expired by age.
+        let retention = SnapshotRetention::new(store.clone(), vec![Arc::new(MaxAgePolicy::new(Duration::from_secs(60)))]);
+        assert_eq!(retention.prune().await.unwrap(), PruneReport { examined: 5, deleted: 1 });
+        assert_eq!(versions(&store, id).await, vec![2, 3, 4, 5]);
+
+        let retention = SnapshotRetention::new(store.clone(), vec![Arc::new(KeepLastPolicy::new(2))]);
+        let reports = retention.reports();
+        assert_eq!(retention.prune().await.unwrap(), PruneReport { examined: 4, deleted: 2 });
+        assert_eq!(*reports.borrow(), Some(PruneReport { examined: 4, deleted: 2 }));
+        assert_eq!(versions(&store, id).await, vec![4, 5]);
+
+        // The latest snapshot is still the one that is loaded.
+        let latest = store.load_snapshot(id, &AggregateType::new("TestAggregate".to_string())).await.unwrap();
+        assert_eq!(latest.unwrap().version, 5);
+    }
+
+    #[tokio::test]
+    async fn test_deleted_aggregates() {
+        let events = Arc::new(MemoryEventStore::new());
+        let store = Arc::new(MemorySnapshotStore::new());
+        let (live, deleted) = (Uuid::new_v4(), Uuid::new_v4());
+        events.save(vec![event("Created", live, 1)], 0).await.unwrap();
+        store.save_snapshot(live, snapshot(1, SystemTime::now())).await.unwrap();
+        store.save_snapshot(deleted, snapshot(1, SystemTime::now())).await.unwrap();
+
+        let retention = Arc::new(SnapshotRetention::new(
+            store.clone(),
+            vec![Arc::new(DeletedAggregatesPolicy::new(events))],
+        ));
+        let mut reports = retention.reports();
+        let task = retention.spawn(Duration::from_secs(60));
+        reports.changed().await.unwrap();
+        task.abort();
+
+        assert_eq!(*reports.borrow(), Some(PruneReport { examined: 2, deleted: 1 }));
+        assert_eq!(versions(&store, live).await, vec![1]);
+        assert!(versions(&store, deleted).await.is_empty());
+    }
+
+    struct FailingPolicy;
+
+    #[async_trait]
+    impl RetentionPolicy for FailingPolicy {
+        async fn select(&self, _: &[SnapshotInfo]) -> Result<Vec<SnapshotInfo>, Box<dyn Error + Send + Sync>> {
+            Err("policy failed".into())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_spawned_errors() {
+        let store = Arc::new(MemorySnapshotStore::new());
+        store.save_snapshot(Uuid::new_v4(), snapshot(1, SystemTime::now())).await.unwrap();
+
+        let retention = Arc::new(SnapshotRetention::new(store, vec![Arc::new(FailingPolicy)]));
+        let mut errors = retention.errors();
+        let task = retention.clone().spawn(Duration::from_secs(60));
+        errors.changed().await.unwrap();
+        task.abort();
+
+        let err = errors.borrow().clone().unwrap();
+        assert_eq!(err.to_string(), "retention policy failed: policy failed");
+        assert_eq!(*retention.reports().borrow(), None);
+    }
+}
@@ -0,0 +1,276 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::RwLock;
+use mongodb::bson::Bson;
+use serde_json::Value;
+use thiserror::Error;
+
+// RawEvent is the undecoded part of a stored event that upcasters operate on.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawEvent {
+    pub event_type: String,
+    pub schema_version: u32,
+    pub data: Option<Value>,
+}
+
+// Errors related to upcasting events.
+#[derive(Error, Debug)]
+pub enum UpcastError {
+    #[error("could not upcast {event_type} v{schema_version}: {reason}")]
+    Failed {
+        event_type: String,
+        schema_version: u32,
+        reason: String,
+    },
+
+    #[error("upcaster cycle at {event_type} v{schema_version}")]
+    Cycle { event_type: String, schema_version: u32 },
+
+    #[error("could not convert event data: {0}")]
+    Conversion(String),
+}
+
+// Upcaster transforms a raw event of one event type and schema version into a
+// newer shape, either by migrating its data or by renaming its event type.
+pub trait Upcaster: Send + Sync {
+    fn upcast(&self, event: RawEvent) -> Result<RawEvent, String>;
+}
+
+// DataUpcaster migrates the event data and bumps the schema version by one.
+pub struct DataUpcaster<F>
+where
+    F: Fn(Value) -> Result<Value, String> + Send + Sync,
+{
+    upcast: F,
+}
+
+impl<F> DataUpcaster<F>
+where
+    F: Fn(Value) -> Result<Value, String> + Send + Sync,
+{
+    pub fn new(upcast: F) -> Self {
+        DataUpcaster { upcast }
+    }
+}
+
+impl<F> Upcaster for DataUpcaster<F>
+where
+    F: Fn(Value) -> Result<Value, String> + Send + Sync,
+{
+    fn upcast(&self, event: RawEvent) -> Result<RawEvent, String> {
+        Ok(RawEvent {
+            event_type: event.event_type,
+            schema_version: event.schema_version + 1,
+            data: event.data.map(&self.upcast).transpose()?,
+        })
+    }
+}
+
+// RenameUpcaster renames the event type, keeping data and schema version.
+pub struct RenameUpcaster {
+    event_type: String,
+}
+
+impl RenameUpcaster {
+    pub fn new(event_type: String) -> Self {
+        RenameUpcaster { event_type }
+    }
+}
+
+impl Upcaster for RenameUpcaster {
+    fn upcast(&self, event: RawEvent) -> Result<RawEvent, String> {
+        Ok(RawEvent {
+            event_type: self.event_type.clone(),
+            ..event
+        })
+    }
+}
+
+// UpcasterRegistry chains registered upcasters by event type and schema
+// version until no upcaster matches the resulting event anymore.
+#[derive(Default)]
+pub struct UpcasterRegistry {
+    upcasters: RwLock<HashMap<(String, u32), Box<dyn Upcaster>>>,
+    schema_versions: RwLock<HashMap<String, u32>>,
+}
+
+// DefaultSchemaVersion is used for events stored without a schema version.
+pub const DEFAULT_SCHEMA_VERSION: u32 = 1;
+
+impl UpcasterRegistry {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // Sets the current schema version written for an event type.
+    pub fn set_schema_version(&self, event_type: String, schema_version: u32) {
+        self.schema_versions.write().unwrap().insert(event_type, schema_version);
+    }
+
+    // Returns the current schema version of an event type.
+    pub fn schema_version(&self, event_type: &str) -> u32 {
+        self.schema_versions
+            .read()
+            .unwrap()
+            .get(event_type)
+            .copied()
+            .unwrap_or(DEFAULT_SCHEMA_VERSION)
+    }
+
+    // Registers an upcaster for events of a type stored with a schema version.
+    pub fn register(&self, event_type: String, schema_version: u32, upcaster: Box<dyn Upcaster>) {
+        if event_type.is_empty() {
+            panic!("attempt to register upcaster for empty event type");
+        }
+
+        let mut upcasters = self.upcasters.write().unwrap();
+        let key = (event_type, schema_version);
+        if upcasters.contains_key(&key) {
+            panic!("registering duplicate upcasters for {} v{}", key.0, key.1);
+        }
+        upcasters.insert(key, upcaster);
+    }
+
+    // Registers a data migration from a schema version to the next one.
+    pub fn register_fn<F>(&self, event_type: String, schema_version: u32, upcast: F)
+    where
+        F: 'static + Fn(Value) -> Result<Value, String> + Send + Sync,
+    {
+        self.register(event_type, schema_version, Box::new(DataUpcaster::new(upcast)));
+    }
+
+    // Registers a rename of an event type stored with a schema version.
+    pub fn register_rename(&self, from: String, schema_version: u32, to: String) {
+        self.register(from, schema_version, Box::new(RenameUpcaster::new(to)));
+    }
+
+    // Returns true if events of a type stored with a schema version are upcast.
+    pub fn has_upcaster(&self, event_type: &str, schema_version: u32) -> bool {
+        self.upcasters
+            .read()
+            .unwrap()
+            .contains_key(&(event_type.to_string(), schema_version))
+    }
+
+    // Upcast runs all matching upcasters in order.
+    pub fn upcast(&self, event: RawEvent) -> Result<RawEvent, UpcastError> {
+        let upcasters = self.upcasters.read().unwrap();
+        let mut event = event;
+        let mut seen = HashSet::new();
+        while let Some(upcaster) = upcasters.get(&(event.event_type.clone(), event.schema_version)) {
+            if !seen.insert((event.event_type.clone(), event.schema_version)) {
+                return Err(UpcastError::Cycle {
+                    event_type: event.event_type,
+                    schema_version: event.schema_version,
+                });
+            }
+            let (event_type, schema_version) = (event.event_type.clone(), event.schema_version);
+            event = upcaster.upcast(event).map_err(|reason| UpcastError::Failed {
+                event_type,
+                schema_version,
+                reason,
+            })?;
+        }
+        Ok(event)
+    }
+
+    // UpcastBson upcasts BSON event data by converting it through relaxed extended JSON.
+    pub fn upcast_bson(
+        &self,
+        event_type: String,
+        schema_version: u32,
+        data: Option<Bson>,
+    ) -> Result<(String, u32, Option<Bson>), UpcastError> {
+        let event = self.upcast(RawEvent {
+            event_type,
+            schema_version,
+            data: data.map(Bson::into_relaxed_extjson),
+        })?;
+        let data = event
+            .data
+            .map(Bson::try_from)
+            .transpose()
+            .map_err(|e| UpcastError::Conversion(e.to_string()))?;
+        Ok((event.event_type, event.schema_version, data))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use mongodb::bson::doc;
+    use serde_json::json;
+
+    fn registry() -> UpcasterRegistry {
+        let registry = UpcasterRegistry::new();
+        // v1 stored a single name, v2 splits it.
+        registry.register_fn("UserCreated".to_string(), 1, |data| {
+            let name = data["name"].as_str().ok_or("missing name")?.to_string();
+            let (first, last) = name.split_once(' ').unwrap_or((&name, ""));
+            Ok(json!({"first_name": first, "last_name": last}))
+        });
+        // The event was renamed after v2.
+        registry.register_rename("UserCreated".to_string(), 2, "UserRegistered".to_string());
+        registry
+    }
+
+    #[test]
+    fn test_upcast_chain() {
+        let event = registry()
+            .upcast(RawEvent {
+                event_type: "UserCreated".to_string(),
+                schema_version: 1,
+                data: Some(json!({"name": "Ada Lovelace"})),
+            })
+            .unwrap();
+        assert_eq!(
+            event,
+            RawEvent {
+                event_type: "UserRegistered".to_string(),
+                schema_version: 2,
+                data: Some(json!({"first_name": "Ada", "last_name": "Lovelace"})),
+            }
+        );
+
+        // Current events are left untouched.
+        let current = RawEvent {
+            event_type: "UserRegistered".to_string(),
+            schema_version: 2,
+            data: None,
+        };
+        assert_eq!(registry().upcast(current.clone()).unwrap(), current);
+    }
+
+    #[test]
+    fn test_upcast_errors() {
+        let err = registry()
+            .upcast(RawEvent {
+                event_type: "UserCreated".to_string(),
+                schema_version: 1,
+                data: Some(json!({})),
+            })
+            .unwrap_err();
+        assert!(matches!(err, UpcastError::Failed { schema_version: 1, .. }));
+
+        let registry = UpcasterRegistry::new();
+        registry.register_rename("A".to_string(), 1, "B".to_string());
+        registry.register_rename("B".to_string(), 1, "A".to_string());
+        let err = registry
+            .upcast(RawEvent {
+                event_type: "A".to_string(),
+                schema_version: 1,
+                data: None,
+            })
+            .unwrap_err();
+        assert!(matches!(err, UpcastError::Cycle { .. }));
+    }
+
+    #[test]
+    fn test_upcast_bson() {
+        let data = Bson::Document(doc! {"name": "Grace Hopper"});
+        let (event_type, schema_version, data) = registry()
+            .upcast_bson("UserCreated".to_string(), 1, Some(data))
+            .unwrap();
+        assert_eq!((event_type.as_str(), schema_version), ("UserRegistered", 2));
+        assert_eq!(data, Some(Bson::Document(doc! {"first_name": "Grace", "last_name": "Hopper"})));
+    }
+}